@@ -0,0 +1,5095 @@
+//! The Azure side of a mount: mapping paths onto blobs, listing directories and reading files.
+//!
+//! [`BlobBackend`] knows nothing about the file system it's projected into. The platform
+//! adapters (ProjFS on Windows, FUSE elsewhere) translate callbacks into calls to it.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use azure_core::{Etag, LeaseState, StatusCode};
+use azure_storage_blobs::{
+    blob::{Blob, BlobProperties, BlobType},
+    container::operations::BlobItem,
+    prelude::{AccessTier, BlobVersioning, ContainerClient, Snapshot, VersionId},
+};
+use futures::{
+    future::BoxFuture, stream::BoxStream, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+};
+use globset::{GlobMatcher, GlobSet};
+use log::{error, info, warn};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::Semaphore;
+
+use crate::{
+    breaker::CircuitBreaker,
+    cache::{CacheCoordinator, TtlCache},
+    coalesce::Coalescer,
+    concat::Parts,
+    disk_cache::DiskCache,
+    error::RazmountError,
+    follow, logging,
+    memory_cache::MemoryCache,
+    pattern::SearchPattern,
+    readahead::ReadAhead,
+    source::{BlobSource, Delimited, ListOptions, TagFiltered},
+    stats::Stats,
+    throttle::RateLimiter,
+    verify::Verifier,
+};
+
+/// The name of a blob, or of a folder of blobs, relative to the container root.
+///
+/// `/` always separates folders: there's no escaping convention, so a blob name can't have a
+/// file name with a `/` in it. Every component of a name that is projected is one that
+/// [`is_projectable`] accepts, so converting the path it's projected at back with
+/// [`BlobPath::from`] gives exactly the original name. Blobs whose names don't survive this
+/// round trip (e.g. `a//b`, `a/./b` or `a\b`) are hidden instead of being mis-split.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BlobPath(String);
+
+impl<P: AsRef<Path>> From<P> for BlobPath {
+    /// Converts a path relative to the container root into a blob path, normalizing away `.` and
+    /// `..` components. Paths can't escape the root: `..` at the root is ignored, and any root or
+    /// drive prefix refers to the container root.
+    fn from(value: P) -> Self {
+        let mut segments = Vec::new();
+
+        for c in value.as_ref().components() {
+            match c {
+                std::path::Component::Prefix(_) | std::path::Component::RootDir => segments.clear(),
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    segments.pop();
+                }
+                std::path::Component::Normal(p) => segments.push(p.to_string_lossy()),
+            }
+        }
+
+        Self(segments.join("/"))
+    }
+}
+
+impl std::fmt::Display for BlobPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl BlobPath {
+    pub fn new(p: impl Into<String>) -> Self {
+        Self(p.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn join(&self, other: &BlobPath) -> BlobPath {
+        if self.0.is_empty() {
+            other.clone()
+        } else if other.0.is_empty() {
+            self.clone()
+        } else {
+            Self(format!("{}/{}", self.0, other.0))
+        }
+    }
+}
+
+/// Determines if a single component of a blob name can be represented as a Windows file name.
+///
+/// Blob names may contain almost anything, whereas Windows reserves a handful of characters, and
+/// `:` would be mistaken for an alternate data stream. Windows also strips trailing dots and
+/// spaces from the names it's handed, so such a file could never be opened by its own name.
+fn is_projectable(name: &str) -> bool {
+    !name.is_empty()
+        && !name.ends_with(['.', ' '])
+        && !name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+}
+
+/// Options controlling how a container is projected.
+#[derive(Debug, Clone)]
+pub struct DriverOptions {
+    /// Patterns of paths, relative to the virtual root, to hide.
+    pub exclude: GlobSet,
+    /// When blobs must have last been modified to be projected.
+    pub modified: ModifiedWindow,
+    /// Extensions of files to hide, lowercase and with a leading `.` (e.g. `.exe`).
+    pub blocked_extensions: Vec<String>,
+    /// How many levels of folders to project below the root, if limited. Blobs in deeper folders
+    /// are hidden.
+    pub max_depth: Option<usize>,
+    /// Whether the client was built without credentials.
+    pub anonymous: bool,
+    /// The blob prefix that is projected as the virtual root.
+    pub prefix: BlobPath,
+    /// The number of directories to track in `known_dirs`.
+    pub dir_cache_size: NonZeroUsize,
+    /// How long directory listings are cached for.
+    pub listing_ttl: Duration,
+    /// How long the properties of files are cached for.
+    pub metadata_ttl: Duration,
+    /// Where to cache downloaded blob ranges on disk, if anywhere.
+    pub cache_dir: Option<PathBuf>,
+    /// The maximum size of the on-disk cache.
+    pub cache_max_bytes: u64,
+    /// Blobs up to this size are downloaded whole, and kept in memory. 0 disables this.
+    pub small_blob_threshold: u64,
+    /// The maximum total size of the small blobs kept in memory.
+    pub small_blob_cache_bytes: u64,
+    /// How many bytes to download ahead of sequential reads.
+    pub readahead_bytes: u64,
+    /// A blob, relative to the prefix, to project as a folder of fixed-size chunks instead of
+    /// projecting the container.
+    pub blob_as_dir: Option<String>,
+    /// The size of each chunk of `blob_as_dir`, in bytes (bar the last, which may be shorter).
+    pub chunk_size: NonZeroU64,
+    /// Virtual files to project, each made of several blobs.
+    pub concat: Vec<Concat>,
+    /// The granularity to download (and so cache) blobs at, in bytes, or zero to download exactly
+    /// what's read.
+    pub block_size: u64,
+    /// How often to log how far along sequential reads of each blob are, if at all.
+    pub progress_interval: Option<Duration>,
+    /// How long to hold small reads back so that adjacent ones can be downloaded together (zero
+    /// to download each read as it comes).
+    pub coalesce_window: Duration,
+    /// The largest download that reads are coalesced into, in bytes.
+    pub coalesce_max_bytes: u64,
+    /// How many concurrent requests to split large reads into.
+    pub parallel_ranges: NonZeroUsize,
+    /// If set, blobs are served as they were at a point in time.
+    pub point_in_time: Option<PointInTime>,
+    /// Whether to require exact matches of blob names.
+    pub case_sensitive: bool,
+    /// Whether to present every file as read-only.
+    pub read_only: bool,
+    /// Whether to verify the data read against the checksums stored with blobs.
+    pub verify_checksums: bool,
+    /// The maximum number of requests to have in flight at once, if limited.
+    pub max_concurrency: Option<NonZeroUsize>,
+    /// The maximum rate to download blobs at, in bytes per second, if limited.
+    pub max_bandwidth: Option<NonZeroU64>,
+    /// The tier to rehydrate archived blobs to when they're read, if any.
+    pub auto_rehydrate: Option<AccessTier>,
+    /// How long to wait on each attempt at a request, if limited.
+    pub request_timeout: Option<Duration>,
+    /// Whether the account has a hierarchical namespace (ADLS Gen2), or `None` to detect it with
+    /// [`BlobBackend::detect_namespace`].
+    pub hns: Option<bool>,
+    /// Whether to fail reads of blobs that have changed since their properties were last seen,
+    /// rather than mixing old and new contents.
+    pub pin_etags: bool,
+    /// The character that separates folders in blob names. The backend itself only ever sees `/`;
+    /// sources for other delimiters are wrapped in [`Delimited`] by whoever builds them.
+    pub delimiter: char,
+    /// Whether to serve everything from the caches, without making any requests to Azure.
+    pub offline: bool,
+    /// How many requests in a row can fail to reach Azure before falling back to the caches, if
+    /// the mount is ever to do so.
+    pub offline_after: Option<NonZeroU32>,
+    /// How many requests in a row can fail with Azure unavailable before the circuit breaker
+    /// opens, if it's enabled.
+    pub breaker_threshold: Option<NonZeroU32>,
+    /// How long the circuit breaker stays open before letting a request through to probe Azure.
+    pub breaker_cooldown: Duration,
+    /// Whether to list soft-deleted blobs, as `<name>.deleted`.
+    pub include_deleted: bool,
+    /// Whether to project the versions of each versioned blob in a `<name>.versions` folder.
+    pub versions_as_subdir: bool,
+    /// Whether to give blobs without a file extension one that matches their content type.
+    pub infer_extensions: bool,
+    /// What to do about reads of blobs that another client holds a lease on.
+    pub lease_policy: LeasePolicy,
+    /// Whether to list folders with the total size of the blobs in them, rather than 0.
+    pub compute_dir_sizes: bool,
+    /// Whether to fetch the properties of every file in a listing as it's listed, rather than
+    /// when each is looked up.
+    pub prefetch_metadata: bool,
+    /// How often to list the container in the background, to notice blobs that were added,
+    /// changed or deleted, if at all.
+    pub follow_interval: Option<Duration>,
+    /// A blob index tag expression that blobs must match to be projected. Like the delimiter,
+    /// this is applied by the source, which is wrapped in [`TagFiltered`] by whoever builds it.
+    pub tag_filter: Option<String>,
+    /// The name that log lines about the mount are attributed to, or `None` for the container's.
+    pub name: Option<String>,
+}
+
+/// A range of last-modified times that blobs must fall within to be projected. The start is
+/// inclusive and the end exclusive, so that adjacent windows don't overlap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModifiedWindow {
+    pub after: Option<OffsetDateTime>,
+    pub before: Option<OffsetDateTime>,
+}
+
+impl ModifiedWindow {
+    fn is_unbounded(&self) -> bool {
+        self.after.is_none() && self.before.is_none()
+    }
+
+    fn contains(&self, t: OffsetDateTime) -> bool {
+        self.after.is_none_or(|after| t >= after) && self.before.is_none_or(|before| t < before)
+    }
+}
+
+/// A virtual file made of the blobs matching a pattern, one after the other in name order.
+#[derive(Debug, Clone)]
+pub struct Concat {
+    /// The blobs to concatenate, relative to the virtual root (e.g. `data/part-*`).
+    pub parts: GlobMatcher,
+    /// The path of the virtual file, relative to the virtual root.
+    pub name: PathBuf,
+}
+
+/// A point in time to serve blobs as of.
+#[derive(Debug, Clone, Copy)]
+pub enum PointInTime {
+    /// The latest snapshot taken at or before this time.
+    Snapshot(OffsetDateTime),
+    /// The version that was current at this time.
+    Version(OffsetDateTime),
+}
+
+impl PointInTime {
+    /// Includes the snapshots or versions needed to resolve this point in time in a listing.
+    fn include(&self, list: ListOptions) -> ListOptions {
+        match self {
+            Self::Snapshot(_) => ListOptions {
+                include_snapshots: true,
+                ..list
+            },
+            Self::Version(_) => ListOptions {
+                include_versions: true,
+                ..list
+            },
+        }
+    }
+
+    /// Returns when a listed snapshot or version of a blob came into being, and how to request
+    /// it, if it was listed as such.
+    fn candidate(&self, b: &Blob) -> Option<(OffsetDateTime, BlobVersioning)> {
+        match self {
+            Self::Snapshot(_) => {
+                let snapshot = b.snapshot.as_ref()?;
+                Some((snapshot_time(snapshot)?, snapshot.clone().into()))
+            }
+            Self::Version(_) => {
+                // Version IDs are (nominally opaque) timestamps of when the version was created.
+                let id = b.version_id.as_ref()?;
+                let created = azure_core::date::parse_rfc3339(id).ok()?;
+                Some((created, VersionId::new(id.clone()).into()))
+            }
+        }
+    }
+
+    fn time(&self) -> OffsetDateTime {
+        match self {
+            Self::Snapshot(t) | Self::Version(t) => *t,
+        }
+    }
+}
+
+/// What to do about blobs that another client holds a lease on, and so may be in the middle of
+/// writing to.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LeasePolicy {
+    /// Read them like any other blob
+    #[default]
+    Ignore,
+    /// Read them, but log that they're leased
+    Warn,
+    /// Refuse to read them
+    Deny,
+}
+
+/// A file or folder, as it is projected into its parent directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    /// The length of a file, in bytes.
+    pub size: u64,
+    /// Windows file attributes (`FILE_ATTRIBUTE_*`).
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub attrs: u32,
+    /// The ETag of a file's blob, as of when it was listed or looked up.
+    pub etag: Option<Etag>,
+    /// When a file's blob was created.
+    pub created: Option<SystemTime>,
+    /// When a file's blob was last written to (or its metadata changed).
+    pub modified: Option<SystemTime>,
+    /// When the entry was listed or looked up. Blob storage only tracks access times with
+    /// last-access tracking enabled, and never reports them in listings, so this stands in for
+    /// the last access time.
+    pub fetched: SystemTime,
+}
+
+impl Entry {
+    /// Describes a folder, named after its last component (or empty for the root).
+    fn dir(path: impl AsRef<Path>) -> Self {
+        Self {
+            name: path
+                .as_ref()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            is_dir: true,
+            size: 0,
+            attrs: 0,
+            etag: None,
+            created: None,
+            modified: None,
+            fetched: SystemTime::now(),
+        }
+    }
+}
+
+/// The number of 100ns intervals between the Windows epoch (1601) and the Unix epoch.
+const FILETIME_UNIX_EPOCH: i64 = 116_444_736_000_000_000;
+
+/// Converts a time into a Windows `FILETIME`, the number of 100ns intervals since 1601. Times
+/// before 1601 are clamped to it.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn filetime(t: SystemTime) -> i64 {
+    let ticks = |d: Duration| i64::try_from(d.as_nanos() / 100).unwrap_or(i64::MAX);
+
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => FILETIME_UNIX_EPOCH.saturating_add(ticks(d)),
+        Err(e) => FILETIME_UNIX_EPOCH
+            .saturating_sub(ticks(e.duration()))
+            .max(0),
+    }
+}
+
+/// The number and total size of the blobs in a mount.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub blobs: u64,
+    pub bytes: u64,
+    /// Whether counting stopped before every blob was counted.
+    pub truncated: bool,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.truncated {
+            write!(
+                f,
+                "at least {} blobs, {} bytes (truncated)",
+                self.blobs, self.bytes
+            )
+        } else {
+            write!(f, "{} blobs, {} bytes", self.blobs, self.bytes)
+        }
+    }
+}
+
+/// Listings of more entries than this aren't cached, so that huge directories aren't held in
+/// memory in their entirety.
+const MAX_CACHED_ENTRIES: usize = 100_000;
+
+/// The number of attempts to make at a request before giving up on it, when requests are subject
+/// to a timeout.
+pub const REQUEST_ATTEMPTS: u32 = 3;
+
+/// The suffix that soft-deleted blobs are projected with, to tell them apart from live ones.
+const DELETED_SUFFIX: &str = ".deleted";
+
+/// The suffix of the folders that the versions of a blob are projected in, next to the blob.
+const VERSIONS_SUFFIX: &str = ".versions";
+
+/// How long to remember that a blob wasn't found (at most), so that applications that keep
+/// looking for a file that isn't there don't make a request each time.
+const MISS_TTL: Duration = Duration::from_secs(5);
+
+/// How long a mount that fell back to its caches waits before trying Azure again.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether requests have been reaching Azure, for `offline_after`.
+#[derive(Default)]
+struct Connectivity {
+    /// Requests in a row that failed to reach Azure.
+    failures: u32,
+    /// When the mount last fell back to its caches, if it has.
+    offline_since: Option<Instant>,
+}
+
+/// The pages of a blob listing, as they arrive.
+type ListingPages = BoxStream<'static, azure_core::Result<Vec<BlobItem>>>;
+
+/// The type, length, access tier and lease state of a blob.
+type BlobKind = (BlobType, u64, Option<AccessTier>, Option<LeaseState>);
+
+/// The state of a directory listing, whose entries are produced as pages of blobs arrive.
+struct DirLister {
+    virt_path: PathBuf,
+    path: BlobPath,
+    /// The prefix of every name in the listing.
+    list_prefix: String,
+    /// The names of folders seen so far.
+    subdirs: HashSet<String>,
+    /// Entries, in listing order, held back behind a file that may yet turn out to collide with
+    /// a folder.
+    pending: VecDeque<Entry>,
+    /// Entries that are ready to be handed out.
+    ready: VecDeque<Entry>,
+    /// Every entry so far, to cache once the listing is complete, unless it isn't to be cached.
+    seen: Option<Vec<Entry>>,
+    /// With `prefetch_metadata`, the files listed from the current page, by the path they're
+    /// cached under, along with the names of their blobs.
+    prefetch: Vec<(BlobPath, BlobPath)>,
+    /// With `concat`, the virtual files in the folder that are yet to be handed out, in name
+    /// order.
+    virtual_files: VecDeque<Entry>,
+}
+
+impl DirLister {
+    /// Hands out held-back entries that can no longer collide with a folder, now that the listing
+    /// has reached `rel_name`.
+    fn release(&mut self, rel_name: &str) {
+        while let Some(front) = self.pending.front() {
+            // A folder is listed as its name followed by `/`, and everything else sorts either
+            // before that or after everything under it.
+            if !front.is_dir {
+                let folder = format!("{}/", front.name);
+                if rel_name <= folder.as_str() || rel_name.starts_with(&folder) {
+                    break;
+                }
+            }
+
+            let entry = self.pending.pop_front().unwrap();
+            self.hand_out(entry);
+        }
+    }
+
+    /// Hands out everything that is left, once the listing is complete.
+    fn finish(&mut self) {
+        while let Some(entry) = self.pending.pop_front() {
+            self.hand_out(entry);
+        }
+        while let Some(entry) = self.virtual_files.pop_front() {
+            self.emit(entry);
+        }
+    }
+
+    /// Hands out an entry, after any virtual files that sort before it. A virtual file hides a
+    /// blob of the same name.
+    fn hand_out(&mut self, entry: Entry) {
+        let key = if entry.is_dir {
+            format!("{}/", entry.name)
+        } else {
+            entry.name.clone()
+        };
+        while self.virtual_files.front().is_some_and(|v| v.name <= key) {
+            let file = self.virtual_files.pop_front().unwrap();
+            if file.name == key {
+                warn!(
+                    "hiding blob {}{key}, which collides with a concatenation of the same name",
+                    self.list_prefix
+                );
+                self.emit(file);
+                return;
+            }
+            self.emit(file);
+        }
+
+        self.emit(entry);
+    }
+
+    fn emit(&mut self, entry: Entry) {
+        if let Some(seen) = &mut self.seen {
+            if seen.len() < MAX_CACHED_ENTRIES {
+                seen.push(entry.clone());
+            } else {
+                self.seen = None;
+            }
+        }
+
+        self.ready.push_back(entry);
+    }
+}
+
+/// The entries of a directory, which are fetched from Azure as they're iterated over.
+///
+/// Errors after the first page of the listing can't be reported through an iterator, so they're
+/// logged, and cut the listing short.
+pub struct Listing<S: BlobSource = Delimited<TagFiltered<ContainerClient>>> {
+    backend: Arc<BlobBackend<S>>,
+    /// The directory being listed, relative to the mount root.
+    virt_path: PathBuf,
+    pattern: Option<SearchPattern>,
+    state: ListingState,
+}
+
+enum ListingState {
+    Cached {
+        items: Arc<Vec<Entry>>,
+        next: usize,
+    },
+    Live {
+        lister: Box<DirLister>,
+        /// The rest of the pages, wrapped to make the listing `Sync`.
+        pages: Mutex<Option<ListingPages>>,
+    },
+}
+
+impl<S: BlobSource> Iterator for Listing<S> {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        // File systems may drain the listing after the callback that started it has returned.
+        let _mount = logging::MountScope::enter(Some(self.backend.name.clone()));
+
+        loop {
+            let entry = match &mut self.state {
+                ListingState::Cached { items, next } => {
+                    let entry = items.get(*next)?.clone();
+                    *next += 1;
+                    entry
+                }
+                ListingState::Live { lister, pages } => match lister.ready.pop_front() {
+                    Some(entry) => entry,
+                    None => {
+                        let rest = pages.get_mut().unwrap().take()?;
+                        match self.backend.next_page(lister, rest) {
+                            Ok(rest) => *pages.get_mut().unwrap() = rest,
+                            Err(e) => {
+                                error!("failed to list {}: {e}", lister.path);
+                                lister.seen = None;
+                                lister.finish();
+                            }
+                        }
+
+                        continue;
+                    }
+                },
+            };
+
+            if self.pattern.as_ref().is_none_or(|p| p.matches(&entry.name)) {
+                let mut entry = entry;
+                if entry.is_dir && self.backend.options.compute_dir_sizes {
+                    entry.size = self.backend.dir_size(&self.virt_path.join(&entry.name));
+                }
+                return Some(entry);
+            }
+        }
+    }
+}
+
+pub struct BlobBackend<S: BlobSource = Delimited<TagFiltered<ContainerClient>>> {
+    source: Arc<S>,
+    options: DriverOptions,
+    /// Directories that we know about. Hack to ensure consistency between iteration and metadata calls.
+    /// Bounded, so that only the most recently seen directories are remembered.
+    known_dirs: Mutex<LruCache<PathBuf, ()>>,
+    /// Recent directory listings, keyed by the listed blob path.
+    listing_cache: Arc<TtlCache<BlobPath, Arc<Vec<Entry>>>>,
+    /// Recently seen files, keyed by blob path. Listings fill this in as well, so that files that
+    /// were just enumerated don't need to be looked up one by one.
+    metadata_cache: Arc<TtlCache<BlobPath, Entry>>,
+    /// Blobs that were recently looked up and not found.
+    misses: Arc<TtlCache<BlobPath, ()>>,
+    /// The total size of the blobs in recently listed folders, with `compute_dir_sizes`.
+    dir_sizes: TtlCache<BlobPath, u64>,
+    /// With `concat`, the virtual files that have been looked up so far, by their paths relative
+    /// to the mount root, along with their parts.
+    concats: Mutex<HashMap<PathBuf, (Entry, Arc<Parts>)>>,
+    /// The snapshot or version each recently seen blob resolves to, when mounting a point-in-time
+    /// view. `None` stands for the current version.
+    versions: Mutex<LruCache<BlobPath, Option<BlobVersioning>>>,
+    /// The actual names of blobs that were looked up with different casing, or with an inferred
+    /// extension.
+    canonical_names: Mutex<LruCache<BlobPath, BlobPath>>,
+    /// The type, length, access tier and lease state of recently seen blobs.
+    blob_kinds: Arc<Mutex<LruCache<BlobPath, BlobKind>>>,
+    /// Soft-deleted blobs seen in listings, by the path they're projected at, and the version that
+    /// each can be read through, if versioning kept one.
+    deleted: Mutex<LruCache<BlobPath, Option<String>>>,
+    /// Versions of blobs seen in `.versions` folders, by the path they're projected at, along
+    /// with the name of the blob and the ID of the version.
+    version_entries: Mutex<LruCache<BlobPath, (String, String)>>,
+    /// Archived blobs that rehydration was requested for.
+    rehydrating: Mutex<LruCache<BlobPath, ()>>,
+    /// Request and cache statistics.
+    stats: Arc<Stats>,
+    /// Downloaded blob ranges, if caching to disk is enabled.
+    disk_cache: Option<Arc<DiskCache>>,
+    /// The whole contents of small blobs, if caching them in memory is enabled.
+    memory_cache: Option<Arc<MemoryCache>>,
+    /// Read positions and prefetched ranges of blobs being read sequentially, if read-ahead or
+    /// progress reporting is enabled.
+    readahead: Option<Arc<ReadAhead>>,
+    /// Tells the caches above when blobs change.
+    coordinator: Arc<CacheCoordinator>,
+    /// Small reads waiting to be downloaded together, if coalescing is enabled.
+    coalescer: Option<Coalescer>,
+    /// Running checksums of blobs being read, if verification is enabled.
+    verifier: Option<Verifier>,
+    /// Permits for requests to Azure, if their concurrency is limited.
+    permits: Option<Arc<Semaphore>>,
+    /// Fails requests fast while Azure is failing, if enabled.
+    breaker: Option<CircuitBreaker>,
+    /// Limits how fast blobs are downloaded, if enabled.
+    limiter: Option<Arc<RateLimiter>>,
+    /// Whether the account has a hierarchical namespace, in which blobs mark directories.
+    hns: bool,
+    /// Whether recent requests have reached Azure.
+    connectivity: Mutex<Connectivity>,
+    /// A handle to the asynchronous runtime that dispatches requests to Azure blob storage.
+    rt: tokio::runtime::Handle,
+    /// The name that log lines about the mount are attributed to.
+    name: Arc<str>,
+}
+
+impl<S: BlobSource> BlobBackend<S> {
+    pub fn new(
+        source: S,
+        rt: tokio::runtime::Handle,
+        options: DriverOptions,
+    ) -> Result<Self, RazmountError> {
+        let disk_cache = options
+            .cache_dir
+            .as_ref()
+            .map(|dir| {
+                crate::extended_path(dir)
+                    .and_then(|path| DiskCache::open(path, options.cache_max_bytes))
+                    .map(Arc::new)
+                    .map_err(|e| {
+                        RazmountError::Config(format!(
+                            "failed to open cache in {}: {e}",
+                            dir.display()
+                        ))
+                    })
+            })
+            .transpose()?;
+
+        let memory_cache = (options.small_blob_threshold > 0)
+            .then(|| Arc::new(MemoryCache::new(options.small_blob_cache_bytes)));
+        let readahead = (options.readahead_bytes > 0 || options.progress_interval.is_some())
+            .then(|| Arc::new(ReadAhead::new(options.readahead_bytes, options.block_size)));
+        let coalescer = (!options.coalesce_window.is_zero())
+            .then(|| Coalescer::new(options.coalesce_window, options.coalesce_max_bytes));
+        let verifier = options.verify_checksums.then(Verifier::new);
+        let permits = options
+            .max_concurrency
+            .map(|n| Arc::new(Semaphore::new(n.get())));
+        let breaker = options
+            .breaker_threshold
+            .map(|n| CircuitBreaker::new(n, options.breaker_cooldown));
+        let limiter = options
+            .max_bandwidth
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+
+        let listing_cache = Arc::new(TtlCache::new(options.listing_ttl));
+        let metadata_cache = Arc::new(TtlCache::new(options.metadata_ttl));
+        let misses = Arc::new(TtlCache::new(std::cmp::min(options.metadata_ttl, MISS_TTL)));
+        let blob_kinds = Arc::new(Mutex::new(LruCache::new(options.dir_cache_size)));
+
+        let mut coordinator = CacheCoordinator::new(options.dir_cache_size);
+        {
+            let metadata_cache = metadata_cache.clone();
+            coordinator.register(move |blob, etag| {
+                metadata_cache.remove_if(&BlobPath::new(blob), |e: &Entry| {
+                    e.etag.as_ref() != Some(etag)
+                });
+            });
+        }
+        {
+            // A listing is stale if it has another version of the blob. Blobs that are missing
+            // from it are added to it when they're looked up (see `metadata`).
+            let listing_cache = listing_cache.clone();
+            coordinator.register(move |blob, etag| {
+                let (dir, name) = blob.rsplit_once('/').unwrap_or(("", blob));
+                listing_cache.remove_if(&BlobPath::new(dir), |items: &Arc<Vec<Entry>>| {
+                    items
+                        .iter()
+                        .any(|e| e.name == name && e.etag.as_ref() != Some(etag))
+                });
+            });
+        }
+        {
+            let misses = misses.clone();
+            coordinator.register(move |blob, _| misses.remove_if(&BlobPath::new(blob), |_| true));
+        }
+        {
+            let blob_kinds = blob_kinds.clone();
+            coordinator.register(move |blob, _| {
+                blob_kinds.lock().unwrap().pop(&BlobPath::new(blob));
+            });
+        }
+        if let Some(cache) = disk_cache.clone() {
+            coordinator.register(move |blob, etag| cache.observe_etag(blob, etag.as_ref()));
+        }
+        if let Some(cache) = memory_cache.clone() {
+            coordinator.register(move |blob, etag| cache.invalidate(blob, etag));
+        }
+        if let Some(ra) = readahead.clone() {
+            coordinator.register(move |blob, etag| ra.invalidate(blob, etag));
+        }
+
+        let name = match &options.name {
+            Some(name) => name.as_str().into(),
+            None => source.container_name().into(),
+        };
+
+        let backend = Self {
+            hns: options.hns == Some(true),
+            known_dirs: Mutex::new(LruCache::new(options.dir_cache_size)),
+            stats: Default::default(),
+            versions: Mutex::new(LruCache::new(options.dir_cache_size)),
+            canonical_names: Mutex::new(LruCache::new(options.dir_cache_size)),
+            blob_kinds,
+            rehydrating: Mutex::new(LruCache::new(options.dir_cache_size)),
+            deleted: Mutex::new(LruCache::new(options.dir_cache_size)),
+            version_entries: Mutex::new(LruCache::new(options.dir_cache_size)),
+            listing_cache,
+            metadata_cache,
+            misses,
+            dir_sizes: TtlCache::new(options.listing_ttl),
+            concats: Mutex::new(HashMap::new()),
+            source: Arc::new(source),
+            options,
+            disk_cache,
+            memory_cache,
+            readahead,
+            coordinator: Arc::new(coordinator),
+            coalescer,
+            verifier,
+            permits,
+            breaker,
+            limiter,
+            connectivity: Default::default(),
+            rt,
+            name,
+        };
+
+        if let Some(interval) = backend.options.follow_interval {
+            backend.follow(interval);
+        }
+
+        Ok(backend)
+    }
+
+    /// Lists the container every `interval` in the background, and drops whatever the caches
+    /// hold of blobs that were added, changed or deleted since the last time.
+    ///
+    /// Changed blobs are handled like any other new ETag. Added and deleted blobs change the
+    /// listings of every folder above them (which may have been created or emptied), so those are
+    /// dropped, to be listed afresh the next time they're enumerated.
+    fn follow(&self, interval: Duration) {
+        let source = self.source.clone();
+        let coordinator = self.coordinator.clone();
+        let listing_cache = self.listing_cache.clone();
+        let metadata_cache = self.metadata_cache.clone();
+        let permits = self.permits.clone();
+        let name = self.name.clone();
+
+        let mut list = ListOptions::default();
+        if !self.options.prefix.as_str().is_empty() {
+            list.prefix = Some(format!("{}/", self.options.prefix));
+        }
+
+        self.rt.spawn(async move {
+            let mut last: Option<follow::Snapshot> = None;
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                // N.B: The first tick completes immediately, taking the snapshot to compare with.
+                ticker.tick().await;
+
+                let r = snapshot(&*source, list.clone(), permits.as_ref()).await;
+
+                let _mount = logging::MountScope::enter(Some(name.clone()));
+                let snapshot = match r {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        warn!("failed to list the container to follow it: {e}");
+                        continue;
+                    }
+                };
+
+                let Some(before) = last.replace(snapshot) else {
+                    continue;
+                };
+                let changes = follow::Changes::between(&before, last.as_ref().unwrap());
+                if changes.is_empty() {
+                    continue;
+                }
+
+                info!("follow: {changes}");
+
+                let drop_listings = |blob: &str| {
+                    let mut dir = blob;
+                    while let Some((parent, _)) = dir.rsplit_once('/') {
+                        listing_cache.remove_if(&BlobPath::new(parent), |_| true);
+                        dir = parent;
+                    }
+                    listing_cache.remove_if(&BlobPath::new(""), |_| true);
+                };
+
+                for (blob, etag) in &changes.changed {
+                    coordinator.observe(blob, etag);
+                }
+                for (blob, etag) in &changes.added {
+                    coordinator.observe(blob, etag);
+                    drop_listings(blob);
+                }
+                for blob in &changes.deleted {
+                    metadata_cache.remove_if(&BlobPath::new(blob.as_str()), |_| true);
+                    drop_listings(blob);
+                }
+            }
+        });
+    }
+
+    /// Records the ETag seen for a blob, so that caches can drop anything from before it changed.
+    fn observe_etag(&self, blob: &str, etag: &Etag) {
+        self.coordinator.observe(blob, etag);
+    }
+
+    /// Records the type and length of a blob, which determine how it is read.
+    fn observe_kind(&self, path: BlobPath, props: &BlobProperties) {
+        self.blob_kinds.lock().unwrap().put(
+            path,
+            (
+                props.blob_type,
+                props.content_length,
+                props.access_tier,
+                props.lease_state,
+            ),
+        );
+    }
+
+    /// Looks up the type and length of a blob, from the last time it was seen if possible.
+    fn blob_kind(&self, path: &BlobPath) -> std::io::Result<BlobKind> {
+        if let Some(kind) = self.blob_kinds.lock().unwrap().get(path) {
+            return Ok(*kind);
+        }
+
+        let props = self.exact_properties(path)?.properties;
+        Ok((
+            props.blob_type,
+            props.content_length,
+            props.access_tier,
+            props.lease_state,
+        ))
+    }
+
+    /// Applies `--lease-policy` to a read of a blob, as of when its lease state was last seen.
+    /// Warnings are only logged for reads from the start of the blob, rather than for every read.
+    fn check_lease(&self, path: &BlobPath, offset: u64) -> std::io::Result<()> {
+        let policy = self.options.lease_policy;
+        if policy == LeasePolicy::Ignore {
+            return Ok(());
+        }
+
+        let Some(state) = active_lease(self.blob_kind(path)?.3) else {
+            return Ok(());
+        };
+
+        match policy {
+            LeasePolicy::Deny => {
+                warn!("refusing to read {path}: it's leased ({state:?})");
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!("{path} is leased by another client, which may be writing to it"),
+                ))
+            }
+            _ => {
+                if offset == 0 {
+                    warn!("{path} is leased ({state:?}): another client may be writing to it");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Explains why an archived blob can't be read. With `--auto-rehydrate`, the blob's
+    /// rehydration is requested the first time it's read, and reads are refused with
+    /// `WouldBlock` until it completes.
+    fn archived(&self, path: &BlobPath) -> std::io::Error {
+        let Some(tier) = self.options.auto_rehydrate else {
+            warn!("{path} is in the archive tier, and must be rehydrated before it can be read");
+            return RazmountError::Storage(
+                format!("{path} is archived: rehydrate it to the hot or cool tier to read it")
+                    .into(),
+            )
+            .into();
+        };
+
+        // Look the blob up again on the next read, to notice once rehydration completes.
+        self.blob_kinds.lock().unwrap().pop(path);
+
+        let requested = self.rehydrating.lock().unwrap().put(path.clone(), ());
+        if requested.is_none() {
+            let version = match self.blob_version(path) {
+                Ok(version) => version,
+                Err(e) => return e,
+            };
+
+            if let Err(e) = self.run(self.source.set_tier(path.as_str(), version, tier)) {
+                self.rehydrating.lock().unwrap().pop(path);
+                warn!("failed to request rehydration of {path}: {e}");
+                return e.into();
+            }
+
+            warn!("{path} is in the archive tier: requested rehydration to the {tier:?} tier");
+        }
+
+        std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            format!("{path} is being rehydrated from the archive tier: try again later"),
+        )
+    }
+
+    /// Checks that the container exists and can be listed with a single small request, so that a
+    /// misconfigured mount fails up front rather than appearing empty.
+    pub fn probe(&self) -> Result<(), RazmountError> {
+        let mut list = ListOptions {
+            max_results: Some(NonZeroU32::MIN),
+            ..Default::default()
+        };
+        if !self.options.prefix.as_str().is_empty() {
+            list.prefix = Some(format!("{}/", self.options.prefix));
+        }
+
+        let mut pages = self.source.list(list);
+        let container = self.source.container_name().to_string();
+
+        match self.run(async move { pages.try_next().await.map(|_| ()) }) {
+            Ok(()) => Ok(()),
+            Err(RazmountError::NotFound(_)) => Err(RazmountError::NotFound(
+                format!("container `{container}` does not exist").into(),
+            )),
+            Err(RazmountError::PermissionDenied(e)) => Err(RazmountError::PermissionDenied(
+                format!(
+                    "not permitted to list container `{container}`; check the credentials: {e}"
+                )
+                .into(),
+            )),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Determines if the account has a hierarchical namespace, unless that was given in the
+    /// options.
+    ///
+    /// The SDK doesn't expose the account's `x-ms-is-hns-enabled` header, so this looks for the
+    /// blobs that represent directories in such accounts at the start of the listing, which finds
+    /// nothing if no directories sort near the start. Pass the option explicitly in that case.
+    pub fn detect_namespace(&mut self) -> Result<(), RazmountError> {
+        if self.options.hns.is_some() || self.options.offline {
+            return Ok(());
+        }
+
+        let mut list = ListOptions {
+            max_results: NonZeroU32::new(HNS_PROBE_BLOBS),
+            include_metadata: true,
+            ..Default::default()
+        };
+        if !self.options.prefix.as_str().is_empty() {
+            list.prefix = Some(format!("{}/", self.options.prefix));
+        }
+
+        let mut pages = self.source.list(list);
+        let page = self.run(async move { pages.try_next().await })?;
+
+        self.hns = page.into_iter().flatten().any(|i| match i {
+            BlobItem::Blob(b) => is_dir_marker(&b),
+            BlobItem::BlobPrefix(_) => false,
+        });
+        if self.hns {
+            info!("detected a hierarchical namespace");
+        }
+
+        Ok(())
+    }
+
+    /// Counts the blobs that are projected (those under the prefix that aren't excluded), and
+    /// their total size, stopping once `max_blobs` have been counted.
+    ///
+    /// Blobs are counted as they currently are, even when mounting a point in time.
+    pub fn summarize(&self, max_blobs: u64) -> std::io::Result<Summary> {
+        self.summarize_dir(Path::new(""), max_blobs)
+    }
+
+    /// Like `summarize`, for the blobs in a folder (and the folders in it), given by its path
+    /// relative to the mount root.
+    fn summarize_dir(&self, virt_path: &Path, max_blobs: u64) -> std::io::Result<Summary> {
+        let path = self.blob_path(virt_path);
+        let mut list = ListOptions {
+            include_metadata: self.hns,
+            ..Default::default()
+        };
+        if !path.as_str().is_empty() {
+            list.prefix = Some(format!("{path}/"));
+        }
+
+        // Exclusions and depths are relative to the mount root.
+        let prefix = if self.options.prefix.as_str().is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.options.prefix)
+        };
+        let mut summary = Summary::default();
+        let mut pages = self.source.list(list);
+
+        loop {
+            let page = self.run(async move {
+                let page = pages.try_next().await?;
+                Ok(page.map(|page| (page, pages)))
+            })?;
+
+            let Some((page, rest)) = page else {
+                return Ok(summary);
+            };
+
+            for item in &page {
+                let BlobItem::Blob(b) = item else {
+                    continue;
+                };
+                if b.name.ends_with('/') || (self.hns && is_dir_marker(b)) {
+                    continue;
+                }
+
+                let rel_name = b.name.strip_prefix(prefix.as_str()).unwrap_or(&b.name);
+                if self.is_excluded(&BlobPath::new(rel_name))
+                    || !self.options.modified.contains(b.properties.last_modified)
+                    || self
+                        .options
+                        .max_depth
+                        .is_some_and(|max| rel_name.matches('/').count() > max)
+                {
+                    continue;
+                }
+
+                if summary.blobs == max_blobs {
+                    summary.truncated = true;
+                    return Ok(summary);
+                }
+
+                summary.blobs += 1;
+                summary.bytes += b.properties.content_length;
+            }
+
+            pages = rest;
+        }
+    }
+
+    /// With `compute_dir_sizes`, the total size of the blobs in a folder, given by its path
+    /// relative to the mount root. Sizes are cached for `--listing-ttl-secs`, and only count the
+    /// first `DIR_SIZE_MAX_BLOBS` blobs of folders with more in them.
+    fn dir_size(&self, virt_path: &Path) -> u64 {
+        let path = self.blob_path(virt_path);
+        if let Some(size) = self.dir_sizes.get(&path) {
+            return size;
+        }
+
+        match self.summarize_dir(virt_path, DIR_SIZE_MAX_BLOBS) {
+            Ok(summary) => {
+                info!("size of {path}: {summary}");
+                self.dir_sizes.insert(path, summary.bytes);
+                summary.bytes
+            }
+            Err(e) => {
+                warn!("failed to add up the size of {path}: {e}");
+                0
+            }
+        }
+    }
+
+    /// Returns the statistics for this driver, which keep updating as it runs.
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    /// Lists a directory, given by its path relative to the mount root, as it is projected.
+    /// Only entries matching `pattern` are returned, if one is given.
+    ///
+    /// Entries are produced as the pages of the listing arrive, rather than once the entire
+    /// directory has been listed.
+    pub fn enumerate(
+        self: &Arc<Self>,
+        virt_path: &Path,
+        pattern: Option<&SearchPattern>,
+    ) -> std::io::Result<Listing<S>> {
+        let path = self.blob_path(virt_path);
+        let _op = logging::Op::start(&self.name, "iter", path.as_str());
+        self.stats.listings.inc();
+
+        if self.options.blob_as_dir.is_some() {
+            return Ok(Listing {
+                backend: self.clone(),
+                virt_path: virt_path.to_path_buf(),
+                pattern: pattern.cloned(),
+                state: ListingState::Cached {
+                    items: Arc::new(self.chunks(virt_path)?),
+                    next: 0,
+                },
+            });
+        }
+
+        if let Some(items) = self.list_versions(virt_path)? {
+            return Ok(Listing {
+                backend: self.clone(),
+                virt_path: virt_path.to_path_buf(),
+                pattern: pattern.cloned(),
+                state: ListingState::Cached {
+                    items: Arc::new(items),
+                    next: 0,
+                },
+            });
+        }
+
+        let cached = self
+            .listing_cache
+            .get(&path)
+            .or_else(|| self.offline().then(|| self.cached_listing(&path)).flatten());
+
+        let state = if let Some(items) = cached {
+            info!("iter (cached): {path}");
+            self.stats.listing_cache_hits.inc();
+
+            // Make sure the cached subdirectories are still known to `metadata`.
+            let mut dirs = self.known_dirs.lock().unwrap();
+            for i in items.iter().filter(|i| i.is_dir) {
+                dirs.put(virt_path.join(&i.name), ());
+            }
+
+            ListingState::Cached { items, next: 0 }
+        } else {
+            let prefix = pattern.and_then(SearchPattern::listing_prefix);
+            if let Some(prefix) = &prefix {
+                // Only part of the directory is listed, so this can't be cached.
+                info!("iter: {path} (names starting with `{prefix}`)");
+            }
+
+            let (mut lister, pages) =
+                self.start_listing(virt_path, prefix.as_deref().unwrap_or_default())?;
+            if prefix.is_some() {
+                lister.seen = None;
+            }
+
+            // The first page is fetched up front, so that failures to list the directory at all
+            // are reported to the caller.
+            let pages = self.next_page(&mut lister, pages)?;
+
+            ListingState::Live {
+                lister: Box::new(lister),
+                pages: Mutex::new(pages),
+            }
+        };
+
+        Ok(Listing {
+            backend: self.clone(),
+            virt_path: virt_path.to_path_buf(),
+            pattern: pattern.cloned(),
+            state,
+        })
+    }
+
+    /// Looks up a file or folder by its path relative to the mount root.
+    pub fn metadata(&self, virt_path: &Path) -> std::io::Result<Entry> {
+        let _op = logging::Op::start(&self.name, "metadata", virt_path.to_string_lossy());
+        info!("metadata: {}", virt_path.display());
+        self.stats.metadata.inc();
+
+        // The root always exists, even if the container (or prefix) is empty.
+        let mut dirs = self.known_dirs.lock().unwrap();
+        if virt_path.as_os_str().is_empty() || dirs.get(virt_path).is_some() {
+            return Ok(Entry::dir(virt_path));
+        }
+
+        drop(dirs);
+
+        if self.options.blob_as_dir.is_some() {
+            return self.chunk(virt_path).map(|(entry, _)| entry);
+        }
+
+        if let Some(concat) = self.concat(virt_path) {
+            return self.concat_file(concat).map(|(entry, _)| entry);
+        }
+
+        if self.is_blocked(&virt_path.to_string_lossy()) {
+            return Err(blocked(virt_path));
+        }
+
+        if self.is_excluded(&BlobPath::from(virt_path)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is excluded", virt_path.display()),
+            ));
+        }
+
+        if virt_path.parent().is_some_and(|p| self.is_too_deep(p)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is deeper than --max-depth", virt_path.display()),
+            ));
+        }
+
+        let blob_path = self.blob_path(virt_path);
+        let cached = if self.offline() {
+            self.metadata_cache.get_stale(&blob_path)
+        } else {
+            self.metadata_cache.get(&blob_path)
+        };
+        if let Some(info) = cached {
+            self.stats.metadata_cache_hits.inc();
+            return Ok(info);
+        }
+
+        if self.offline() {
+            return self.offline_entry(virt_path);
+        }
+
+        if self.misses.get(&blob_path).is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} was not found a moment ago", virt_path.display()),
+            ));
+        }
+
+        if let Some(info) = self.version_metadata(virt_path)? {
+            return Ok(info);
+        }
+
+        let blob = match self.properties(&blob_path) {
+            // The folder may not have been enumerated yet, e.g. if an application opened a path
+            // within it directly.
+            Err(e)
+                if e.kind() == std::io::ErrorKind::NotFound
+                    && !self.is_too_deep(virt_path)
+                    && self.is_folder(virt_path)? =>
+            {
+                self.known_dirs
+                    .lock()
+                    .unwrap()
+                    .put(virt_path.to_path_buf(), ());
+                return Ok(Entry::dir(virt_path));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.misses.insert(blob_path, ());
+                return Err(e);
+            }
+            r => r?,
+        };
+
+        if self.hns && is_dir_marker(&blob) {
+            self.known_dirs
+                .lock()
+                .unwrap()
+                .put(virt_path.to_path_buf(), ());
+            return Ok(Entry::dir(virt_path));
+        }
+
+        if self.is_blocked(&blob.name) {
+            self.misses.insert(blob_path, ());
+            return Err(blocked(virt_path));
+        }
+
+        if !self
+            .options
+            .modified
+            .contains(blob.properties.last_modified)
+        {
+            self.misses.insert(blob_path, ());
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!(
+                    "{} was not modified within the mounted window",
+                    virt_path.display()
+                ),
+            ));
+        }
+
+        if let Some(tier) = blob.properties.access_tier {
+            info!(
+                "{blob_path}: {} bytes, {tier:?} tier",
+                blob.properties.content_length
+            );
+        }
+
+        if self.options.lease_policy != LeasePolicy::Ignore {
+            if let Some(state) = active_lease(blob.properties.lease_state) {
+                warn!("{blob_path} is leased ({state:?}): another client may be writing to it");
+            }
+        }
+
+        let info = self.blob_info(&blob);
+        self.add_to_listing(&BlobPath::new(&blob.name), &info);
+        self.metadata_cache.insert(blob_path, info.clone());
+
+        Ok(info)
+    }
+
+    /// Adds a blob that was found by looking it up to the cached listing of its folder, if it's
+    /// missing from it (e.g. because it was uploaded after the folder was listed), so that it
+    /// shows up in the folder without waiting for the listing to expire.
+    fn add_to_listing(&self, blob: &BlobPath, info: &Entry) {
+        let dir = BlobPath::new(blob.as_str().rsplit_once('/').map_or("", |(dir, _)| dir));
+
+        self.listing_cache.update(&dir, |items| {
+            if items.iter().all(|e| e.name != info.name) {
+                info!("-> {} (found since {dir} was listed)", info.name);
+
+                // Listings are in name order, as they come from Azure.
+                let at = items.partition_point(|e| e.name < info.name);
+                Arc::make_mut(items).insert(at, info.clone());
+            }
+        });
+    }
+
+    /// With `versions_as_subdir`, lists the versions of the blob that a `<name>.versions` folder
+    /// stands for, or returns `None` if `virt_path` isn't such a folder (or the blob has no
+    /// versions).
+    ///
+    /// Versions are named after their IDs, which are timestamps, with `:` replaced by `-` and the
+    /// blob's extension kept, e.g. `2024-05-01T12-30-00.1234567Z.csv`. They are read-only, since
+    /// versions can't be modified.
+    fn list_versions(&self, virt_path: &Path) -> std::io::Result<Option<Vec<Entry>>> {
+        if !self.options.versions_as_subdir {
+            return Ok(None);
+        }
+
+        let Some(blob) = virt_path
+            .to_str()
+            .and_then(|p| p.strip_suffix(VERSIONS_SUFFIX))
+            .filter(|p| !p.is_empty() && !p.ends_with('/'))
+        else {
+            return Ok(None);
+        };
+
+        let blob = self.blob_path(Path::new(blob));
+        let list = ListOptions {
+            prefix: Some(blob.as_str().to_string()),
+            include_versions: true,
+            ..Default::default()
+        };
+
+        let dir = self.blob_path(virt_path);
+        let ext = Path::new(blob.as_str())
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut items = Vec::new();
+        for item in self.list(list)? {
+            let BlobItem::Blob(b) = item else { continue };
+            let Some(id) = b.version_id.as_ref().filter(|_| b.name == blob.as_str()) else {
+                continue;
+            };
+
+            let name = format!("{}{ext}", id.replace(':', "-"));
+            if !is_projectable(&name) {
+                continue;
+            }
+
+            // Everything about the version is kept under the path it's projected at.
+            let path = dir.join(&BlobPath::new(name.as_str()));
+            self.observe_kind(path.clone(), &b.properties);
+            self.version_entries
+                .lock()
+                .unwrap()
+                .put(path.clone(), (b.name.clone(), id.clone()));
+
+            let info = Entry {
+                name,
+                attrs: self.file_attributes(&b.properties) | FILE_ATTRIBUTE_READONLY,
+                ..self.blob_info(&b)
+            };
+            self.metadata_cache.insert(path, info.clone());
+            items.push(info);
+        }
+
+        if items.is_empty() {
+            return Ok(None);
+        }
+
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Some(items))
+    }
+
+    /// Looks up a `.versions` folder, or a version within one, that isn't cached (e.g. because
+    /// its folder hasn't been listed lately), by listing the versions of its blob.
+    fn version_metadata(&self, virt_path: &Path) -> std::io::Result<Option<Entry>> {
+        if self.list_versions(virt_path)?.is_some() {
+            self.known_dirs
+                .lock()
+                .unwrap()
+                .put(virt_path.to_path_buf(), ());
+            return Ok(Some(Entry::dir(virt_path)));
+        }
+
+        let (Some(parent), Some(name)) = (virt_path.parent(), virt_path.file_name()) else {
+            return Ok(None);
+        };
+
+        match self.list_versions(parent)? {
+            Some(items) => match items.into_iter().find(|i| name == i.name.as_str()) {
+                Some(info) => Ok(Some(info)),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} is not a version of its blob", virt_path.display()),
+                )),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the contents of a file, given by its path relative to the mount root, at `offset`.
+    /// The whole buffer is filled, so it must not extend past the end of the file.
+    pub fn read(&self, virt_path: &Path, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        if let Some(concat) = self.concat(virt_path) {
+            let (_, parts) = self.concat_file(concat)?;
+            if offset + buf.len() as u64 > parts.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("{} is only {} bytes long", virt_path.display(), parts.len()),
+                ));
+            }
+
+            for (part, offset, range) in parts.split(offset, buf.len()) {
+                self.read(part, offset, &mut buf[range])?;
+            }
+            return Ok(());
+        }
+
+        let (path, offset) = match &self.options.blob_as_dir {
+            Some(_) => {
+                let (_, start) = self.chunk(virt_path)?;
+                (self.chunked_blob()?.0, start + offset)
+            }
+            None => (self.canonical_name(self.blob_path(virt_path)), offset),
+        };
+        if self.is_blocked(&virt_path.to_string_lossy()) || self.is_blocked(path.as_str()) {
+            return Err(blocked(virt_path));
+        }
+
+        let _op = logging::Op::start(&self.name, "read", path.as_str());
+        info!("{path}: {offset}, {}", buf.len());
+        self.stats.reads.inc();
+
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        self.check_lease(&path, offset)?;
+
+        let len = buf.len();
+        let sequential = self
+            .readahead
+            .as_ref()
+            .is_some_and(|ra| ra.access(path.as_str(), offset, len));
+        if sequential {
+            self.report_progress(&path);
+        }
+
+        if let Some(cache) = &self.memory_cache {
+            if cache.get(path.as_str(), offset, buf).is_some() {
+                self.verify(&path, offset, buf)?;
+                self.stats.memory_cache_hits.inc();
+                self.stats.read_bytes.add(len as u64);
+                return Ok(());
+            }
+        }
+
+        if let Some(cache) = &self.disk_cache {
+            if cache.get(path.as_str(), offset, buf) {
+                self.verify(&path, offset, buf)?;
+                self.stats.disk_cache_hits.inc();
+                self.stats.read_bytes.add(len as u64);
+                return Ok(());
+            }
+        }
+
+        let prefetched = self
+            .readahead
+            .as_ref()
+            .filter(|_| sequential)
+            .and_then(|ra| ra.get(path.as_str(), offset, buf));
+
+        // The range that was downloaded to serve this read, if any.
+        let (etag, downloaded) = match prefetched {
+            Some(etag) => {
+                self.stats.readahead_hits.inc();
+                (etag, None)
+            }
+            None => {
+                let download = self.download(&path)?;
+                let parts = self.options.parallel_ranges.get();
+                let kind = self.blob_kind(&path)?;
+
+                // Downloads cover whole blocks, so that what's cached can serve reads nearby, and
+                // small blobs are downloaded whole, to serve every later read from memory.
+                let whole = self.memory_cache.is_some()
+                    && kind.1 <= self.options.small_blob_threshold
+                    && offset + len as u64 <= kind.1;
+                let (start, span) = if whole {
+                    (0, kind.1 as usize)
+                } else {
+                    self.align(offset, len, kind.1)
+                };
+
+                // Small reads are a single request each, which may as well be shared.
+                let coalescer = self
+                    .coalescer
+                    .as_ref()
+                    .filter(|_| span < PARALLEL_READ_THRESHOLD);
+
+                let r = match (kind, coalescer) {
+                    ((_, _, Some(AccessTier::Archive), _), _) => return Err(self.archived(&path)),
+                    ((BlobType::PageBlob, blob_len, _, _), _) => self
+                        .run_parallel(
+                            parts,
+                            get_sparse_range(download, start, span, blob_len, parts),
+                        )
+                        .map_err(Into::into),
+                    (_, Some(coalescer)) => {
+                        coalescer.read(path.as_str(), start, span, |start, len| {
+                            Ok(self.run(download.get_range(start, len))?)
+                        })
+                    }
+                    ((kind, _, _, _), None) => self
+                        .run_parallel(
+                            if span < PARALLEL_READ_THRESHOLD {
+                                1
+                            } else {
+                                parts
+                            },
+                            get_ranges(download, start, span, parts, kind != BlobType::AppendBlob),
+                        )
+                        .map_err(Into::into),
+                };
+
+                let (etag, data) = r.map_err(|e| {
+                    if changed(&e) {
+                        RazmountError::Storage(
+                            format!("{path} has changed since its properties were read").into(),
+                        )
+                        .into()
+                    } else {
+                        e
+                    }
+                })?;
+
+                // Never hand back a partially-filled buffer.
+                let skip = (offset - start) as usize;
+                if data.len() < skip + len {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "short read of {path} at offset {offset}: got {} of {len} bytes",
+                            data.len().saturating_sub(skip)
+                        ),
+                    ));
+                }
+
+                buf.copy_from_slice(&data[skip..skip + len]);
+                if let Some(cache) = self.memory_cache.as_ref().filter(|_| whole) {
+                    cache.insert(path.as_str(), etag.clone(), data.clone());
+                }
+                (etag, Some((start, data)))
+            }
+        };
+
+        self.verify(&path, offset, buf)?;
+
+        if let Some(ra) = self
+            .readahead
+            .as_ref()
+            .filter(|_| sequential && self.options.readahead_bytes > 0)
+        {
+            let download = self.download(&path)?;
+            let permits = self.permits.clone();
+            ra.prefetch(path.as_str(), len, &self.rt, |start, len| {
+                let r = download.get_range(start, len);
+                let permits = permits.clone();
+
+                // Read-ahead counts towards `--max-concurrency` like any other request.
+                async move {
+                    let _permit = match permits {
+                        Some(permits) => permits.acquire_owned().await.ok(),
+                        None => None,
+                    };
+
+                    r.await
+                }
+            });
+        }
+
+        if let Some(etag) = &etag {
+            self.observe_etag(path.as_str(), etag);
+        }
+
+        if let (Some(cache), Some(etag)) = (&self.disk_cache, etag) {
+            match &downloaded {
+                Some((start, data)) => cache.insert(path.as_str(), etag.as_ref(), *start, data),
+                None => cache.insert(path.as_str(), etag.as_ref(), offset, buf),
+            }
+        }
+
+        self.stats.read_bytes.add(len as u64);
+        Ok(())
+    }
+
+    /// With `concat`, the concatenation projected at a path, if any.
+    fn concat(&self, virt_path: &Path) -> Option<&Concat> {
+        self.options.concat.iter().find(|c| c.name == virt_path)
+    }
+
+    /// With `concat`, the virtual file made of the blobs a concatenation names, and its parts.
+    /// The parts are listed the first time the file is looked up, and not again, so that the
+    /// file's size stays the same for as long as it's mounted.
+    fn concat_file(&self, concat: &Concat) -> std::io::Result<(Entry, Arc<Parts>)> {
+        if let Some(file) = self.concats.lock().unwrap().get(&concat.name) {
+            return Ok(file.clone());
+        }
+
+        // Only the blobs that start with the literal part of the pattern need to be listed.
+        let pattern = concat.parts.glob().glob();
+        let literal = &pattern[..pattern
+            .find(['*', '?', '[', '{', '\\'])
+            .unwrap_or(pattern.len())];
+        let prefix = if self.options.prefix.as_str().is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.options.prefix)
+        };
+        let list = ListOptions {
+            prefix: Some(format!("{prefix}{literal}")).filter(|p| !p.is_empty()),
+            include_metadata: self.hns,
+            ..Default::default()
+        };
+
+        let mut parts = Vec::new();
+        let mut latest: Option<Blob> = None;
+        let mut pages = self.source.list(list);
+        loop {
+            let page = self.run(async move {
+                let page = pages.try_next().await?;
+                Ok(page.map(|page| (page, pages)))
+            })?;
+            let Some((page, rest)) = page else {
+                break;
+            };
+
+            for item in page {
+                let BlobItem::Blob(b) = item else {
+                    continue;
+                };
+                let rel_name = b.name.strip_prefix(prefix.as_str()).unwrap_or(&b.name);
+                if b.name.ends_with('/')
+                    || (self.hns && is_dir_marker(&b))
+                    || !concat.parts.is_match(rel_name)
+                    || self.is_excluded(&BlobPath::new(rel_name))
+                {
+                    continue;
+                }
+
+                parts.push((PathBuf::from(rel_name), b.properties.content_length));
+                if latest
+                    .as_ref()
+                    .is_none_or(|l| l.properties.last_modified < b.properties.last_modified)
+                {
+                    latest = Some(b);
+                }
+            }
+
+            pages = rest;
+        }
+
+        let Some(latest) = latest else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no blobs match `{pattern}`"),
+            ));
+        };
+
+        info!(
+            "{}: {} parts matching `{pattern}`",
+            concat.name.display(),
+            parts.len()
+        );
+        let parts = Arc::new(Parts::new(parts));
+        let entry = Entry {
+            name: concat
+                .name
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            size: parts.len(),
+            attrs: FILE_ATTRIBUTE_READONLY,
+            etag: None,
+            ..self.blob_info(&latest)
+        };
+
+        let file = (entry, parts);
+        self.concats
+            .lock()
+            .unwrap()
+            .insert(concat.name.clone(), file.clone());
+        Ok(file)
+    }
+
+    /// With `blob_as_dir`, the blob that is projected as chunks, and its properties.
+    fn chunked_blob(&self) -> std::io::Result<(BlobPath, Entry)> {
+        let name = self.options.blob_as_dir.as_deref().unwrap_or_default();
+        let path = self.options.prefix.join(&BlobPath::new(name));
+
+        if let Some(info) = self.metadata_cache.get(&path) {
+            return Ok((path, info));
+        }
+
+        let info = self.blob_info(&self.exact_properties(&path)?);
+        self.metadata_cache.insert(path.clone(), info.clone());
+        Ok((path, info))
+    }
+
+    /// With `blob_as_dir`, the chunk files that make up the root, named after their index.
+    fn chunks(&self, virt_path: &Path) -> std::io::Result<Vec<Entry>> {
+        if !virt_path.as_os_str().is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{} is not a folder", virt_path.display()),
+            ));
+        }
+
+        let (_, info) = self.chunked_blob()?;
+        let chunks = info.size.div_ceil(self.options.chunk_size.get());
+
+        Ok((0..chunks).map(|i| self.chunk_entry(&info, i)).collect())
+    }
+
+    /// Describes the chunk of a blob with the given index.
+    fn chunk_entry(&self, info: &Entry, index: u64) -> Entry {
+        let chunk = self.options.chunk_size.get();
+
+        Entry {
+            name: format!("{index:08}.chunk"),
+            size: std::cmp::min(chunk, info.size - index * chunk),
+            ..info.clone()
+        }
+    }
+
+    /// With `blob_as_dir`, finds the chunk file at a path, along with the offset in the blob that
+    /// it starts at.
+    fn chunk(&self, virt_path: &Path) -> std::io::Result<(Entry, u64)> {
+        let (_, info) = self.chunked_blob()?;
+        let chunk = self.options.chunk_size.get();
+
+        let name = virt_path.to_string_lossy();
+        let index = name
+            .strip_suffix(".chunk")
+            .and_then(|i| i.parse::<u64>().ok())
+            .filter(|&i| i < info.size.div_ceil(chunk));
+
+        match index.map(|i| (self.chunk_entry(&info, i), i)) {
+            // Only the canonical spelling of the index (e.g. not `0.chunk`).
+            Some((entry, i)) if entry.name == name => Ok((entry, i * chunk)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{name} is not a chunk of the blob"),
+            )),
+        }
+    }
+
+    /// Widens a read to the `block_size` boundaries around it, up to the end of the blob.
+    fn align(&self, offset: u64, len: usize, blob_len: u64) -> (u64, usize) {
+        let block = self.options.block_size;
+        if block == 0 {
+            return (offset, len);
+        }
+
+        let end = offset + len as u64;
+        let start = offset / block * block;
+        let aligned_end = std::cmp::max(std::cmp::min(end.div_ceil(block) * block, blob_len), end);
+
+        (start, (aligned_end - start) as usize)
+    }
+
+    /// Logs how much of a blob has been read in a row, at most once per `progress_interval`.
+    fn report_progress(&self, path: &BlobPath) {
+        let (Some(interval), Some(ra)) = (self.options.progress_interval, &self.readahead) else {
+            return;
+        };
+        let Some(read) = ra.progress(path.as_str(), interval) else {
+            return;
+        };
+
+        match self.blob_kinds.lock().unwrap().peek(path) {
+            Some(&(_, len, _, _)) if len > 0 => info!(
+                path = path.as_str(),
+                bytes = read;
+                "{path}: read {read} of {len} bytes ({}%)", read * 100 / len
+            ),
+            _ => info!(path = path.as_str(), bytes = read; "{path}: read {read} bytes"),
+        }
+    }
+
+    /// Describes how to download a blob: the version to download, and (with `--pin-etags`) the
+    /// ETag it must still have.
+    fn download(&self, path: &BlobPath) -> std::io::Result<Download<S>> {
+        // A soft-deleted blob can't be read as such, only through a version of it.
+        if let Some(version) = self.deleted.lock().unwrap().get(path).cloned() {
+            let Some(id) = version else {
+                return Err(RazmountError::Storage(
+                    format!("{path} is soft-deleted: undelete it to read it").into(),
+                )
+                .into());
+            };
+
+            let name = path
+                .as_str()
+                .strip_suffix(DELETED_SUFFIX)
+                .unwrap_or_default();
+            return Ok(Download {
+                source: self.source.clone(),
+                name: name.to_string(),
+                version: Some(VersionId::new(id).into()),
+                if_match: None,
+                limiter: self.limiter.clone(),
+            });
+        }
+
+        // Versions are immutable, so there's no ETag to pin.
+        if let Some((name, id)) = self.version_entries.lock().unwrap().get(path).cloned() {
+            return Ok(Download {
+                source: self.source.clone(),
+                name,
+                version: Some(VersionId::new(id).into()),
+                if_match: None,
+                limiter: self.limiter.clone(),
+            });
+        }
+
+        Ok(Download {
+            source: self.source.clone(),
+            name: path.as_str().to_string(),
+            version: self.blob_version(path)?,
+            if_match: self.pinned_etag(path)?,
+            limiter: self.limiter.clone(),
+        })
+    }
+
+    /// With `--pin-etags`, the ETag that reads of a blob are conditional on: the one it had when
+    /// its properties were last seen, for as long as they're cached, so that applications don't
+    /// read contents that disagree with the size (or anything else) they were told about.
+    fn pinned_etag(&self, path: &BlobPath) -> std::io::Result<Option<Etag>> {
+        if !self.options.pin_etags {
+            return Ok(None);
+        }
+
+        if let Some(info) = self.metadata_cache.get(path) {
+            return Ok(info.etag);
+        }
+
+        let info = self.blob_info(&self.exact_properties(path)?);
+        self.metadata_cache.insert(path.clone(), info.clone());
+        Ok(info.etag)
+    }
+
+    /// Determines if requests to Azure are to be skipped, either because the mount is offline or
+    /// because it fell back to its caches after too many failed requests. In the latter case,
+    /// Azure is tried again every `RECONNECT_INTERVAL`.
+    fn offline(&self) -> bool {
+        self.options.offline
+            || self
+                .connectivity
+                .lock()
+                .unwrap()
+                .offline_since
+                .is_some_and(|t| t.elapsed() < RECONNECT_INTERVAL)
+    }
+
+    /// Records whether a request reached Azure, falling back to the caches once too many in a
+    /// row haven't (with `offline_after`).
+    fn record_connectivity(&self, reached: bool) {
+        let Some(max) = self.options.offline_after else {
+            return;
+        };
+
+        let mut c = self.connectivity.lock().unwrap();
+        if reached {
+            if c.offline_since.take().is_some() {
+                info!("blob storage is reachable again: leaving offline mode");
+            }
+
+            c.failures = 0;
+            return;
+        }
+
+        c.failures = c.failures.saturating_add(1);
+        if c.failures >= max.get() {
+            if c.offline_since.is_none() {
+                warn!(
+                    "{} requests in a row failed to reach blob storage: serving only what is cached",
+                    c.failures
+                );
+            }
+
+            c.offline_since = Some(Instant::now());
+        }
+    }
+
+    /// Looks a path up in its parent's cached listing, for when Azure can't be asked.
+    fn offline_entry(&self, virt_path: &Path) -> std::io::Result<Entry> {
+        let parent = virt_path.parent().unwrap_or(Path::new(""));
+        let name = virt_path.file_name().unwrap_or_default().to_string_lossy();
+
+        let entry = self
+            .cached_listing(&self.blob_path(parent))
+            .and_then(|items| {
+                items
+                    .iter()
+                    .find(|e| {
+                        e.name == name
+                            || (!self.options.case_sensitive
+                                && e.name.to_lowercase() == name.to_lowercase())
+                    })
+                    .cloned()
+            });
+
+        match entry {
+            Some(e) if e.is_dir => {
+                self.known_dirs
+                    .lock()
+                    .unwrap()
+                    .put(virt_path.to_path_buf(), ());
+                Ok(Entry::dir(virt_path))
+            }
+            Some(e) => {
+                if e.name != name {
+                    let actual = self.blob_path(&parent.join(&e.name));
+                    let mut names = self.canonical_names.lock().unwrap();
+                    names.put(self.blob_path(virt_path), actual);
+                }
+
+                Ok(e)
+            }
+            None => Err(not_cached()),
+        }
+    }
+
+    /// The last listing of a directory, however old, from memory or else from the disk cache.
+    fn cached_listing(&self, path: &BlobPath) -> Option<Arc<Vec<Entry>>> {
+        if let Some(items) = self.listing_cache.get_stale(path) {
+            return Some(items);
+        }
+
+        let data = self.disk_cache.as_ref()?.get_listing(path.as_str())?;
+        let items: Vec<Entry> = match serde_json::from_slice(&data) {
+            Ok(items) => items,
+            Err(e) => {
+                warn!("ignoring unreadable cached listing of {path}: {e}");
+                return None;
+            }
+        };
+
+        // Blocks are only served from the disk cache once their blob's ETag is known, and the
+        // listing is the only place to learn it from.
+        for e in items.iter().filter(|e| !e.is_dir) {
+            if let Some(etag) = &e.etag {
+                self.observe_etag(path.join(&BlobPath::new(&e.name)).as_str(), etag);
+            }
+        }
+
+        Some(Arc::new(items))
+    }
+
+    /// Stores a complete listing of a directory in the disk cache, so that it can be browsed
+    /// offline later on.
+    fn save_listing(&self, path: &BlobPath, items: &[Entry]) {
+        let Some(cache) = &self.disk_cache else {
+            return;
+        };
+
+        match serde_json::to_vec(items) {
+            Ok(data) => cache.insert_listing(path.as_str(), &data),
+            Err(e) => warn!("failed to serialize the listing of {path}: {e}"),
+        }
+    }
+
+    /// Runs a request on the shared runtime, blocking the calling thread until it completes.
+    ///
+    /// File systems invoke callbacks from many threads at once, so rather than having each of them
+    /// drive the runtime with `block_on`, the request is spawned onto the runtime's worker
+    /// threads and its result handed back over a oneshot channel.
+    fn run<T, F>(&self, f: F) -> Result<T, RazmountError>
+    where
+        T: Send + 'static,
+        F: Future<Output = azure_core::Result<T>> + Send + 'static,
+    {
+        self.run_parallel(1, f)
+    }
+
+    /// Like `run`, for a request that is made up of up to `parts` concurrent requests.
+    ///
+    /// With `--max-concurrency`, the request waits for a permit for each part before it starts,
+    /// and holds them until it completes. Requests never take more permits than there are, and
+    /// take all of them at once, so that they can't deadlock waiting on each other.
+    fn run_parallel<T, F>(&self, parts: usize, f: F) -> Result<T, RazmountError>
+    where
+        T: Send + 'static,
+        F: Future<Output = azure_core::Result<T>> + Send + 'static,
+    {
+        if self.offline() {
+            return Err(RazmountError::NotFound(NOT_CACHED.into()));
+        }
+
+        if self.breaker.as_ref().is_some_and(|b| !b.allow()) {
+            return Err(RazmountError::Network(
+                "blob storage is failing: the circuit breaker is open".into(),
+            ));
+        }
+
+        let permits = self.permits.clone().map(|permits| {
+            let max = self.options.max_concurrency.map_or(1, NonZeroUsize::get);
+            (permits, std::cmp::min(parts, max).max(1) as u32)
+        });
+
+        // Each attempt is bounded by the client's pipeline, which retries attempts that time out.
+        // This bounds the request as a whole (including reading the response body, which the
+        // pipeline doesn't cover), allowing for every attempt to run out its time.
+        let timeout = self.options.request_timeout.map(|t| t * REQUEST_ATTEMPTS);
+
+        let start = Instant::now();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.rt.spawn(async move {
+            // N.B: The semaphore is never closed, so acquiring a permit can't fail.
+            let _permit = match permits {
+                Some((permits, n)) => permits.acquire_many_owned(n).await.ok(),
+                None => None,
+            };
+
+            let r = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, f).await.map_err(|_| {
+                    RazmountError::Network(
+                        std::io::Error::new(
+                            std::io::ErrorKind::TimedOut,
+                            format!("blob storage request did not complete within {timeout:?}"),
+                        )
+                        .into(),
+                    )
+                }),
+                None => Ok(f.await),
+            };
+
+            let _ = tx.send(r);
+        });
+
+        let r = rx.blocking_recv().unwrap_or_else(|_| {
+            Err(RazmountError::Storage(
+                "blob storage request was cancelled".into(),
+            ))
+        });
+
+        // Requests that time out or fail to connect never reach Azure, as opposed to Azure
+        // answering with an error.
+        let reached = match &r {
+            Ok(Err(e)) => !matches!(e.kind(), azure_core::error::ErrorKind::Io),
+            Ok(Ok(_)) => true,
+            Err(_) => false,
+        };
+        self.record_connectivity(reached);
+
+        let r = r.and_then(|r| r.map_err(|e| self.storage_error(e)));
+        if let Some(breaker) = &self.breaker {
+            breaker.record(r.as_ref().is_err_and(is_outage));
+        }
+
+        self.stats.record_request(start.elapsed(), r.is_ok());
+        r
+    }
+
+    /// Renders the properties and user metadata of the blob at a path, relative to the mount
+    /// root, as a JSON document (e.g. for the `azmeta` alternate data stream).
+    pub fn meta_stream(&self, virt_path: &Path) -> std::io::Result<Vec<u8>> {
+        let blob = self.properties(&self.blob_path(virt_path))?;
+
+        let props = &blob.properties;
+        let doc = serde_json::json!({
+            "content_type": props.content_type,
+            "content_encoding": props.content_encoding,
+            "content_language": props.content_language,
+            "content_disposition": props.content_disposition,
+            "cache_control": props.cache_control,
+            "content_length": props.content_length,
+            "etag": props.etag.to_string(),
+            "last_modified": azure_core::date::to_rfc3339(&props.last_modified),
+            "metadata": blob.metadata.unwrap_or_default(),
+        });
+
+        serde_json::to_vec_pretty(&doc).map_err(std::io::Error::from)
+    }
+
+    /// Fetches the properties of a blob, as of the mounted point in time.
+    ///
+    /// Windows treats paths case-insensitively, so unless `case_sensitive` is set, a blob that
+    /// can't be found by its exact name is looked for amongst its siblings by case-folded name.
+    ///
+    /// With `infer_extensions`, a name with an extension that blobs can be projected with is also
+    /// looked for without it.
+    fn properties(&self, path: &BlobPath) -> std::io::Result<Blob> {
+        let path = &self.canonical_name(path.clone());
+
+        match self.exact_properties(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if let Some(blob) = self.strip_inferred(path)? {
+                    return Ok(blob);
+                }
+
+                if self.options.case_sensitive {
+                    return Err(e);
+                }
+
+                match self.resolve_case(path)? {
+                    Some(actual) => self.exact_properties(&actual),
+                    None => Err(e),
+                }
+            }
+            r => r,
+        }
+    }
+
+    /// With `infer_extensions`, looks for the blob that `path` would have been inferred from:
+    /// the same name without its extension, with a content type that the extension stands for.
+    /// If found, the name is remembered for subsequent requests.
+    fn strip_inferred(&self, path: &BlobPath) -> std::io::Result<Option<Blob>> {
+        if !self.options.infer_extensions {
+            return Ok(None);
+        }
+
+        let Some((actual, ext)) = path.as_str().rsplit_once('.') else {
+            return Ok(None);
+        };
+        if actual.ends_with('/') || actual.rsplit('/').next().is_some_and(|l| l.contains('.')) {
+            return Ok(None);
+        }
+        if !EXTENSIONS.iter().any(|(_, e)| *e == ext) {
+            return Ok(None);
+        }
+
+        let actual = BlobPath::new(actual);
+        let blob = match self.exact_properties(&actual) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            r => r?,
+        };
+
+        if inferred_extension(&blob.properties.content_type) != Some(ext) {
+            return Ok(None);
+        }
+
+        info!("resolved {path} to {actual}");
+        let mut names = self.canonical_names.lock().unwrap();
+        names.put(path.clone(), actual);
+
+        Ok(Some(blob))
+    }
+
+    /// Returns the actual name of a blob previously found by a case-insensitive match.
+    fn canonical_name(&self, path: BlobPath) -> BlobPath {
+        match self.canonical_names.lock().unwrap().get(&path) {
+            Some(actual) => actual.clone(),
+            None => path,
+        }
+    }
+
+    /// Looks for a blob whose name only differs from `path` by case, remembering it if found.
+    fn resolve_case(&self, path: &BlobPath) -> std::io::Result<Option<BlobPath>> {
+        let prefix = match path.as_str().rsplit_once('/') {
+            Some((parent, _)) => format!("{parent}/"),
+            None => String::new(),
+        };
+
+        let list = ListOptions {
+            prefix: (!prefix.is_empty()).then_some(prefix),
+            delimiter: Some('/'),
+            ..Default::default()
+        };
+
+        let wanted = path.as_str().to_lowercase();
+        let actual = self.list(list)?.into_iter().find_map(|i| match i {
+            BlobItem::Blob(b) if b.name.to_lowercase() == wanted => Some(BlobPath::new(b.name)),
+            _ => None,
+        });
+
+        if let Some(actual) = &actual {
+            info!("resolved {path} to {actual}");
+            let mut names = self.canonical_names.lock().unwrap();
+            names.put(path.clone(), actual.clone());
+        }
+
+        Ok(actual)
+    }
+
+    /// Fetches the properties of the blob with exactly the given name.
+    fn exact_properties(&self, path: &BlobPath) -> std::io::Result<Blob> {
+        let version = self.blob_version(path)?;
+        let blob = self.run(self.source.properties(path.as_str(), version))?;
+        self.observe_etag(path.as_str(), &blob.properties.etag);
+        self.observe_kind(path.clone(), &blob.properties);
+
+        Ok(blob)
+    }
+
+    /// Determines which snapshot or version of a blob to serve, if mounting a point-in-time view.
+    ///
+    /// Blobs seen in a listing are resolved as part of it; anything else is looked up directly.
+    fn blob_version(&self, path: &BlobPath) -> std::io::Result<Option<BlobVersioning>> {
+        let Some(pit) = self.options.point_in_time else {
+            return Ok(None);
+        };
+
+        if let Some(version) = self.versions.lock().unwrap().get(path) {
+            return Ok(version.clone());
+        }
+
+        let list = ListOptions {
+            prefix: Some(path.as_str().to_string()),
+            ..Default::default()
+        };
+
+        let items = self
+            .list(pit.include(list))?
+            .into_iter()
+            .filter_map(|i| match i {
+                BlobItem::Blob(b) if b.name == path.as_str() => Some(b),
+                _ => None,
+            });
+
+        // N.B: `select_versions` records its selection in `versions`.
+        if self.select_versions(items, pit).is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{path} did not exist at the mounted point in time"),
+            ));
+        }
+
+        Ok(self.versions.lock().unwrap().get(path).cloned().flatten())
+    }
+
+    /// Picks the snapshot or version of each blob to serve as of `pit`, and remembers it for
+    /// subsequent requests.
+    ///
+    /// With snapshots, blobs with no snapshot taken before then are dropped. With versions, blobs
+    /// created since then fall back to their current version.
+    fn select_versions(
+        &self,
+        blobs: impl IntoIterator<Item = Blob>,
+        pit: PointInTime,
+    ) -> Vec<Blob> {
+        let mut latest: HashMap<String, (OffsetDateTime, Option<BlobVersioning>, Blob)> =
+            HashMap::new();
+        let mut current = HashMap::new();
+
+        for b in blobs {
+            if b.is_current_version == Some(true) {
+                current.insert(b.name.clone(), b.clone());
+            }
+
+            let Some((created, version)) = pit.candidate(&b) else {
+                continue;
+            };
+
+            if created > pit.time() {
+                continue;
+            }
+
+            match latest.get(&b.name) {
+                Some((t, _, _)) if *t >= created => {}
+                _ => {
+                    latest.insert(b.name.clone(), (created, Some(version), b));
+                }
+            }
+        }
+
+        if let PointInTime::Version(_) = pit {
+            for (name, b) in current {
+                latest
+                    .entry(name)
+                    .or_insert((OffsetDateTime::UNIX_EPOCH, None, b));
+            }
+        }
+
+        let mut versions = self.versions.lock().unwrap();
+        latest
+            .into_values()
+            .map(|(_, version, b)| {
+                versions.put(BlobPath::new(&b.name), version);
+                b
+            })
+            .collect()
+    }
+
+    /// Collects every item from a blob listing.
+    fn list(&self, list: ListOptions) -> std::io::Result<Vec<BlobItem>> {
+        let stream = self.source.list(list);
+
+        let items = self.run(async {
+            let pages = stream.try_collect::<Vec<_>>().await?;
+            Ok(pages.into_iter().flatten().collect())
+        })?;
+
+        Ok(items)
+    }
+
+    /// Maps a path relative to the mount root onto the blob path it represents.
+    fn blob_path(&self, path: &Path) -> BlobPath {
+        self.options.prefix.join(&BlobPath::from(path))
+    }
+
+    /// Prints the tree under a directory as it would be projected, going at most `max_depth`
+    /// levels deep.
+    pub fn print_tree(
+        &self,
+        virt_path: &Path,
+        depth: usize,
+        max_depth: Option<usize>,
+    ) -> std::io::Result<()> {
+        let indent = "  ".repeat(depth);
+
+        for item in self.list_dir(virt_path, "")? {
+            let name = &item.name;
+
+            if item.is_dir {
+                println!("{indent}{name}/");
+                if max_depth.is_none_or(|max| depth + 1 < max) {
+                    self.print_tree(&virt_path.join(name), depth + 1, max_depth)?;
+                }
+            } else {
+                println!("{indent}{name} ({} bytes)", item.size);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the immediate children of a directory from Azure, optionally only those whose names
+    /// start with `name_prefix`.
+    fn list_dir(&self, virt_path: &Path, name_prefix: &str) -> std::io::Result<Vec<Entry>> {
+        let (mut lister, pages) = self.start_listing(virt_path, name_prefix)?;
+        let pages = self.run(pages.try_collect::<Vec<_>>())?;
+        lister.seen = None;
+
+        for item in pages.iter().flatten() {
+            self.list_item(&mut lister, item);
+        }
+
+        lister.finish();
+        Ok(lister.ready.into())
+    }
+
+    /// Starts listing a directory, returning a stream of the pages of the listing.
+    fn start_listing(
+        &self,
+        virt_path: &Path,
+        name_prefix: &str,
+    ) -> std::io::Result<(DirLister, ListingPages)> {
+        let path = self.blob_path(virt_path);
+        info!("iter: {path}");
+
+        // List with a delimiter so that Azure returns only the immediate children of this path,
+        // with deeper blobs rolled up into prefixes. The root of the container is listed without
+        // a prefix at all, and every blob name is relative to it.
+        let is_root = path.as_str().is_empty();
+        let list_prefix = if is_root {
+            String::new()
+        } else {
+            format!("{path}/")
+        };
+
+        let mut list = ListOptions {
+            delimiter: Some('/'),
+            include_metadata: self.hns,
+            include_deleted: self.options.include_deleted,
+            ..Default::default()
+        };
+        if !is_root || !name_prefix.is_empty() {
+            list.prefix = Some(format!("{list_prefix}{name_prefix}"));
+        }
+
+        let pages = if let Some(pit) = self.options.point_in_time {
+            // Picking versions takes every version of a blob into account, and they may be
+            // spread across pages, so the listing is collected up front.
+            let r = self.list(pit.include(list))?;
+            let (blobs, mut r): (Vec<_>, Vec<_>) =
+                r.into_iter().partition(|i| matches!(i, BlobItem::Blob(_)));
+
+            let blobs = blobs.into_iter().filter_map(|i| match i {
+                BlobItem::Blob(b) => Some(b),
+                BlobItem::BlobPrefix(_) => None,
+            });
+
+            // N.B: Folders are still listed even if none of the blobs in them existed back then.
+            r.extend(
+                self.select_versions(blobs, pit)
+                    .into_iter()
+                    .map(BlobItem::Blob),
+            );
+
+            // Restore the listing order that Azure returns items in.
+            r.sort_by(|a, b| item_name(a).cmp(item_name(b)));
+            futures::stream::iter([Ok(r)]).boxed()
+        } else {
+            self.source.list(list)
+        };
+
+        let mut virtual_files = Vec::new();
+        for concat in &self.options.concat {
+            if concat.name.parent() != Some(virt_path) {
+                continue;
+            }
+            match self.concat_file(concat) {
+                Ok((entry, _)) => virtual_files.push(entry),
+                Err(e) => warn!("failed to list the parts of {}: {e}", concat.name.display()),
+            }
+        }
+        virtual_files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let lister = DirLister {
+            virt_path: virt_path.to_path_buf(),
+            path,
+            list_prefix,
+            subdirs: HashSet::new(),
+            pending: VecDeque::new(),
+            ready: VecDeque::new(),
+            seen: Some(Vec::new()),
+            prefetch: Vec::new(),
+            virtual_files: virtual_files.into(),
+        };
+
+        Ok((lister, pages))
+    }
+
+    /// Fetches the next page of a listing into `lister`, handing back the rest of the pages, or
+    /// `None` once the listing is complete.
+    fn next_page(
+        &self,
+        lister: &mut DirLister,
+        mut pages: ListingPages,
+    ) -> std::io::Result<Option<ListingPages>> {
+        let page = self.run(async move {
+            let page = pages.try_next().await?;
+            Ok(page.map(|page| (page, pages)))
+        })?;
+
+        let Some((page, pages)) = page else {
+            lister.finish();
+
+            if let Some(items) = lister.seen.take() {
+                self.save_listing(&lister.path, &items);
+                self.listing_cache
+                    .insert(lister.path.clone(), Arc::new(items));
+            }
+
+            return Ok(None);
+        };
+
+        for item in &page {
+            self.list_item(lister, item);
+        }
+
+        self.prefetch_metadata(std::mem::take(&mut lister.prefetch));
+        Ok(Some(pages))
+    }
+
+    /// Fetches the properties of `blobs` concurrently, with up to `--max-concurrency` requests in
+    /// flight, and caches the entries they're projected with, so that applications that look up
+    /// every file in a folder as they enumerate it (e.g. Explorer's details view) find them
+    /// cached. Blobs whose properties can't be fetched keep the entries they were listed with.
+    fn prefetch_metadata(&self, blobs: Vec<(BlobPath, BlobPath)>) {
+        if blobs.is_empty() {
+            return;
+        }
+
+        let mut requests = Vec::with_capacity(blobs.len());
+        for (_, name) in &blobs {
+            match self.blob_version(name) {
+                Ok(version) => requests.push(self.source.properties(name.as_str(), version)),
+                Err(e) => {
+                    warn!("not prefetching the properties of {name}: {e}");
+                    return;
+                }
+            }
+        }
+
+        let concurrency = self
+            .options
+            .max_concurrency
+            .map_or(requests.len(), NonZeroUsize::get);
+        let fetched = self.run_parallel(requests.len(), async move {
+            Ok(futures::stream::iter(requests)
+                .buffered(concurrency)
+                .collect::<Vec<_>>()
+                .await)
+        });
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                warn!("failed to prefetch properties: {e}");
+                return;
+            }
+        };
+
+        for ((path, name), blob) in blobs.into_iter().zip(fetched) {
+            match blob {
+                Ok(blob) => {
+                    self.observe_etag(name.as_str(), &blob.properties.etag);
+                    self.observe_kind(name, &blob.properties);
+                    self.metadata_cache.insert(path, self.blob_info(&blob));
+                }
+                Err(e) => info!("failed to prefetch the properties of {name}: {e}"),
+            }
+        }
+    }
+
+    /// Adds an item from a listing to the entries of the directory being listed.
+    fn list_item(&self, lister: &mut DirLister, item: &BlobItem) {
+        // Listings come back in name order, so anything held back that would sort before a
+        // folder by this name can no longer collide with one.
+        if let Some(rel_name) = item_name(item).strip_prefix(lister.list_prefix.as_str()) {
+            lister.release(rel_name);
+        }
+
+        match item {
+            BlobItem::Blob(b) if b.deleted == Some(true) => self.list_deleted(lister, b),
+            BlobItem::Blob(b) => {
+                self.observe_etag(&b.name, &b.properties.etag);
+                self.observe_kind(BlobPath::new(b.name.clone()), &b.properties);
+
+                // Names are taken apart as strings rather than as paths, so that characters
+                // that are significant in a path (e.g. `\`) can't change how a name is split.
+                // The SDK hands names back verbatim, so `%`, `+` and the like need no decoding.
+                let Some(rel_name) = b.name.strip_prefix(lister.list_prefix.as_str()) else {
+                    warn!("ignoring unexpected blob {} under {}", b.name, lister.path);
+                    return;
+                };
+
+                // Tools such as Storage Explorer mark empty folders with a zero-length blob named
+                // after the folder (e.g. `folder/`), which is the folder being listed itself.
+                if rel_name.is_empty() {
+                    return;
+                }
+
+                // Determine which files are located in "subdirectories" from the search path,
+                // and hide them behind folder entries.
+                if let Some((dir, _)) = rel_name.split_once('/') {
+                    if is_projectable(dir) {
+                        self.add_subdir(lister, dir);
+                    } else {
+                        warn!("ignoring blob {} with unrepresentable folder name", b.name);
+                    }
+
+                    return;
+                }
+
+                if !is_projectable(rel_name) {
+                    warn!("ignoring blob {} with unrepresentable name", b.name);
+                    return;
+                }
+
+                // Directories are real in a hierarchical namespace, and may well be empty.
+                if self.hns && is_dir_marker(b) {
+                    self.add_subdir(lister, rel_name);
+                    return;
+                }
+
+                if self
+                    .is_excluded(&BlobPath::from(&lister.virt_path).join(&BlobPath::new(rel_name)))
+                    || !self.options.modified.contains(b.properties.last_modified)
+                    || self.is_blocked(rel_name)
+                {
+                    return;
+                }
+
+                let info = self.blob_info(b);
+                if self.is_blocked(&info.name) {
+                    return;
+                }
+
+                info!("-> {rel_name}");
+
+                let path = BlobPath::new(format!("{}{}", lister.list_prefix, info.name));
+
+                // A blob named with an inferred extension (e.g. `image` as `image.png`) sorts
+                // before a blob that has that name already, and is still held back when it's
+                // listed. The blob whose name it is wins, and the other is hidden.
+                if let Some(i) = lister
+                    .pending
+                    .iter()
+                    .position(|e| !e.is_dir && e.name == info.name)
+                {
+                    warn!(
+                        "hiding a blob listed as {path} with an inferred extension, which \
+                         collides with the blob of that name"
+                    );
+                    lister.pending.remove(i);
+                    lister.prefetch.retain(|(p, _)| *p != path);
+                    self.canonical_names.lock().unwrap().pop(&path);
+                }
+
+                // Alright, we should only get here if this is a file in the current directory.
+                lister.pending.push_back(info.clone());
+
+                if info.name != rel_name {
+                    let mut names = self.canonical_names.lock().unwrap();
+                    names.put(path.clone(), BlobPath::new(b.name.clone()));
+                }
+                if self.options.prefetch_metadata {
+                    lister
+                        .prefetch
+                        .push((path.clone(), BlobPath::new(b.name.clone())));
+                }
+                self.metadata_cache.insert(path, info);
+
+                // Blobs in containers with versioning enabled are listed with the ID of their
+                // current version, and only those get a folder of versions.
+                // N.B: A real folder of the same name is hidden behind it.
+                if self.options.versions_as_subdir && b.version_id.is_some() {
+                    let dir = format!("{rel_name}{VERSIONS_SUFFIX}");
+                    if lister.subdirs.insert(dir.clone()) {
+                        info!("-> folder: {dir} (versions)");
+                        self.known_dirs
+                            .lock()
+                            .unwrap()
+                            .put(lister.virt_path.join(&dir), ());
+                        lister.pending.push_back(Entry::dir(dir));
+                    }
+                }
+            }
+            BlobItem::BlobPrefix(p) => {
+                // Prefixes are the listing prefix, followed by a single folder name and a
+                // trailing delimiter (e.g. `path/dir/`).
+                let dir = p
+                    .name
+                    .strip_prefix(lister.list_prefix.as_str())
+                    .and_then(|d| d.strip_suffix('/'))
+                    .filter(|d| !d.contains('/') && is_projectable(d));
+
+                if let Some(dir) = dir {
+                    self.add_subdir(lister, dir);
+                } else {
+                    warn!(
+                        "ignoring unexpected prefix {} under {}",
+                        p.name, lister.path
+                    );
+                }
+            }
+        }
+    }
+
+    /// With `include_deleted`, adds a soft-deleted blob to the directory being listed, as
+    /// `<name>.deleted`.
+    ///
+    /// N.B: The entry is listed where the blob's own name sorts, which may be out of order with
+    /// siblings that sort between the two (e.g. `name-2`).
+    fn list_deleted(&self, lister: &mut DirLister, b: &Blob) {
+        if !self.options.include_deleted {
+            return;
+        }
+
+        let Some(rel_name) = b.name.strip_prefix(lister.list_prefix.as_str()) else {
+            return;
+        };
+
+        // Folders are listed even if all that's left in them is deleted.
+        if let Some((dir, _)) = rel_name.split_once('/') {
+            if is_projectable(dir) {
+                self.add_subdir(lister, dir);
+            }
+
+            return;
+        }
+
+        let name = format!("{rel_name}{DELETED_SUFFIX}");
+        if rel_name.is_empty() || !is_projectable(&name) {
+            return;
+        }
+
+        let virt_path = BlobPath::from(&lister.virt_path).join(&BlobPath::new(name.as_str()));
+        if self.is_excluded(&virt_path) {
+            return;
+        }
+
+        // Everything about the deleted blob is kept under the path it's projected at, apart from
+        // the blob of the same name that may have replaced it.
+        let path = BlobPath::new(format!("{}{DELETED_SUFFIX}", b.name));
+        self.observe_etag(path.as_str(), &b.properties.etag);
+        self.observe_kind(path.clone(), &b.properties);
+        self.deleted
+            .lock()
+            .unwrap()
+            .put(path.clone(), b.version_id.clone());
+
+        info!("-> {name} (deleted)");
+
+        let info = Entry {
+            name,
+            ..self.blob_info(b)
+        };
+        lister.pending.push_back(info.clone());
+        self.metadata_cache.insert(path, info);
+    }
+
+    /// Emits a directory entry for `dir` under the directory being listed, unless one was
+    /// already emitted.
+    fn add_subdir(&self, lister: &mut DirLister, dir: &str) {
+        if lister.subdirs.insert(dir.to_string()) {
+            if self.is_too_deep(&lister.virt_path.join(dir))
+                || self.is_excluded_dir(&lister.virt_path, dir)
+            {
+                return;
+            }
+
+            info!("-> folder: {}", dir);
+
+            // HACK: Track "known" directories.
+            let mut dirs = self.known_dirs.lock().unwrap();
+            dirs.put(lister.virt_path.join(dir), ());
+
+            // A blob can share its name with a folder (e.g. `foo` and `foo/bar`), which a file
+            // system can't represent. The folder wins, since it may hold any number of blobs, and
+            // the blob is hidden.
+            lister.pending.retain(|i| {
+                let hidden = !i.is_dir && i.name == dir;
+                if hidden {
+                    warn!(
+                        "hiding blob {}{dir}, which collides with a folder of the same name",
+                        lister.list_prefix
+                    );
+                }
+
+                !hidden
+            });
+
+            lister.pending.push_back(Entry::dir(dir))
+        }
+    }
+
+    /// Determines if a folder, relative to the virtual root, is nested deeper than `max_depth`
+    /// allows. Folders directly in the root are at depth 1.
+    fn is_too_deep(&self, virt_path: &Path) -> bool {
+        self.options
+            .max_depth
+            .is_some_and(|max| virt_path.components().count() > max)
+    }
+
+    /// Determines if a file's name ends with one of the blocked extensions, ignoring case.
+    fn is_blocked(&self, name: &str) -> bool {
+        if self.options.blocked_extensions.is_empty() {
+            return false;
+        }
+
+        let leaf = name
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(name)
+            .to_lowercase();
+        self.options
+            .blocked_extensions
+            .iter()
+            .any(|ext| leaf.len() > ext.len() && leaf.ends_with(ext.as_str()))
+    }
+
+    /// Determines if a path, relative to the virtual root, matches an exclusion pattern.
+    fn is_excluded(&self, path: &BlobPath) -> bool {
+        !self.options.exclude.is_empty() && self.options.exclude.is_match(path.as_str())
+    }
+
+    /// Determines if a folder should be hidden, either because it is excluded outright, or
+    /// because everything in it is (or was modified outside the mounted window).
+    fn is_excluded_dir(&self, virt_path: &Path, dir: &str) -> bool {
+        if self.options.exclude.is_empty() && self.options.modified.is_unbounded() {
+            return false;
+        }
+
+        let dir = BlobPath::from(virt_path).join(&BlobPath::new(dir));
+
+        // Patterns such as `logs/**` exclude the entire folder, whatever is in it.
+        let covered = ["\0", "\0/\0"]
+            .iter()
+            .all(|probe| self.is_excluded(&dir.join(&BlobPath::new(*probe))));
+
+        if self.is_excluded(&dir) || covered {
+            return true;
+        }
+
+        // Otherwise, look through the folder for something that isn't excluded. Listing without a
+        // delimiter enumerates everything under it, one page at a time.
+        let prefix = format!("{}/", self.options.prefix.join(&dir));
+        let mut pages = self.source.list(ListOptions {
+            prefix: Some(prefix),
+            ..Default::default()
+        });
+
+        let root = match self.options.prefix.as_str() {
+            "" => String::new(),
+            p => format!("{p}/"),
+        };
+        let exclude = self.options.exclude.clone();
+        let modified = self.options.modified;
+
+        let r = self.run(async move {
+            while let Some(page) = pages.try_next().await? {
+                let visible = page.iter().any(|i| {
+                    let name = item_name(i);
+                    let rel = name.strip_prefix(&root).unwrap_or(name);
+                    let in_window = match i {
+                        BlobItem::Blob(b) => modified.contains(b.properties.last_modified),
+                        BlobItem::BlobPrefix(_) => true,
+                    };
+
+                    !exclude.is_match(rel) && in_window
+                });
+
+                if visible {
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        });
+
+        match r {
+            Ok(visible) => !visible,
+            Err(e) => {
+                warn!("failed to check {dir} for non-excluded blobs: {e}");
+                false
+            }
+        }
+    }
+
+    /// Adds data handed to the file system to the blob's running checksum, if verification is enabled.
+    fn verify(&self, path: &BlobPath, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let Some(verifier) = &self.verifier else {
+            return Ok(());
+        };
+
+        let start = if verifier.starts(path.as_str(), offset) {
+            let blob = self.properties(path)?;
+            match blob.properties.content_md5 {
+                Some(md5) => Some((blob.properties.content_length, md5)),
+                // Nothing to check against, e.g. for blobs uploaded in blocks without one.
+                None => return Ok(()),
+            }
+        } else {
+            None
+        };
+
+        verifier
+            .update(path.as_str(), offset, data, start)
+            .inspect_err(|e| error!("{e}"))
+    }
+
+    /// Determines if a path that isn't known to be a folder is one, i.e. if there are any
+    /// (non-excluded) blobs under it.
+    fn is_folder(&self, virt_path: &Path) -> std::io::Result<bool> {
+        let (Some(parent), Some(name)) = (virt_path.parent(), virt_path.file_name()) else {
+            return Ok(false);
+        };
+
+        let mut pages = self.source.list(ListOptions {
+            prefix: Some(format!("{}/", self.blob_path(virt_path))),
+            max_results: Some(NonZeroU32::MIN),
+            ..Default::default()
+        });
+
+        let found = self.run(async move {
+            let page = pages.try_next().await?;
+            Ok(page.is_some_and(|p| !p.is_empty()))
+        })?;
+
+        Ok(found && !self.is_excluded_dir(parent, &name.to_string_lossy()))
+    }
+
+    /// Describes a blob as a file, named after the last component of the blob's name, as it
+    /// would be listed in its directory.
+    ///
+    /// With `infer_extensions`, blobs without an extension are named with one that matches their
+    /// content type, if it's a well-known one. Listings hide such a blob if another already has
+    /// that name (e.g. `image` next to `image.png`).
+    fn blob_info(&self, blob: &Blob) -> Entry {
+        let leaf = blob.name.rsplit('/').next().unwrap_or(&blob.name);
+        let ext = inferred_extension(&blob.properties.content_type)
+            .filter(|_| self.options.infer_extensions && !leaf.contains('.'));
+
+        Entry {
+            name: match ext {
+                Some(ext) => format!("{leaf}.{ext}"),
+                None => leaf.to_string(),
+            },
+            is_dir: false,
+            size: blob.properties.content_length,
+            attrs: self.file_attributes(&blob.properties),
+            etag: Some(blob.properties.etag.clone()),
+            created: Some(blob.properties.creation_time.into()),
+            modified: Some(blob.properties.last_modified.into()),
+            fetched: SystemTime::now(),
+        }
+    }
+
+    /// Determines the attributes to project a blob with.
+    ///
+    /// Without ProjFS notifications, there is no way to veto modifications to a placeholder, so
+    /// read-only mounts rely on the read-only attribute: Windows refuses to open such files for
+    /// writing, or to delete them. FUSE mounts are always read-only.
+    fn file_attributes(&self, props: &BlobProperties) -> u32 {
+        let attrs = blob_attributes(props);
+        if self.options.read_only {
+            attrs | FILE_ATTRIBUTE_READONLY
+        } else {
+            attrs
+        }
+    }
+
+    /// Classifies an Azure error, with a hint at what went wrong.
+    fn storage_error(&self, e: azure_core::Error) -> RazmountError {
+        let e = if self.options.anonymous && requires_auth(&e) {
+            e.context("the container does not allow anonymous access; specify credentials")
+        } else {
+            e.context("failed to query blob storage")
+        };
+
+        e.into()
+    }
+}
+
+/// Lists every blob under a prefix, along with its ETag, for `follow`. Each page takes a permit,
+/// if requests are limited.
+async fn snapshot<S: BlobSource>(
+    source: &S,
+    list: ListOptions,
+    permits: Option<&Arc<Semaphore>>,
+) -> azure_core::Result<follow::Snapshot> {
+    let mut pages = source.list(list);
+    let mut snapshot = follow::Snapshot::new();
+
+    loop {
+        // N.B: The semaphore is never closed, so acquiring a permit can't fail.
+        let _permit = match permits {
+            Some(permits) => permits.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+
+        let Some(page) = pages.try_next().await? else {
+            return Ok(snapshot);
+        };
+
+        for item in page {
+            if let BlobItem::Blob(b) = item {
+                snapshot.insert(b.name, b.properties.etag);
+            }
+        }
+    }
+}
+
+/// The error for files with a blocked extension, which are hidden as if they didn't exist.
+fn blocked(virt_path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{} has a blocked extension", virt_path.display()),
+    )
+}
+
+/// The error for anything that would need a request to Azure while offline.
+const NOT_CACHED: &str =
+    "offline, and not in the cache: only files and folders seen before can be opened";
+
+fn not_cached() -> std::io::Error {
+    RazmountError::NotFound(NOT_CACHED.into()).into()
+}
+
+/// Parses the time a snapshot was taken from its (nominally opaque) identifier, which Azure
+/// formats as an RFC 3339 timestamp.
+fn snapshot_time(snapshot: &Snapshot) -> Option<OffsetDateTime> {
+    // `Snapshot` doesn't expose its contents other than through serialization.
+    let s = serde_json::to_value(snapshot).ok()?;
+    azure_core::date::parse_rfc3339(s.as_str()?).ok()
+}
+
+/// The name of a listed blob or prefix.
+fn item_name(item: &BlobItem) -> &str {
+    match item {
+        BlobItem::Blob(b) => &b.name,
+        BlobItem::BlobPrefix(p) => &p.name,
+    }
+}
+
+/// A blob to download from, as of some version and ETag.
+struct Download<S: BlobSource> {
+    source: Arc<S>,
+    name: String,
+    version: Option<BlobVersioning>,
+    if_match: Option<Etag>,
+    /// Limits how fast ranges are downloaded, if `--max-bandwidth` is set.
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S: BlobSource> Download<S> {
+    fn get_range(
+        &self,
+        offset: u64,
+        len: usize,
+    ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>> {
+        let request = self.source.get_range(
+            &self.name,
+            self.version.clone(),
+            self.if_match.clone(),
+            offset,
+            len,
+        );
+
+        let Some(limiter) = self.limiter.clone() else {
+            return request;
+        };
+        async move {
+            limiter.acquire(len as u64).await;
+            request.await
+        }
+        .boxed()
+    }
+
+    fn page_ranges(&self) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>> {
+        self.source
+            .page_ranges(&self.name, self.version.clone(), self.if_match.clone())
+    }
+}
+
+/// The number of blobs to look through for directories when detecting a hierarchical namespace.
+const HNS_PROBE_BLOBS: u32 = 1000;
+
+/// The number of blobs to add up the sizes of, at most, for the size of a folder.
+const DIR_SIZE_MAX_BLOBS: u64 = 100_000;
+
+/// Reads smaller than this are always fetched with a single request.
+const PARALLEL_READ_THRESHOLD: usize = 4 * 1024 * 1024;
+
+/// Downloads `len` bytes of a blob starting at `offset`, splitting large reads into up to `parts`
+/// concurrent range requests.
+///
+/// As with `BlobSource::get_range`, the result is shorter than requested if the range extends past the end
+/// of the blob. A failure of any part fails the whole download.
+///
+/// If `consistent` is set, every part must come from the same ETag. Append blobs get a new ETag
+/// with each block appended to them, but committed blocks never change, so parts of an append
+/// blob can safely be read across appends.
+async fn get_ranges<S: BlobSource>(
+    download: Download<S>,
+    offset: u64,
+    len: usize,
+    parts: usize,
+    consistent: bool,
+) -> azure_core::Result<(Option<Etag>, Vec<u8>)> {
+    if parts <= 1 || len < PARALLEL_READ_THRESHOLD {
+        return download.get_range(offset, len).await;
+    }
+
+    let part_len = len.div_ceil(parts);
+    let requests = (0..len).step_by(part_len).map(|start| {
+        let end = std::cmp::min(start + part_len, len);
+        download.get_range(offset + start as u64, end - start)
+    });
+
+    // N.B: `try_join_all` returns the results in the order the requests were made.
+    let results = futures::future::try_join_all(requests).await?;
+
+    let mut etag: Option<Etag> = None;
+    let mut buf = Vec::with_capacity(len);
+
+    for (part_etag, data) in results {
+        if let (Some(etag), Some(part_etag)) = (&etag, &part_etag) {
+            if consistent && etag != part_etag {
+                return Err(azure_core::Error::message(
+                    azure_core::error::ErrorKind::Other,
+                    "blob was modified while it was being read",
+                ));
+            }
+        }
+
+        etag = etag.or(part_etag);
+
+        let short = data.len() < part_len;
+        buf.extend_from_slice(&data);
+
+        // Anything after a short part lies past the end of the blob.
+        if short {
+            break;
+        }
+    }
+
+    Ok((etag, buf))
+}
+
+/// Downloads `len` bytes of a page blob that is `blob_len` bytes long, starting at `offset`.
+///
+/// Page blobs are sparse, so only the pages that have been written to are downloaded, up to
+/// `parts` at a time, and the rest of the range is filled with zeros. The result is shorter than
+/// requested if the range extends past the end of the blob.
+async fn get_sparse_range<S: BlobSource>(
+    download: Download<S>,
+    offset: u64,
+    len: usize,
+    blob_len: u64,
+    parts: usize,
+) -> azure_core::Result<(Option<Etag>, Vec<u8>)> {
+    let end = std::cmp::min(offset + len as u64, blob_len);
+    if end <= offset {
+        return Ok((None, Vec::new()));
+    }
+
+    let (etag, pages) = download.page_ranges().await?;
+
+    let requests = pages.into_iter().filter_map(|p| {
+        let start = std::cmp::max(p.start, offset);
+        let stop = std::cmp::min(p.end, end);
+
+        (start < stop).then(|| {
+            download
+                .get_range(start, (stop - start) as usize)
+                .map_ok(move |r| (start, r))
+        })
+    });
+
+    let results = futures::stream::iter(requests)
+        .buffer_unordered(parts)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut buf = vec![0u8; (end - offset) as usize];
+
+    for (start, (part_etag, data)) in results {
+        if part_etag.is_some_and(|e| e != etag) {
+            return Err(azure_core::Error::message(
+                azure_core::error::ErrorKind::Other,
+                "blob was modified while it was being read",
+            ));
+        }
+
+        let at = (start - offset) as usize;
+        buf[at..at + data.len()].copy_from_slice(&data);
+    }
+
+    Ok((Some(etag), buf))
+}
+
+/// Well-known content types, and the extensions that files of each type usually have.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("application/gzip", "gz"),
+    ("application/json", "json"),
+    ("application/pdf", "pdf"),
+    ("application/xml", "xml"),
+    ("application/zip", "zip"),
+    ("audio/mpeg", "mp3"),
+    ("audio/wav", "wav"),
+    ("image/gif", "gif"),
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/svg+xml", "svg"),
+    ("image/tiff", "tif"),
+    ("image/webp", "webp"),
+    ("text/csv", "csv"),
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    ("text/xml", "xml"),
+    ("video/mp4", "mp4"),
+];
+
+/// The extension to give a blob of the given content type (ignoring parameters such as
+/// `charset`), if it's a well-known one.
+fn inferred_extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+
+    EXTENSIONS
+        .iter()
+        .find(|(t, _)| t.eq_ignore_ascii_case(mime))
+        .map(|(_, ext)| *ext)
+}
+
+/// The state of a blob's lease, if another client holds one. A lease that is being broken is held
+/// until its break period ends.
+fn active_lease(state: Option<LeaseState>) -> Option<LeaseState> {
+    state.filter(|s| matches!(s, LeaseState::Leased | LeaseState::Breaking))
+}
+
+pub const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+
+/// Maps blob properties onto Windows file attributes.
+///
+/// Leased blobs are marked read-only, since another client holds the right to write them,
+/// and archived blobs are marked offline, since they cannot be read without rehydration.
+/// The SDK does not currently expose immutability policies or legal holds.
+fn blob_attributes(props: &BlobProperties) -> u32 {
+    let mut attrs = 0;
+
+    if props.lease_state == Some(LeaseState::Leased) {
+        attrs |= FILE_ATTRIBUTE_READONLY;
+    }
+
+    if props.access_tier == Some(AccessTier::Archive) {
+        attrs |= FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_READONLY;
+    }
+
+    attrs
+}
+
+/// Determines if a blob represents a directory, as blobs do in accounts with a hierarchical
+/// namespace.
+fn is_dir_marker(blob: &Blob) -> bool {
+    blob.metadata
+        .as_ref()
+        .and_then(|m| m.get("hdi_isfolder"))
+        .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+/// Determines if a request failed in a way that suggests Azure is unavailable, as opposed to
+/// refusing that particular request (e.g. because the blob doesn't exist).
+fn is_outage(e: &RazmountError) -> bool {
+    match e {
+        RazmountError::Network(_) | RazmountError::Throttled(_) => true,
+        RazmountError::Storage(_) => matches!(
+            e.azure().map(azure_core::Error::kind),
+            Some(azure_core::error::ErrorKind::HttpResponse { status, .. })
+                if u16::from(*status) >= 500
+        ),
+        _ => false,
+    }
+}
+
+/// Determines if a conditional request failed because the blob no longer has the ETag it was
+/// made on.
+fn changed(e: &std::io::Error) -> bool {
+    let e = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<RazmountError>())
+        .and_then(RazmountError::azure);
+
+    matches!(
+        e.map(azure_core::Error::kind),
+        Some(azure_core::error::ErrorKind::HttpResponse {
+            status: StatusCode::PreconditionFailed,
+            ..
+        })
+    )
+}
+
+/// Determines if an error indicates the request was rejected for lack of credentials.
+fn requires_auth(e: &azure_core::Error) -> bool {
+    match e.kind() {
+        azure_core::error::ErrorKind::HttpResponse { status, error_code } => match *status {
+            StatusCode::Unauthorized | StatusCode::Forbidden => true,
+            // Private containers present themselves as nonexistent to anonymous clients.
+            StatusCode::NotFound => error_code.as_deref() == Some("ResourceNotFound"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        ops::Range,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    use azure_storage_blobs::container::operations::BlobPrefix;
+    use futures::{future::BoxFuture, stream::BoxStream, FutureExt, StreamExt};
+
+    use super::*;
+
+    /// A container with a fixed set of blobs, served from memory.
+    struct FakeSource {
+        blobs: BTreeMap<String, Vec<u8>>,
+        /// The number of items per page of a listing.
+        page_size: usize,
+        /// Blobs in the archive tier.
+        archived: HashSet<String>,
+        /// The blobs that were moved to another tier.
+        tier_changes: Mutex<Vec<String>>,
+        /// Blobs whose downloads never complete.
+        stalled: HashSet<String>,
+        /// Blobs that have been overwritten, and so have a new ETag.
+        modified: Mutex<HashSet<String>>,
+        /// The ranges that have been downloaded.
+        downloads: Mutex<Vec<Range<u64>>>,
+        /// Blobs that represent directories, as in a hierarchical namespace.
+        folders: HashSet<String>,
+        /// Whether requests fail as if the network were down.
+        unreachable: AtomicBool,
+        /// Blobs that have been soft-deleted.
+        deleted: HashSet<String>,
+        /// Blobs that haven't been uploaded yet, and so don't exist.
+        pending: Mutex<HashSet<String>>,
+        /// The contents of the previous version of blobs in a container with versioning enabled.
+        versions: HashMap<String, Vec<u8>>,
+        /// When blobs were last modified, if not at the start of 2023.
+        modified_at: HashMap<String, &'static str>,
+        /// The content types of blobs, if not `application/octet-stream`.
+        content_types: HashMap<String, &'static str>,
+        /// Blobs that another client holds a lease on.
+        leased: HashSet<String>,
+        /// The number of listing pages that have been served.
+        pages_served: Arc<AtomicUsize>,
+        /// Page blobs, with the ranges of them that have been written to.
+        page_blobs: HashMap<String, Vec<Range<u64>>>,
+        /// Append blobs that are appended to between any two requests, and so have a new ETag
+        /// with each download.
+        growing: HashSet<String>,
+    }
+
+    impl FakeSource {
+        fn new(names: &[&str]) -> Self {
+            let blobs = names
+                .iter()
+                .map(|name| (name.to_string(), name.as_bytes().to_vec()))
+                .collect();
+
+            Self {
+                blobs,
+                page_size: 5000,
+                archived: HashSet::new(),
+                tier_changes: Mutex::new(Vec::new()),
+                stalled: HashSet::new(),
+                modified: Mutex::new(HashSet::new()),
+                downloads: Mutex::new(Vec::new()),
+                folders: HashSet::new(),
+                unreachable: AtomicBool::new(false),
+                deleted: HashSet::new(),
+                pending: Mutex::new(HashSet::new()),
+                versions: HashMap::new(),
+                modified_at: HashMap::new(),
+                content_types: HashMap::new(),
+                leased: HashSet::new(),
+                pages_served: Arc::new(AtomicUsize::new(0)),
+                page_blobs: HashMap::new(),
+                growing: HashSet::new(),
+            }
+        }
+
+        fn unreachable(&self) -> bool {
+            self.unreachable.load(Ordering::Relaxed)
+        }
+
+        fn etag(&self, name: &str) -> Etag {
+            if self.modified.lock().unwrap().contains(name) {
+                Etag::from("0x2")
+            } else {
+                Etag::from("0x1")
+            }
+        }
+
+        fn blob(&self, name: &str, metadata: bool) -> Option<Blob> {
+            if self.pending.lock().unwrap().contains(name) {
+                return None;
+            }
+
+            let data = self.blobs.get(name)?;
+            let tier = if self.archived.contains(name) {
+                "Archive"
+            } else {
+                "Hot"
+            };
+            let blob_type = if self.page_blobs.contains_key(name) {
+                "PageBlob"
+            } else if self.growing.contains(name) {
+                "AppendBlob"
+            } else {
+                "BlockBlob"
+            };
+
+            let blob = serde_json::json!({
+                "Name": name,
+                "Properties": {
+                    "Creation-Time": "Sun, 01 Jan 2023 00:00:00 GMT",
+                    "Last-Modified": self
+                        .modified_at
+                        .get(name)
+                        .copied()
+                        .unwrap_or("Sun, 01 Jan 2023 00:00:00 GMT"),
+                    "Etag": self.etag(name),
+                    "Content-Length": data.len(),
+                    "Content-Type": self
+                        .content_types
+                        .get(name)
+                        .copied()
+                        .unwrap_or("application/octet-stream"),
+                    "BlobType": blob_type,
+                    "AccessTier": tier,
+                },
+            });
+
+            let mut blob = blob;
+            if metadata && self.folders.contains(name) {
+                blob["Metadata"] = serde_json::json!({ "hdi_isfolder": "true" });
+            }
+            if self.deleted.contains(name) {
+                blob["Deleted"] = true.into();
+                blob["VersionId"] = "2023-01-01T00:00:00.0000000Z".into();
+            }
+            if self.versions.contains_key(name) {
+                blob["VersionId"] = "2023-06-01T00:00:00.0000000Z".into();
+                blob["IsCurrentVersion"] = true.into();
+            }
+            if self.leased.contains(name) {
+                blob["Properties"]["LeaseStatus"] = "locked".into();
+                blob["Properties"]["LeaseState"] = "leased".into();
+            }
+
+            Some(serde_json::from_value(blob).unwrap())
+        }
+
+        /// Describes the previous version of a blob.
+        fn old_version(&self, name: &str) -> Option<Blob> {
+            let data = self.versions.get(name)?;
+            let mut blob: Blob = self.blob(name, false)?;
+            blob.version_id = Some("2023-01-01T00:00:00.0000000Z".into());
+            blob.is_current_version = None;
+            blob.properties.content_length = data.len() as u64;
+            Some(blob)
+        }
+    }
+
+    fn not_found() -> azure_core::Error {
+        azure_core::Error::message(
+            azure_core::error::ErrorKind::HttpResponse {
+                status: StatusCode::NotFound,
+                error_code: Some("BlobNotFound".into()),
+            },
+            "the specified blob does not exist",
+        )
+    }
+
+    fn connection_refused() -> azure_core::Error {
+        azure_core::Error::message(azure_core::error::ErrorKind::Io, "connection refused")
+    }
+
+    fn condition_not_met() -> azure_core::Error {
+        azure_core::Error::message(
+            azure_core::error::ErrorKind::HttpResponse {
+                status: StatusCode::PreconditionFailed,
+                error_code: Some("ConditionNotMet".into()),
+            },
+            "the condition specified using HTTP conditional header(s) is not met",
+        )
+    }
+
+    impl BlobSource for FakeSource {
+        fn container_name(&self) -> &str {
+            "fake"
+        }
+
+        fn list(
+            &self,
+            options: ListOptions,
+        ) -> BoxStream<'static, azure_core::Result<Vec<BlobItem>>> {
+            if self.unreachable() {
+                return futures::stream::iter([Err(connection_refused())]).boxed();
+            }
+
+            let prefix = options.prefix.unwrap_or_default();
+            let mut items = Vec::new();
+
+            let pending = self.pending.lock().unwrap().clone();
+            let names = self.blobs.keys().filter(|n| {
+                n.starts_with(&prefix)
+                    && (options.include_deleted || !self.deleted.contains(*n))
+                    && !pending.contains(*n)
+            });
+
+            for name in names {
+                let rest = &name[prefix.len()..];
+
+                match options
+                    .delimiter
+                    .and_then(|d| Some((rest.split_once(d)?, d)))
+                {
+                    Some(((dir, _), d)) => {
+                        let dir = format!("{prefix}{dir}{d}");
+                        if !items.iter().any(|i| item_name(i) == dir) {
+                            items.push(BlobItem::BlobPrefix(BlobPrefix { name: dir }));
+                        }
+                    }
+                    _ => {
+                        if let Some(old) =
+                            self.old_version(name).filter(|_| options.include_versions)
+                        {
+                            items.push(BlobItem::Blob(old));
+                        }
+
+                        items.push(BlobItem::Blob(
+                            self.blob(name, options.include_metadata).unwrap(),
+                        ))
+                    }
+                }
+            }
+
+            let pages = items
+                .chunks(self.page_size)
+                .map(|page| Ok(page.to_vec()))
+                .collect::<Vec<_>>();
+
+            let served = self.pages_served.clone();
+            futures::stream::iter(pages)
+                .inspect(move |_| {
+                    served.fetch_add(1, Ordering::Relaxed);
+                })
+                .boxed()
+        }
+
+        fn properties(
+            &self,
+            name: &str,
+            _version: Option<BlobVersioning>,
+        ) -> BoxFuture<'static, azure_core::Result<Blob>> {
+            if self.unreachable() {
+                return async { Err(connection_refused()) }.boxed();
+            }
+
+            let blob = self.blob(name, true).ok_or_else(not_found);
+            async { blob }.boxed()
+        }
+
+        fn get_range(
+            &self,
+            name: &str,
+            version: Option<BlobVersioning>,
+            if_match: Option<Etag>,
+            offset: u64,
+            len: usize,
+        ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>> {
+            if self.stalled.contains(name) {
+                return futures::future::pending().boxed();
+            }
+            if self.unreachable() {
+                return async { Err(connection_refused()) }.boxed();
+            }
+
+            let mut downloads = self.downloads.lock().unwrap();
+            downloads.push(offset..offset + len as u64);
+
+            let etag = if self.growing.contains(name) {
+                Etag::from(format!("0x{}", downloads.len() + 1))
+            } else {
+                self.etag(name)
+            };
+            drop(downloads);
+            if if_match.is_some_and(|e| e != etag) {
+                return async { Err(condition_not_met()) }.boxed();
+            }
+
+            let blob = match version {
+                Some(_) => self.versions.get(name).or(self.blobs.get(name)),
+                None => self.blobs.get(name),
+            };
+            let data = blob.map(|data| {
+                let start = std::cmp::min(offset as usize, data.len());
+                let end = std::cmp::min(start + len, data.len());
+                (Some(etag), data[start..end].to_vec())
+            });
+
+            async { data.ok_or_else(not_found) }.boxed()
+        }
+
+        fn page_ranges(
+            &self,
+            name: &str,
+            _version: Option<BlobVersioning>,
+            _if_match: Option<Etag>,
+        ) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>> {
+            let pages = self.page_blobs.get(name).cloned().ok_or_else(not_found);
+            let etag = self.etag(name);
+            async move { Ok((etag, pages?)) }.boxed()
+        }
+
+        fn set_tier(
+            &self,
+            name: &str,
+            _version: Option<BlobVersioning>,
+            _tier: AccessTier,
+        ) -> BoxFuture<'static, azure_core::Result<()>> {
+            self.tier_changes.lock().unwrap().push(name.to_string());
+            async { Ok(()) }.boxed()
+        }
+    }
+
+    fn options() -> DriverOptions {
+        DriverOptions {
+            exclude: GlobSet::empty(),
+            modified: ModifiedWindow::default(),
+            blocked_extensions: Vec::new(),
+            max_depth: None,
+            anonymous: false,
+            prefix: BlobPath::new(""),
+            dir_cache_size: NonZeroUsize::new(64).unwrap(),
+            listing_ttl: Duration::from_secs(30),
+            metadata_ttl: Duration::from_secs(30),
+            cache_dir: None,
+            cache_max_bytes: 0,
+            small_blob_threshold: 0,
+            small_blob_cache_bytes: 0,
+            readahead_bytes: 0,
+            progress_interval: None,
+            block_size: 0,
+            blob_as_dir: None,
+            chunk_size: NonZeroU64::MIN,
+            concat: Vec::new(),
+            coalesce_window: Duration::ZERO,
+            coalesce_max_bytes: 0,
+            parallel_ranges: NonZeroUsize::MIN,
+            point_in_time: None,
+            case_sensitive: false,
+            read_only: true,
+            verify_checksums: false,
+            max_concurrency: None,
+            max_bandwidth: None,
+            auto_rehydrate: None,
+            request_timeout: None,
+            pin_etags: false,
+            hns: None,
+            delimiter: '/',
+            offline: false,
+            offline_after: None,
+            breaker_threshold: None,
+            breaker_cooldown: Duration::from_secs(30),
+            include_deleted: false,
+            versions_as_subdir: false,
+            infer_extensions: false,
+            prefetch_metadata: false,
+            compute_dir_sizes: false,
+            lease_policy: LeasePolicy::Ignore,
+            name: None,
+            follow_interval: None,
+            tag_filter: None,
+        }
+    }
+
+    fn backend(rt: &tokio::runtime::Runtime, names: &[&str]) -> Arc<BlobBackend<FakeSource>> {
+        paged_backend(rt, names, 5000)
+    }
+
+    fn paged_backend(
+        rt: &tokio::runtime::Runtime,
+        names: &[&str],
+        page_size: usize,
+    ) -> Arc<BlobBackend<FakeSource>> {
+        let source = FakeSource {
+            page_size,
+            ..FakeSource::new(names)
+        };
+
+        Arc::new(BlobBackend::new(source, rt.handle().clone(), options()).unwrap())
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    /// Lists a directory as `name` for files and `name/` for folders.
+    fn ls<S: BlobSource>(backend: &Arc<BlobBackend<S>>, path: &str) -> Vec<String> {
+        let mut names = backend
+            .enumerate(Path::new(path), None)
+            .unwrap()
+            .map(|e| {
+                if e.is_dir {
+                    format!("{}/", e.name)
+                } else {
+                    e.name
+                }
+            })
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn folders_are_inferred_from_blob_names() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a/b/c.txt", "a/d.txt", "e.txt"]);
+
+        assert_eq!(ls(&backend, ""), ["a/", "e.txt"]);
+        assert_eq!(ls(&backend, "a"), ["b/", "d.txt"]);
+        assert_eq!(ls(&backend, "a/b"), ["c.txt"]);
+    }
+
+    #[test]
+    fn root_blobs_are_files_at_the_root() {
+        let rt = runtime();
+        let backend = backend(&rt, &["top.txt", "sub/inner.txt"]);
+
+        assert_eq!(ls(&backend, ""), ["sub/", "top.txt"]);
+        assert_eq!(ls(&backend, "sub"), ["inner.txt"]);
+
+        let entry = backend.metadata(Path::new("top.txt")).unwrap();
+        assert_eq!(entry.name, "top.txt");
+        assert!(!entry.is_dir);
+        assert!(backend.metadata(Path::new("top.txt/x")).is_err());
+    }
+
+    #[test]
+    fn folders_hide_blobs_of_the_same_name() {
+        let rt = runtime();
+        let backend = backend(&rt, &["foo", "foo/bar", "baz"]);
+
+        assert_eq!(ls(&backend, ""), ["baz", "foo/"]);
+        assert!(backend.metadata(Path::new("foo")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn unprojectable_names_are_hidden() {
+        let rt = runtime();
+        let backend = backend(&rt, &["ok.txt", "bad:name", "what?/file", "a//b"]);
+
+        assert_eq!(ls(&backend, ""), ["a/", "ok.txt"]);
+        assert!(ls(&backend, "a").is_empty());
+    }
+
+    #[test]
+    fn entries_are_named_by_their_leaf() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a/b/c.txt"]);
+
+        let entry = backend.metadata(Path::new("a/b/c.txt")).unwrap();
+        assert_eq!(entry.name, "c.txt");
+        assert_eq!(entry.size, "a/b/c.txt".len() as u64);
+        assert!(!entry.is_dir);
+
+        let entry = backend.metadata(Path::new("a/b")).unwrap();
+        assert_eq!(entry.name, "b");
+        assert!(entry.is_dir);
+    }
+
+    #[test]
+    fn missing_blobs_are_not_found() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a/b.txt"]);
+
+        let e = backend.metadata(Path::new("a/c.txt")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn reads_serve_blob_contents() {
+        let rt = runtime();
+        let backend = backend(&rt, &["dir/file.txt"]);
+
+        let mut buf = [0; 4];
+        backend
+            .read(Path::new("dir/file.txt"), 4, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"file");
+    }
+
+    #[test]
+    fn collisions_are_resolved_across_pages() {
+        let rt = runtime();
+        let names = ["foo", "foo.txt", "foo/bar", "foo/baz", "qux"];
+
+        for page_size in 1..=names.len() {
+            let backend = paged_backend(&rt, &names, page_size);
+            assert_eq!(ls(&backend, ""), ["foo.txt", "foo/", "qux"]);
+        }
+    }
+
+    #[test]
+    fn listings_are_produced_in_order() {
+        let rt = runtime();
+        let backend = paged_backend(&rt, &["a", "a.txt", "a/b", "c", "d/e"], 1);
+
+        let names = backend
+            .enumerate(Path::new(""), None)
+            .unwrap()
+            .map(|e| e.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.txt", "a", "c", "d"]);
+    }
+
+    #[test]
+    fn complete_listings_are_cached() {
+        let rt = runtime();
+        let backend = paged_backend(&rt, &["a", "b", "c"], 1);
+
+        let mut listing = backend.enumerate(Path::new(""), None).unwrap();
+        listing.next();
+        drop(listing);
+        assert!(backend.listing_cache.get(&BlobPath::new("")).is_none());
+
+        assert_eq!(ls(&backend, ""), ["a", "b", "c"]);
+        assert!(backend.listing_cache.get(&BlobPath::new("")).is_some());
+    }
+
+    #[test]
+    fn archived_blobs_are_not_read() {
+        let rt = runtime();
+        let source = FakeSource {
+            archived: HashSet::from(["cold.bin".to_string()]),
+            ..FakeSource::new(&["cold.bin"])
+        };
+        let backend = BlobBackend::new(source, rt.handle().clone(), options()).unwrap();
+
+        let mut buf = [0; 4];
+        let e = backend
+            .read(Path::new("cold.bin"), 0, &mut buf)
+            .unwrap_err();
+        assert!(e.to_string().contains("archived"));
+        assert!(backend.source.tier_changes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn archived_blobs_are_rehydrated_once() {
+        let rt = runtime();
+        let source = FakeSource {
+            archived: HashSet::from(["cold.bin".to_string()]),
+            ..FakeSource::new(&["cold.bin"])
+        };
+        let options = DriverOptions {
+            auto_rehydrate: Some(AccessTier::Hot),
+            ..options()
+        };
+        let backend = BlobBackend::new(source, rt.handle().clone(), options).unwrap();
+
+        let mut buf = [0; 4];
+        for _ in 0..2 {
+            let e = backend
+                .read(Path::new("cold.bin"), 0, &mut buf)
+                .unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+        }
+
+        assert_eq!(*backend.source.tier_changes.lock().unwrap(), ["cold.bin"]);
+    }
+
+    #[test]
+    fn stalled_reads_time_out() {
+        let rt = runtime();
+        let source = FakeSource {
+            stalled: HashSet::from(["slow.bin".to_string()]),
+            ..FakeSource::new(&["slow.bin", "fast.bin"])
+        };
+        let options = DriverOptions {
+            request_timeout: Some(Duration::from_millis(20)),
+            ..options()
+        };
+        let backend = BlobBackend::new(source, rt.handle().clone(), options).unwrap();
+
+        let mut buf = [0; 8];
+        let e = backend
+            .read(Path::new("slow.bin"), 0, &mut buf)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+
+        backend.read(Path::new("fast.bin"), 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"fast.bin");
+    }
+
+    #[test]
+    fn retried_timeouts_are_recognized() {
+        let e = azure_core::Error::new(
+            azure_core::error::ErrorKind::Io,
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "no response"),
+        )
+        .context("retry policy expired and the request will no longer be retried");
+
+        let e = std::io::Error::from(RazmountError::from(e));
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn storage_errors_are_classified() {
+        let throttled = azure_core::Error::message(
+            azure_core::error::ErrorKind::HttpResponse {
+                status: StatusCode::TooManyRequests,
+                error_code: Some("ServerBusy".into()),
+            },
+            "slow down",
+        );
+        let throttled = RazmountError::from(throttled);
+        assert!(matches!(throttled, RazmountError::Throttled(_)));
+
+        // Throttling isn't an interruption, which would be retried straight away.
+        let e = std::io::Error::from(throttled);
+        assert_eq!(e.kind(), std::io::ErrorKind::Other);
+        assert!(matches!(
+            e.get_ref().and_then(|e| e.downcast_ref::<RazmountError>()),
+            Some(RazmountError::Throttled(_))
+        ));
+
+        // The classification survives the conversion for the file system.
+        let e = std::io::Error::from(RazmountError::from(not_found()));
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            e.get_ref().and_then(|e| e.downcast_ref::<RazmountError>()),
+            Some(RazmountError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn pinned_reads_fail_once_blobs_change() {
+        let rt = runtime();
+        for pin_etags in [false, true] {
+            let options = DriverOptions {
+                pin_etags,
+                ..options()
+            };
+            let source = FakeSource::new(&["data.csv"]);
+            let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+            assert_eq!(ls(&backend, ""), ["data.csv"]);
+            backend
+                .source
+                .modified
+                .lock()
+                .unwrap()
+                .insert("data.csv".to_string());
+
+            let mut buf = [0; 8];
+            let r = backend.read(Path::new("data.csv"), 0, &mut buf);
+            if pin_etags {
+                assert!(r.unwrap_err().to_string().contains("has changed"));
+            } else {
+                r.unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn adjacent_reads_are_coalesced() {
+        let rt = runtime();
+        let options = DriverOptions {
+            coalesce_window: Duration::from_millis(200),
+            coalesce_max_bytes: 1024,
+            ..options()
+        };
+        let source = FakeSource::new(&["abcdefghijkl"]);
+        let backend = BlobBackend::new(source, rt.handle().clone(), options).unwrap();
+
+        // The first read opens the batch, and the others each join it from either side. (A read
+        // between two batches wouldn't join them up.)
+        let reads = std::thread::scope(|s| {
+            let reads = [(4, 4), (0, 4), (8, 4)].map(|(offset, len)| {
+                s.spawn({
+                    let backend = &backend;
+                    move || {
+                        if offset != 4 {
+                            std::thread::sleep(Duration::from_millis(50));
+                        }
+
+                        let mut buf = vec![0; len];
+                        backend
+                            .read(Path::new("abcdefghijkl"), offset, &mut buf)
+                            .map(|()| buf)
+                    }
+                })
+            });
+
+            reads.map(|r| r.join().unwrap().unwrap())
+        });
+
+        assert_eq!(reads, [b"efgh", b"abcd", b"ijkl"]);
+        let downloads = backend.source.downloads.lock().unwrap();
+        assert_eq!(downloads.len(), 1);
+        assert_eq!(downloads[0], 0..12);
+    }
+
+    #[test]
+    fn summaries_stop_at_the_limit() {
+        let rt = runtime();
+        let names = ["a/x.bin", "a/y.bin", "b/z.tmp", "c.bin"];
+        let options = DriverOptions {
+            exclude: GlobSet::new([globset::Glob::new("**/*.tmp").unwrap()]).unwrap(),
+            ..options()
+        };
+        let source = FakeSource {
+            page_size: 2,
+            ..FakeSource::new(&names)
+        };
+        let backend = BlobBackend::new(source, rt.handle().clone(), options).unwrap();
+
+        let summary = backend.summarize(10).unwrap();
+        assert_eq!(summary.to_string(), "3 blobs, 19 bytes");
+
+        let summary = backend.summarize(2).unwrap();
+        assert_eq!(
+            summary.to_string(),
+            "at least 2 blobs, 14 bytes (truncated)"
+        );
+    }
+
+    #[test]
+    fn hierarchical_namespaces_have_real_directories() {
+        let rt = runtime();
+        let mk = || FakeSource {
+            folders: HashSet::from(["dir".to_string(), "empty".to_string()]),
+            ..FakeSource::new(&["dir", "dir/a.txt", "empty"])
+        };
+
+        let mut backend = BlobBackend::new(mk(), rt.handle().clone(), options()).unwrap();
+        backend.detect_namespace().unwrap();
+        let backend = Arc::new(backend);
+
+        assert_eq!(ls(&backend, ""), ["dir/", "empty/"]);
+        assert!(ls(&backend, "empty").is_empty());
+        assert!(backend.metadata(Path::new("empty")).unwrap().is_dir);
+
+        // Without a hierarchical namespace, directory blobs are mere files.
+        let options = DriverOptions {
+            hns: Some(false),
+            ..options()
+        };
+        let mut backend = BlobBackend::new(mk(), rt.handle().clone(), options).unwrap();
+        backend.detect_namespace().unwrap();
+
+        assert_eq!(ls(&Arc::new(backend), ""), ["dir/", "empty"]);
+    }
+
+    #[test]
+    fn folder_marker_blobs_are_empty_folders() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a.txt", "folder/"]);
+
+        assert_eq!(ls(&backend, ""), ["a.txt", "folder/"]);
+        assert!(backend
+            .known_dirs
+            .lock()
+            .unwrap()
+            .contains(Path::new("folder")));
+        assert!(ls(&backend, "folder").is_empty());
+        assert!(backend.metadata(Path::new("folder")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn other_delimiters_are_projected_as_folders() {
+        let rt = runtime();
+        let source = Delimited::new(
+            FakeSource::new(&["a\\b\\c.txt", "a\\d.txt", "e.txt", "odd/name"]),
+            '\\',
+        );
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options()).unwrap());
+
+        assert_eq!(ls(&backend, ""), ["a/", "e.txt"]);
+        assert_eq!(ls(&backend, "a"), ["b/", "d.txt"]);
+        assert_eq!(ls(&backend, "a/b"), ["c.txt"]);
+
+        let mut buf = [0u8; 8];
+        backend.read(Path::new("a/b/c.txt"), 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"a\\b\\c.tx");
+        assert!(backend.metadata(Path::new("odd/name")).is_err());
+    }
+
+    #[test]
+    fn offline_mounts_serve_only_what_is_cached() {
+        let rt = runtime();
+        let dir = std::env::temp_dir().join(format!("razmount-offline-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let online = DriverOptions {
+            cache_dir: Some(dir.clone()),
+            cache_max_bytes: 1 << 20,
+            offline_after: NonZeroU32::new(1),
+            ..options()
+        };
+        let source = FakeSource::new(&["a/b.txt", "a/c.txt", "d.txt"]);
+        let backend =
+            Arc::new(BlobBackend::new(source, rt.handle().clone(), online.clone()).unwrap());
+
+        assert_eq!(ls(&backend, "a"), ["b.txt", "c.txt"]);
+        let mut buf = [0u8; 7];
+        backend.read(Path::new("a/b.txt"), 0, &mut buf).unwrap();
+
+        // The first request that fails to connect falls back to the caches.
+        backend.source.unreachable.store(true, Ordering::Relaxed);
+        let e = backend.metadata(Path::new("d.txt")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Other);
+
+        assert_eq!(ls(&backend, "a"), ["b.txt", "c.txt"]);
+        assert!(!backend.metadata(Path::new("a/c.txt")).unwrap().is_dir);
+        let e = backend.metadata(Path::new("d.txt")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+
+        // What was cached on disk is still there after a restart.
+        let offline = DriverOptions {
+            offline: true,
+            ..online
+        };
+        let source = FakeSource::new(&[]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), offline).unwrap());
+
+        assert_eq!(ls(&backend, "a"), ["b.txt", "c.txt"]);
+        assert_eq!(backend.metadata(Path::new("a/c.txt")).unwrap().size, 7);
+
+        let mut buf = [0u8; 7];
+        backend.read(Path::new("a/b.txt"), 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"a/b.txt");
+
+        let e = backend.read(Path::new("a/c.txt"), 0, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            backend.enumerate(Path::new(""), None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn progress_counts_reads_in_a_row() {
+        let rt = runtime();
+        let source = FakeSource::new(&["big.bin"]);
+        let options = DriverOptions {
+            progress_interval: Some(Duration::ZERO),
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+        let progress = || {
+            let ra = backend.readahead.as_ref().unwrap();
+            ra.progress("big.bin", Duration::ZERO)
+        };
+
+        let mut buf = [0u8; 2];
+        for offset in [0, 2, 4] {
+            backend
+                .read(Path::new("big.bin"), offset, &mut buf)
+                .unwrap();
+        }
+        assert_eq!(progress(), Some(6));
+
+        // Seeking elsewhere starts counting again.
+        backend.read(Path::new("big.bin"), 1, &mut buf).unwrap();
+        assert_eq!(progress(), Some(2));
+    }
+
+    #[test]
+    fn downloads_are_aligned_to_blocks() {
+        let rt = runtime();
+        let dir = std::env::temp_dir().join(format!("razmount-blocks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let options = DriverOptions {
+            cache_dir: Some(dir.clone()),
+            cache_max_bytes: 1 << 20,
+            block_size: 4,
+            ..options()
+        };
+        let source = FakeSource::new(&["0123456789.bin"]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        let mut buf = [0u8; 2];
+        backend
+            .read(Path::new("0123456789.bin"), 5, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"56");
+
+        // The rest of the block is served from the cache.
+        let mut buf = [0u8; 3];
+        backend
+            .read(Path::new("0123456789.bin"), 4, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"456");
+
+        // The last block stops at the end of the blob.
+        let mut buf = [0u8; 1];
+        backend
+            .read(Path::new("0123456789.bin"), 13, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"n");
+
+        assert_eq!(*backend.source.downloads.lock().unwrap(), [4..8, 12..14]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn blobs_can_be_projected_as_chunks() {
+        let rt = runtime();
+        let options = DriverOptions {
+            blob_as_dir: Some("big.bin".into()),
+            chunk_size: NonZeroU64::new(3).unwrap(),
+            ..options()
+        };
+        let source = FakeSource::new(&["big.bin", "other.txt"]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        assert_eq!(
+            ls(&backend, ""),
+            ["00000000.chunk", "00000001.chunk", "00000002.chunk"]
+        );
+        assert_eq!(
+            backend.metadata(Path::new("00000002.chunk")).unwrap().size,
+            1
+        );
+        assert!(backend.metadata(Path::new("00000003.chunk")).is_err());
+        assert!(backend.metadata(Path::new("other.txt")).is_err());
+
+        let mut buf = [0u8; 2];
+        backend
+            .read(Path::new("00000001.chunk"), 1, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"bi");
+    }
+
+    #[test]
+    fn new_etags_invalidate_every_cache() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a/b.txt", "a/c.txt"]);
+        let (dir, blob) = (BlobPath::new("a"), BlobPath::new("a/b.txt"));
+
+        assert_eq!(ls(&backend, "a"), ["b.txt", "c.txt"]);
+        assert!(backend.listing_cache.get(&dir).is_some());
+        assert!(backend.metadata_cache.get(&blob).is_some());
+
+        // Reading the blob after it's overwritten is the first sign that it has changed.
+        backend
+            .source
+            .modified
+            .lock()
+            .unwrap()
+            .insert("a/b.txt".into());
+        let mut buf = [0u8; 3];
+        backend.read(Path::new("a/b.txt"), 0, &mut buf).unwrap();
+
+        assert!(backend.listing_cache.get(&dir).is_none());
+        assert!(backend.metadata_cache.get(&blob).is_none());
+        assert!(backend
+            .metadata_cache
+            .get(&BlobPath::new("a/c.txt"))
+            .is_some());
+    }
+
+    #[test]
+    fn deleted_blobs_are_only_listed_on_request() {
+        let rt = runtime();
+        let mk = || FakeSource {
+            deleted: HashSet::from(["gone.txt".to_string()]),
+            ..FakeSource::new(&["gone.txt", "live.txt"])
+        };
+
+        let backend = Arc::new(BlobBackend::new(mk(), rt.handle().clone(), options()).unwrap());
+        assert_eq!(ls(&backend, ""), ["live.txt"]);
+
+        let options = DriverOptions {
+            include_deleted: true,
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(mk(), rt.handle().clone(), options).unwrap());
+        assert_eq!(ls(&backend, ""), ["gone.txt.deleted", "live.txt"]);
+        assert_eq!(
+            backend
+                .metadata(Path::new("gone.txt.deleted"))
+                .unwrap()
+                .size,
+            8
+        );
+
+        // The deleted blob is read through the version that versioning kept of it.
+        let mut buf = [0u8; 4];
+        backend
+            .read(Path::new("gone.txt.deleted"), 0, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"gone");
+    }
+
+    #[test]
+    fn blob_times_convert_to_filetimes() {
+        let rt = runtime();
+        let backend =
+            BlobBackend::new(FakeSource::new(&["a.txt"]), rt.handle().clone(), options()).unwrap();
+
+        let before = filetime(SystemTime::now());
+        let entry = backend.metadata(Path::new("a.txt")).unwrap();
+
+        // 2023-01-01T00:00:00Z, in 100ns intervals since 1601.
+        assert_eq!(entry.created.map(filetime), Some(133_170_048_000_000_000));
+        assert_eq!(entry.modified.map(filetime), Some(133_170_048_000_000_000));
+        assert!((before..=filetime(SystemTime::now())).contains(&filetime(entry.fetched)));
+
+        assert_eq!(filetime(UNIX_EPOCH), 116_444_736_000_000_000);
+        assert_eq!(
+            filetime(UNIX_EPOCH - Duration::from_secs(400 * 366 * 86400)),
+            0
+        );
+    }
+
+    #[test]
+    fn tag_filters_hide_blobs_that_dont_match() {
+        let rt = runtime();
+        let source = TagFiltered::new(
+            FakeSource::new(&["a/x.txt", "a/y.txt", "b/z.txt", "c.txt", "d.txt"]),
+            Some(["a/x.txt".to_string(), "d.txt".to_string()].into()),
+        );
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options()).unwrap());
+
+        // Folders without any matching blobs are hidden as well.
+        assert_eq!(ls(&backend, ""), ["a/", "d.txt"]);
+        assert_eq!(ls(&backend, "a"), ["x.txt"]);
+        assert!(backend.metadata(Path::new("a/x.txt")).is_ok());
+        assert_eq!(
+            backend.metadata(Path::new("c.txt")).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn lookups_add_new_blobs_to_cached_listings() {
+        let rt = runtime();
+        let backend = backend(&rt, &["a/b.txt", "a/new.txt"]);
+        let new = Path::new("a/new.txt");
+        backend
+            .source
+            .pending
+            .lock()
+            .unwrap()
+            .insert("a/new.txt".into());
+
+        assert_eq!(ls(&backend, "a"), ["b.txt"]);
+        assert!(backend.metadata(new).is_err());
+
+        // Repeated misses are answered without asking Azure again.
+        let requests = backend.stats.requests.get();
+        assert!(backend.metadata(new).is_err());
+        assert_eq!(backend.stats.requests.get(), requests);
+
+        // Once the blob is uploaded (and the miss has expired), looking it up by name adds it to
+        // the cached listing, rather than waiting for the listing to expire.
+        backend.source.pending.lock().unwrap().clear();
+        backend
+            .misses
+            .remove_if(&BlobPath::new("a/new.txt"), |_| true);
+        assert_eq!(backend.metadata(new).unwrap().name, "new.txt");
+        assert!(backend.listing_cache.get(&BlobPath::new("a")).is_some());
+        assert_eq!(ls(&backend, "a"), ["b.txt", "new.txt"]);
+    }
+
+    #[test]
+    fn followed_mounts_notice_new_blobs() {
+        let rt = runtime();
+        let source = FakeSource::new(&["a/b.txt", "a/new.txt", "c/gone.txt"]);
+        source.pending.lock().unwrap().insert("a/new.txt".into());
+        let options = DriverOptions {
+            follow_interval: Some(Duration::from_millis(20)),
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        assert_eq!(ls(&backend, "a"), ["b.txt"]);
+        assert_eq!(ls(&backend, "c"), ["gone.txt"]);
+
+        // Let the first poll take its snapshot before the container changes.
+        std::thread::sleep(Duration::from_millis(100));
+        backend.source.pending.lock().unwrap().clear();
+        backend
+            .source
+            .pending
+            .lock()
+            .unwrap()
+            .insert("c/gone.txt".into());
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(ls(&backend, "a"), ["b.txt", "new.txt"]);
+        assert!(ls(&backend, "c").is_empty());
+    }
+
+    #[test]
+    fn versions_are_projected_as_folders() {
+        let rt = runtime();
+        let mk = || FakeSource {
+            versions: HashMap::from([("a.txt".to_string(), b"old".to_vec())]),
+            ..FakeSource::new(&["a.txt", "b.txt"])
+        };
+
+        // Without the option, nothing changes.
+        let backend = Arc::new(BlobBackend::new(mk(), rt.handle().clone(), options()).unwrap());
+        assert_eq!(ls(&backend, ""), ["a.txt", "b.txt"]);
+
+        let options = DriverOptions {
+            versions_as_subdir: true,
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(mk(), rt.handle().clone(), options).unwrap());
+
+        // Only the versioned blob gets a folder.
+        assert_eq!(ls(&backend, ""), ["a.txt", "a.txt.versions/", "b.txt"]);
+        assert_eq!(
+            ls(&backend, "a.txt.versions"),
+            [
+                "2023-01-01T00-00-00.0000000Z.txt",
+                "2023-06-01T00-00-00.0000000Z.txt"
+            ]
+        );
+
+        let old = Path::new("a.txt.versions/2023-01-01T00-00-00.0000000Z.txt");
+        let info = backend.metadata(old).unwrap();
+        assert_eq!(info.size, 3);
+        assert_ne!(info.attrs & FILE_ATTRIBUTE_READONLY, 0);
+
+        let mut buf = [0u8; 3];
+        backend.read(old, 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"old");
+    }
+
+    #[test]
+    fn blobs_are_filtered_by_modification_time() {
+        let rt = runtime();
+        let source = FakeSource {
+            modified_at: HashMap::from([
+                ("b.txt".to_string(), "Wed, 01 Mar 2023 00:00:00 GMT"),
+                ("c.txt".to_string(), "Thu, 01 Jun 2023 00:00:00 GMT"),
+                ("d/e.txt".to_string(), "Sat, 01 Apr 2023 00:00:00 GMT"),
+            ]),
+            ..FakeSource::new(&["a.txt", "b.txt", "c.txt", "d/e.txt", "f/g.txt"])
+        };
+        let at = |s| azure_core::date::parse_rfc3339(s).unwrap();
+        let options = DriverOptions {
+            modified: ModifiedWindow {
+                after: Some(at("2023-03-01T00:00:00Z")),
+                before: Some(at("2023-06-01T00:00:00Z")),
+            },
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        // Blobs modified exactly at the start are included, and exactly at the end excluded.
+        // Folders with nothing in the window are hidden.
+        assert_eq!(ls(&backend, ""), ["b.txt", "d/"]);
+        assert_eq!(ls(&backend, "d"), ["e.txt"]);
+
+        for hidden in ["a.txt", "c.txt", "f"] {
+            let e = backend.metadata(Path::new(hidden)).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "{hidden}");
+        }
+        assert!(!backend.metadata(Path::new("b.txt")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn folders_stop_at_the_maximum_depth() {
+        let rt = runtime();
+        let names = ["a.txt", "b/c.txt", "b/d/e.txt"];
+        let mk = |max_depth| {
+            let options = DriverOptions {
+                max_depth: Some(max_depth),
+                ..options()
+            };
+            let source = FakeSource::new(&names);
+            Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap())
+        };
+
+        let backend = mk(1);
+        assert_eq!(ls(&backend, ""), ["a.txt", "b/"]);
+        assert_eq!(ls(&backend, "b"), ["c.txt"]);
+        for hidden in ["b/d", "b/d/e.txt"] {
+            let e = backend.metadata(Path::new(hidden)).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::NotFound, "{hidden}");
+        }
+        assert_eq!(backend.summarize(u64::MAX).unwrap().blobs, 2);
+
+        let backend = mk(0);
+        assert_eq!(ls(&backend, ""), ["a.txt"]);
+        assert!(backend.metadata(Path::new("b")).is_err());
+    }
+
+    #[test]
+    fn extensions_are_inferred_from_content_types() {
+        let rt = runtime();
+        let mk = || {
+            let source = FakeSource {
+                content_types: HashMap::from([
+                    ("img/logo".to_string(), "image/png"),
+                    ("img/notes.md".to_string(), "text/plain"),
+                ]),
+                ..FakeSource::new(&["img/logo", "img/notes.md", "img/raw"])
+            };
+            let options = DriverOptions {
+                infer_extensions: true,
+                ..options()
+            };
+            Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap())
+        };
+        let backend = mk();
+
+        // Blobs that have an extension already, or an unknown content type, keep their names.
+        assert_eq!(ls(&backend, "img"), ["logo.png", "notes.md", "raw"]);
+
+        let mut buf = [0u8; 8];
+        backend
+            .read(Path::new("img/logo.png"), 0, &mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"img/logo");
+
+        // Names are resolved back to the blob when looked up without listing the folder first,
+        // but only with the extension that the content type stands for.
+        let backend = mk();
+        let info = backend.metadata(Path::new("img/logo.png")).unwrap();
+        assert_eq!(info.name, "logo.png");
+        assert!(backend.metadata(Path::new("img/logo.jpg")).is_err());
+    }
+
+    #[test]
+    fn inferred_names_give_way_to_blobs_of_that_name() {
+        let rt = runtime();
+        let source = FakeSource {
+            content_types: HashMap::from([("img/logo".to_string(), "image/png")]),
+            ..FakeSource::new(&["img/logo", "img/logo-2.txt", "img/logo.png"])
+        };
+        let options = DriverOptions {
+            infer_extensions: true,
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        assert_eq!(ls(&backend, "img"), ["logo-2.txt", "logo.png"]);
+
+        // The blob whose name it is is the one that's read.
+        let path = Path::new("img/logo.png");
+        let mut buf = [0u8; 12];
+        backend.read(path, 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"img/logo.png");
+        assert_eq!(backend.metadata(path).unwrap().size, 12);
+    }
+
+    #[test]
+    fn blocked_extensions_are_hidden() {
+        let rt = runtime();
+        let source = FakeSource::new(&["a.txt", "lib/x.dll", "setup.EXE", "exe"]);
+        let options = DriverOptions {
+            blocked_extensions: vec![".exe".to_string(), ".dll".to_string()],
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        assert_eq!(ls(&backend, ""), ["a.txt", "exe", "lib/"]);
+        assert!(ls(&backend, "lib").is_empty());
+
+        let e = backend.metadata(Path::new("setup.EXE")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+
+        let mut buf = [0u8; 4];
+        let e = backend
+            .read(Path::new("lib/x.dll"), 0, &mut buf)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn failing_requests_open_the_circuit_breaker() {
+        let rt = runtime();
+        let options = DriverOptions {
+            breaker_threshold: NonZeroU32::new(2),
+            breaker_cooldown: Duration::from_millis(50),
+            ..options()
+        };
+        let source = FakeSource::new(&["a.txt", "b.txt", "c.txt", "d.txt"]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+        let stats = backend.stats();
+
+        // Missing blobs don't count as failures.
+        assert!(backend.metadata(Path::new("missing.txt")).is_err());
+
+        backend.source.unreachable.store(true, Ordering::Relaxed);
+        assert!(backend.metadata(Path::new("a.txt")).is_err());
+        assert!(backend.metadata(Path::new("b.txt")).is_err());
+        let requests = stats.requests.get();
+
+        // Once open, requests fail without being made.
+        let e = backend.metadata(Path::new("c.txt")).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::Other);
+        assert_eq!(stats.requests.get(), requests);
+
+        // After the cooldown, a request probes Azure, and closes the breaker if it succeeds.
+        backend.source.unreachable.store(false, Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!backend.metadata(Path::new("c.txt")).unwrap().is_dir);
+        assert!(!backend.metadata(Path::new("d.txt")).unwrap().is_dir);
+    }
+
+    #[test]
+    fn listed_files_have_their_properties_prefetched() {
+        let rt = runtime();
+        let names = ["a/1.txt", "a/2.txt", "a/3.txt", "a/b/4.txt"];
+        let requests = |prefetch_metadata| {
+            let options = DriverOptions {
+                prefetch_metadata,
+                max_concurrency: NonZeroUsize::new(2),
+                ..options()
+            };
+            let source = FakeSource {
+                page_size: 2,
+                ..FakeSource::new(&names)
+            };
+            let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+            assert_eq!(ls(&backend, "a"), ["1.txt", "2.txt", "3.txt", "b/"]);
+            let listed = backend.stats().requests.get();
+            for name in ["a/1.txt", "a/2.txt", "a/3.txt"] {
+                assert_eq!(
+                    backend.metadata(Path::new(name)).unwrap().size,
+                    name.len() as u64
+                );
+            }
+            assert_eq!(backend.stats().requests.get(), listed);
+            listed
+        };
+
+        // The properties of each page of files are fetched together.
+        assert_eq!(requests(true), requests(false) + 2);
+    }
+
+    #[test]
+    fn leased_blobs_are_read_according_to_the_lease_policy() {
+        let rt = runtime();
+        let read = |lease_policy| {
+            let source = FakeSource {
+                leased: HashSet::from(["a.txt".to_string()]),
+                ..FakeSource::new(&["a.txt", "b.txt"])
+            };
+            let options = DriverOptions {
+                lease_policy,
+                ..options()
+            };
+            let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+            let mut buf = [0u8; 5];
+            backend.read(Path::new("b.txt"), 0, &mut buf).unwrap();
+            backend.read(Path::new("a.txt"), 0, &mut buf).map(|_| buf)
+        };
+
+        assert_eq!(&read(LeasePolicy::Ignore).unwrap(), b"a.txt");
+        assert_eq!(&read(LeasePolicy::Warn).unwrap(), b"a.txt");
+        let e = read(LeasePolicy::Deny).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn downloads_are_limited_to_the_maximum_bandwidth() {
+        let rt = runtime();
+        let rate = 1024 * 1024;
+        let mut source = FakeSource::new(&[]);
+        source
+            .blobs
+            .insert("big.bin".to_string(), vec![7; rate * 3 / 2]);
+        let options = DriverOptions {
+            max_bandwidth: NonZeroU64::new(rate as u64),
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        // The first second's worth of bytes can be downloaded straight away, and the rest takes
+        // as long as it does at the rate.
+        let start = Instant::now();
+        let mut buf = vec![0u8; 64 * 1024];
+        for offset in (0..rate * 3 / 2).step_by(buf.len()) {
+            backend
+                .read(Path::new("big.bin"), offset as u64, &mut buf)
+                .unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(1500), "{elapsed:?}");
+    }
+
+    #[test]
+    fn projected_paths_round_trip_to_blob_names() {
+        const CHARS: &[char] = &[
+            'a', 'b', 'Z', '0', ' ', '.', '-', '%', '+', '#', '~', 'é', '日', '/', '/', '\\', ':',
+        ];
+
+        // A fixed xorshift sequence, so that failures can be reproduced.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut names = HashSet::new();
+        while names.len() < 300 {
+            let len = 1 + next() % 12;
+            let name: String = (0..len)
+                .map(|_| CHARS[next() as usize % CHARS.len()])
+                .collect();
+            names.insert(name);
+        }
+
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let rt = runtime();
+        let backend = backend(&rt, &names);
+
+        for name in &names {
+            // Blobs that are hidden behind a folder of the same name are never read as files.
+            let shadowed = names.iter().any(|n| n.starts_with(&format!("{name}/")));
+            if shadowed || !name.split('/').all(is_projectable) {
+                continue;
+            }
+
+            // The path each component is projected at, as the file system adapters build it.
+            let virt_path = name.split('/').fold(PathBuf::new(), |p, c| p.join(c));
+            assert_eq!(BlobPath::from(&virt_path).as_str(), *name);
+
+            let entry = backend.metadata(&virt_path).unwrap();
+            assert_eq!(entry.size, name.len() as u64, "{name}");
+            let mut buf = vec![0u8; name.len()];
+            backend.read(&virt_path, 0, &mut buf).unwrap();
+            assert_eq!(buf, name.as_bytes(), "{name}");
+        }
+    }
+
+    #[test]
+    fn small_blobs_are_read_from_memory() {
+        let rt = runtime();
+        let options = DriverOptions {
+            small_blob_threshold: 16,
+            small_blob_cache_bytes: 1024,
+            ..options()
+        };
+        let source = FakeSource::new(&["config.json", "a/much-larger-blob.txt"]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+        let read = |name: &str, offset: u64| {
+            let mut buf = [0u8; 2];
+            backend.read(Path::new(name), offset, &mut buf).unwrap();
+            buf
+        };
+
+        // The first read downloads the whole blob, and the rest are served from memory.
+        assert_eq!(&read("config.json", 2), b"nf");
+        assert_eq!(&read("config.json", 7), b"js");
+        let downloads = backend.source.downloads.lock().unwrap().clone();
+        assert_eq!((downloads.len(), &downloads[0]), (1, &(0..11)));
+        assert_eq!(backend.stats().memory_cache_hits.get(), 1);
+
+        // Larger blobs are only downloaded as far as they're read.
+        assert_eq!(&read("a/much-larger-blob.txt", 2), b"mu");
+        assert_eq!(backend.source.downloads.lock().unwrap()[1], 2..4);
+
+        // Once the blob is known to have changed, it's downloaded again.
+        backend.observe_etag("config.json", &Etag::from("0x2"));
+        read("config.json", 0);
+        assert_eq!(backend.source.downloads.lock().unwrap()[2], 0..11);
+    }
+
+    #[test]
+    fn folders_are_listed_with_the_size_of_their_contents() {
+        let rt = runtime();
+        let options = DriverOptions {
+            compute_dir_sizes: true,
+            ..options()
+        };
+        let source = FakeSource::new(&["a/b.txt", "a/c/d.txt", "e/f.txt", "g.txt"]);
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+        let sizes = |path: &str| {
+            let mut sizes = backend
+                .enumerate(Path::new(path), None)
+                .unwrap()
+                .map(|e| (e.name, e.size))
+                .collect::<Vec<_>>();
+            sizes.sort();
+            sizes
+        };
+
+        // Blobs are as long as their names.
+        let expected = |names: &[(&str, u64)]| {
+            names
+                .iter()
+                .map(|(n, s)| (n.to_string(), *s))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(""), expected(&[("a", 7 + 9), ("e", 7), ("g.txt", 5)]));
+        assert_eq!(sizes("a"), expected(&[("b.txt", 7), ("c", 9)]));
+
+        // Sizes are cached along with listings.
+        let requests = backend.stats().requests.get();
+        assert_eq!(sizes("")[0], ("a".to_string(), 16));
+        assert_eq!(backend.stats().requests.get(), requests);
+    }
+
+    #[test]
+    fn huge_folders_are_listed_a_page_at_a_time() {
+        let rt = runtime();
+        // More than are cached, so that the folder is listed from Azure every time.
+        let names = (0..MAX_CACHED_ENTRIES + 100_000)
+            .map(|i| format!("{i:06}.txt"))
+            .collect::<Vec<_>>();
+        let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        let backend = paged_backend(&rt, &names, 5000);
+        let pages = || backend.source.pages_served.load(Ordering::Relaxed);
+
+        // The first entries are handed out as soon as the first page is in.
+        let mut listing = backend.enumerate(Path::new(""), None).unwrap();
+        let first = listing
+            .by_ref()
+            .take(10)
+            .map(|e| e.name)
+            .collect::<Vec<_>>();
+        assert_eq!(first[..2], ["000000.txt", "000001.txt"]);
+        assert_eq!(pages(), 1);
+
+        // The rest follow in order, a page at a time, without any being left out or repeated.
+        let mut count = first.len();
+        let mut last = first.last().cloned().unwrap();
+        for entry in listing.by_ref() {
+            assert!(entry.name > last, "{} after {last}", entry.name);
+            last = entry.name;
+            count += 1;
+
+            let expected = count.div_ceil(5000);
+            assert!(
+                pages() <= expected + 1,
+                "{} pages for {count} entries",
+                pages()
+            );
+        }
+        assert_eq!((count, pages()), (200_000, 40));
+
+        // Listing it again starts over, again with only the first page.
+        let mut listing = backend.enumerate(Path::new(""), None).unwrap();
+        assert_eq!(listing.next().unwrap().name, "000000.txt");
+        assert_eq!(pages(), 41);
+    }
+
+    #[test]
+    fn parts_are_read_as_one_file() {
+        let rt = runtime();
+        let options = DriverOptions {
+            concat: vec![Concat {
+                parts: globset::Glob::new("data/part-*").unwrap().compile_matcher(),
+                name: PathBuf::from("data/all.bin"),
+            }],
+            ..options()
+        };
+        let names = [
+            "data/part-0000",
+            "data/part-0001",
+            "data/part-0002",
+            "data/x",
+        ];
+        let backend = Arc::new(
+            BlobBackend::new(FakeSource::new(&names), rt.handle().clone(), options).unwrap(),
+        );
+
+        assert_eq!(
+            ls(&backend, "data"),
+            ["all.bin", "part-0000", "part-0001", "part-0002", "x"]
+        );
+        let file = Path::new("data/all.bin");
+        assert_eq!(backend.metadata(file).unwrap().size, 3 * 14);
+
+        // Each part is as long as its name, and a read that crosses a part boundary (14 bytes
+        // in) is served from both.
+        let mut buf = [0; 8];
+        backend.read(file, 10, &mut buf).unwrap();
+        assert_eq!(&buf, b"0000data");
+
+        let mut buf = [0; 3 * 14];
+        backend.read(file, 0, &mut buf).unwrap();
+        assert_eq!(&buf, b"data/part-0000data/part-0001data/part-0002");
+
+        assert!(backend.read(file, 40, &mut [0; 3]).is_err());
+    }
+
+    #[test]
+    fn names_are_listed_and_read_verbatim() {
+        let rt = runtime();
+        let name = "a b/ünïcode%20.txt";
+        let backend = backend(&rt, &[name]);
+
+        // Spaces, non-ASCII characters and percent signs aren't encoded or decoded.
+        assert_eq!(ls(&backend, ""), ["a b/"]);
+        assert_eq!(ls(&backend, "a b"), ["ünïcode%20.txt"]);
+
+        let path = Path::new(name);
+        let len = backend.metadata(path).unwrap().size;
+        assert_eq!(len, name.len() as u64);
+
+        let mut buf = vec![0; len as usize];
+        backend.read(path, 0, &mut buf).unwrap();
+        assert_eq!(buf, name.as_bytes());
+    }
+
+    #[test]
+    fn final_partial_chunks_are_read_in_parallel() {
+        let rt = runtime();
+        const MIB: usize = 1024 * 1024;
+
+        // Three reads of 5 MiB, the last of which is cut short by the end of the blob, but is
+        // still large enough to be split into parallel ranges.
+        let chunk = 5 * MIB;
+        let len = 2 * chunk + PARALLEL_READ_THRESHOLD + 777;
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut source = FakeSource::new(&[]);
+        source.blobs.insert("big.bin".to_string(), data.clone());
+        let options = DriverOptions {
+            parallel_ranges: NonZeroUsize::new(4).unwrap(),
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        let path = Path::new("big.bin");
+        let mut read = Vec::new();
+        for offset in (0..len).step_by(chunk) {
+            let mut buf = vec![0; chunk.min(len - offset)];
+            backend.read(path, offset as u64, &mut buf).unwrap();
+            read.extend_from_slice(&buf);
+        }
+        assert!(read == data, "contents differ");
+
+        // The last read is split into 4 ranges, which end at the end of the blob.
+        let downloads = backend.source.downloads.lock().unwrap().clone();
+        let mut last = downloads[downloads.len() - 4..].to_vec();
+        last.sort_by_key(|r| r.start);
+        assert_eq!(last[0].start, 2 * chunk as u64);
+        assert!(last.windows(2).all(|w| w[0].end == w[1].start));
+        assert_eq!(last[3].end, len as u64);
+    }
+
+    #[test]
+    fn blob_paths_are_normalized() {
+        let cases = [
+            ("a/./b", "a/b"),
+            ("a/../b", "b"),
+            ("/a/b", "a/b"),
+            ("./a/b/", "a/b"),
+            // `..` can't escape the container root.
+            ("../../a", "a"),
+            ("/", ""),
+        ];
+        for (path, blob) in cases {
+            assert_eq!(BlobPath::from(path).as_str(), blob, "{path}");
+        }
+
+        // Nor the prefix that's mounted.
+        let rt = runtime();
+        let options = DriverOptions {
+            prefix: BlobPath::new("data"),
+            ..options()
+        };
+        let backend = BlobBackend::new(FakeSource::new(&[]), rt.handle().clone(), options).unwrap();
+        assert_eq!(
+            backend.blob_path(Path::new("/x/../../y")).as_str(),
+            "data/y"
+        );
+    }
+
+    #[test]
+    fn names_are_matched_case_insensitively_unless_case_sensitive() {
+        let rt = runtime();
+        for case_sensitive in [false, true] {
+            let options = DriverOptions {
+                case_sensitive,
+                ..options()
+            };
+            let source = FakeSource::new(&["dir/file.txt"]);
+            let backend = BlobBackend::new(source, rt.handle().clone(), options).unwrap();
+
+            let path = Path::new("dir/File.TXT");
+            let found = backend.metadata(path);
+            let mut buf = [0; 12];
+            let read = backend.read(path, 0, &mut buf);
+            if case_sensitive {
+                assert_eq!(found.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+                assert!(read.is_err());
+            } else {
+                assert_eq!(found.unwrap().size, 12);
+                read.unwrap();
+                assert_eq!(&buf, b"dir/file.txt");
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_page_blobs_only_download_written_pages() {
+        let rt = runtime();
+
+        // Unwritten pages hold garbage, which the mount must never hand back.
+        let mut data = vec![0xff; 8 * 512];
+        data[512..1024].fill(1);
+        data[2048..3072].fill(2);
+        let written = vec![512..1024, 2048..3072];
+
+        let mut source = FakeSource::new(&[]);
+        source.blobs.insert("disk.vhd".to_string(), data);
+        source
+            .page_blobs
+            .insert("disk.vhd".to_string(), written.clone());
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options()).unwrap());
+
+        let mut buf = vec![0xee; 8 * 512];
+        backend.read(Path::new("disk.vhd"), 0, &mut buf).unwrap();
+
+        let mut expected = vec![0; 8 * 512];
+        expected[512..1024].fill(1);
+        expected[2048..3072].fill(2);
+        assert!(buf == expected, "contents differ");
+
+        let mut downloads = backend.source.downloads.lock().unwrap().clone();
+        downloads.sort_by_key(|r| r.start);
+        assert_eq!(downloads, written);
+
+        // A read that only spans unwritten pages downloads nothing.
+        let mut buf = vec![0xee; 512];
+        backend.read(Path::new("disk.vhd"), 3072, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(backend.source.downloads.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn growing_append_blobs_are_read_across_appends() {
+        let rt = runtime();
+
+        let len = 2 * PARALLEL_READ_THRESHOLD;
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut source = FakeSource::new(&[]);
+        source.blobs.insert("app.log".to_string(), data.clone());
+        source.growing.insert("app.log".to_string());
+        let options = DriverOptions {
+            parallel_ranges: NonZeroUsize::new(4).unwrap(),
+            ..options()
+        };
+        let backend = Arc::new(BlobBackend::new(source, rt.handle().clone(), options).unwrap());
+
+        // Every part of the read comes back with a different ETag, as the blob is appended to.
+        let mut buf = vec![0; len];
+        backend.read(Path::new("app.log"), 0, &mut buf).unwrap();
+        assert!(buf == data, "contents differ");
+        assert_eq!(backend.source.downloads.lock().unwrap().len(), 4);
+
+        // Only the length committed when the blob was last seen is read.
+        let mut buf = vec![0; 16];
+        let e = backend
+            .read(Path::new("app.log"), len as u64, &mut buf)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn names_longer_than_max_path_are_listed_and_read() {
+        let rt = runtime();
+        let dirs = ["0123456789abcdef"; 20];
+        let name = format!("{}/data.csv", dirs.join("/"));
+        assert!(name.len() > 260);
+        let backend = backend(&rt, &[&name]);
+
+        let parent = dirs.join("/");
+        assert_eq!(ls(&backend, &parent), ["data.csv"]);
+
+        let mut buf = vec![0; name.len()];
+        backend.read(Path::new(&name), 0, &mut buf).unwrap();
+        assert_eq!(buf, name.as_bytes());
+    }
+}
@@ -0,0 +1,89 @@
+//! A circuit breaker for requests to Azure, for `--circuit-breaker-threshold`.
+//!
+//! During an outage, every callback would otherwise wait out its own retries and timeouts before
+//! failing, which makes the whole mount (and anything browsing it, such as Explorer) sluggish.
+//! After enough failures in a row, the breaker opens, and requests fail straight away. Once the
+//! cooldown has passed, a single request is let through to probe Azure: if it succeeds, the
+//! breaker closes again, and otherwise it stays open for another cooldown.
+
+use std::{
+    num::NonZeroU32,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+enum State {
+    /// Requests are made as usual. Counts the failures in a row.
+    Closed(u32),
+    /// Requests fail without being made, since this time.
+    Open(Instant),
+    /// A single request is probing whether Azure has recovered.
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    threshold: NonZeroU32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: NonZeroU32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::new(State::Closed(0)),
+        }
+    }
+
+    /// Determines if a request may be made. Every request that is allowed must have its outcome
+    /// recorded with [`Self::record`].
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed(_) => true,
+            State::Open(since) if since.elapsed() >= self.cooldown => {
+                info!("circuit breaker half-open: probing blob storage");
+                *state = State::HalfOpen;
+                true
+            }
+            State::Open(_) | State::HalfOpen => false,
+        }
+    }
+
+    /// Records whether an allowed request failed in a way that suggests Azure is unavailable.
+    pub fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        match (&*state, failed) {
+            (State::Closed(_), false) => *state = State::Closed(0),
+            (State::Closed(n), true) => {
+                let n = n + 1;
+                if n >= self.threshold.get() {
+                    warn!(
+                        "circuit breaker open: {n} requests in a row failed; failing requests for \
+                         {:?}",
+                        self.cooldown
+                    );
+                    *state = State::Open(Instant::now());
+                } else {
+                    *state = State::Closed(n);
+                }
+            }
+            (State::HalfOpen, false) => {
+                info!("circuit breaker closed: blob storage is responding again");
+                *state = State::Closed(0);
+            }
+            (State::HalfOpen, true) => {
+                warn!(
+                    "circuit breaker open: probe failed; failing requests for {:?}",
+                    self.cooldown
+                );
+                *state = State::Open(Instant::now());
+            }
+            // Requests that were made before the breaker opened don't change anything.
+            (State::Open(_), _) => {}
+        }
+    }
+}
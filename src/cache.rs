@@ -0,0 +1,148 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use azure_core::Etag;
+use lru::LruCache;
+
+/// A simple thread-safe map whose entries expire after a fixed time-to-live.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<Entries<K, V>>,
+}
+
+struct Entries<K, V> {
+    map: HashMap<K, (Instant, V)>,
+    /// The number of entries at which to next sweep out expired ones.
+    sweep_at: usize,
+}
+
+impl<K, V> Default for Entries<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            sweep_at: 1,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    /// Creates a new cache. A zero `ttl` disables caching entirely.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Default::default(),
+        }
+    }
+
+    /// Returns the cached value for `key`, if it has not yet expired.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let entries = &mut self.entries.lock().unwrap().map;
+
+        match entries.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Returns the cached value for `key` even if it has expired, for when there's no way to
+    /// fetch a fresh one. Expired entries are only kept until an insertion sweeps them out.
+    pub fn get_stale(&self, key: &K) -> Option<V> {
+        let entries = &self.entries.lock().unwrap().map;
+        entries.get(key).map(|(_, value)| value.clone())
+    }
+
+    /// Removes the cached value for `key` if `stale` says it should go.
+    pub fn remove_if(&self, key: &K, stale: impl FnOnce(&V) -> bool) {
+        let entries = &mut self.entries.lock().unwrap().map;
+        if entries.get(key).is_some_and(|(_, value)| stale(value)) {
+            entries.remove(key);
+        }
+    }
+
+    /// Changes the cached value for `key` in place, if it has not yet expired. It expires when it
+    /// would have anyway.
+    pub fn update(&self, key: &K, f: impl FnOnce(&mut V)) {
+        let entries = &mut self.entries.lock().unwrap().map;
+        if let Some((inserted, value)) = entries.get_mut(key) {
+            if inserted.elapsed() < self.ttl {
+                f(value);
+            }
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+
+        // Sweep out anything that has expired, so that entries that are never looked up
+        // again don't stick around forever. Sweeping only once the map has doubled in size
+        // since the last sweep keeps insertions cheap, even when listing a huge folder fills
+        // the cache with an entry per file.
+        if entries.map.len() >= entries.sweep_at {
+            let ttl = self.ttl;
+            entries
+                .map
+                .retain(|_, (inserted, _)| inserted.elapsed() < ttl);
+            entries.sweep_at = (entries.map.len() * 2).max(1);
+        }
+
+        entries.map.insert(key, (Instant::now(), value));
+    }
+}
+
+/// Called with a blob's name and its new ETag.
+type Invalidator = Box<dyn Fn(&str, &Etag) + Send + Sync>;
+
+/// Keeps the caches of a mount coherent with each other as blobs change.
+///
+/// Every ETag seen for a blob, whether in a listing, a lookup or a download, is reported here.
+/// When it isn't the one last seen for the blob, each registered cache is told, and drops whatever
+/// it holds from another version of the blob.
+pub struct CacheCoordinator {
+    /// The last ETag seen for each recently seen blob.
+    etags: Mutex<LruCache<String, Etag>>,
+    caches: Vec<Invalidator>,
+}
+
+impl CacheCoordinator {
+    /// Creates a coordinator that remembers the ETags of up to `capacity` blobs.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            etags: Mutex::new(LruCache::new(capacity)),
+            caches: Vec::new(),
+        }
+    }
+
+    /// Registers a cache to be told about new ETags.
+    pub fn register(&mut self, invalidate: impl Fn(&str, &Etag) + Send + Sync + 'static) {
+        self.caches.push(Box::new(invalidate));
+    }
+
+    /// Records the ETag seen for a blob, telling every cache if it's new.
+    pub fn observe(&self, blob: &str, etag: &Etag) {
+        let last = self
+            .etags
+            .lock()
+            .unwrap()
+            .put(blob.to_string(), etag.clone());
+        if last.as_ref() == Some(etag) {
+            return;
+        }
+
+        for invalidate in &self.caches {
+            invalidate(blob, etag);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! A liveness probe for a running mount (`razmount check <path>`).
+//!
+//! The probe goes through the mounted file system, as any application would, rather than through
+//! Azure, so it needs no credentials. It stats the mount root and reads the start of a file in it;
+//! if razmount has stopped serving the mount, or can no longer reach the container, one of these
+//! fails or hangs.
+
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+/// How much of the file to read. Just enough to prove that contents are being served.
+const PROBE_BYTES: u64 = 4096;
+
+/// Checks that the mount at `root` is serving, reading `file` (relative to the root), or else the
+/// first file in the root. Returns a description of what was checked.
+///
+/// A mount that has stopped responding can block file system calls indefinitely, so the check
+/// fails if it takes longer than `timeout`.
+pub fn check(root: &Path, file: Option<&Path>, timeout: Duration) -> Result<String> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let (root, file) = (root.to_path_buf(), file.map(|f| root.join(f)));
+
+    // N.B: A hung probe can't be cancelled, so its thread is left behind to exit with the process.
+    std::thread::spawn({
+        let root = root.clone();
+        move || tx.send(probe(&root, file))
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(r) => r,
+        Err(_) => bail!("{} did not respond within {timeout:?}", root.display()),
+    }
+}
+
+fn probe(root: &Path, file: Option<PathBuf>) -> Result<String> {
+    let meta =
+        std::fs::metadata(root).with_context(|| format!("failed to stat {}", root.display()))?;
+    if !meta.is_dir() {
+        bail!("{} is not a directory", root.display());
+    }
+
+    let file = match file {
+        Some(file) => file,
+        None => match first_file(root)? {
+            Some(file) => file,
+            None => return Ok(format!("{}: ok (no files to read)", root.display())),
+        },
+    };
+
+    let mut data = Vec::new();
+    std::fs::File::open(&file)
+        .and_then(|f| f.take(PROBE_BYTES).read_to_end(&mut data))
+        .with_context(|| format!("failed to read {}", file.display()))?;
+
+    Ok(format!(
+        "{}: ok (read {} bytes of {})",
+        root.display(),
+        data.len(),
+        file.display()
+    ))
+}
+
+/// Finds a file directly within `dir`, if there is one.
+fn first_file(dir: &Path) -> Result<Option<PathBuf>> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))?;
+
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to list {}", dir.display()))?;
+        if entry.file_type().is_ok_and(|t| t.is_file()) {
+            return Ok(Some(entry.path()));
+        }
+    }
+
+    Ok(None)
+}
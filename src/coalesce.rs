@@ -0,0 +1,155 @@
+//! Coalescing of small reads of the same blob into larger downloads.
+//!
+//! File systems often split a read into many small, adjacent requests that arrive at about the
+//! same time. The first read of a blob opens a batch and waits a short while before downloading it;
+//! reads that arrive in the meantime and touch the batch's range (without growing it past a size
+//! limit) join it, and are handed their part of the one download once it completes.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use azure_core::Etag;
+
+/// A downloaded range and the ETag of the blob it came from.
+type Downloaded = Arc<(Option<Etag>, Vec<u8>)>;
+
+/// The outcome of a batch's download. I/O errors can't be cloned, so joined reads are handed
+/// back a copy of the error's kind and message.
+type Outcome = Result<Downloaded, (std::io::ErrorKind, String)>;
+
+struct Range {
+    start: u64,
+    end: u64,
+    outcome: Option<Outcome>,
+}
+
+struct Batch {
+    range: Mutex<Range>,
+    done: Condvar,
+}
+
+pub struct Coalescer {
+    window: Duration,
+    max_bytes: u64,
+    /// The batches of each blob that are still open to be joined.
+    open: Mutex<HashMap<String, Vec<Arc<Batch>>>>,
+}
+
+impl Coalescer {
+    /// Creates a coalescer that holds batches open for `window`, and doesn't grow them past
+    /// `max_bytes`.
+    pub fn new(window: Duration, max_bytes: u64) -> Self {
+        Self {
+            window,
+            max_bytes,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `len` bytes of a blob at `offset`, as part of a larger download if other reads of the
+    /// blob come along in time. `fetch` downloads a range, and is only called if this read opens
+    /// a batch.
+    ///
+    /// As with a download, the result is shorter than requested if the range extends past the
+    /// end of the blob.
+    pub fn read(
+        &self,
+        blob: &str,
+        offset: u64,
+        len: usize,
+        fetch: impl FnOnce(u64, usize) -> std::io::Result<(Option<Etag>, Vec<u8>)>,
+    ) -> std::io::Result<(Option<Etag>, Vec<u8>)> {
+        let end = offset + len as u64;
+
+        let (batch, opened) = {
+            let mut open = self.open.lock().unwrap();
+            let batches = open.entry(blob.to_string()).or_default();
+
+            let joined = batches.iter().find(|b| {
+                let mut range = b.range.lock().unwrap();
+                let (start, stop) = (range.start.min(offset), range.end.max(end));
+
+                // Adjacent ranges are joined as well as overlapping ones.
+                let fits = offset <= range.end && end >= range.start;
+                if fits && stop - start <= self.max_bytes {
+                    (range.start, range.end) = (start, stop);
+                    true
+                } else {
+                    false
+                }
+            });
+
+            match joined {
+                Some(batch) => (batch.clone(), false),
+                None => {
+                    let batch = Arc::new(Batch {
+                        range: Mutex::new(Range {
+                            start: offset,
+                            end,
+                            outcome: None,
+                        }),
+                        done: Condvar::new(),
+                    });
+
+                    batches.push(batch.clone());
+                    (batch, true)
+                }
+            }
+        };
+
+        if opened {
+            std::thread::sleep(self.window);
+            self.download(blob, &batch, fetch);
+        }
+
+        let mut range = batch.range.lock().unwrap();
+        while range.outcome.is_none() {
+            range = batch.done.wait(range).unwrap();
+        }
+
+        match range.outcome.as_ref().unwrap() {
+            Ok(downloaded) => {
+                let (etag, data) = &**downloaded;
+                let from = std::cmp::min((offset - range.start) as usize, data.len());
+                let to = std::cmp::min(from + len, data.len());
+
+                Ok((etag.clone(), data[from..to].to_vec()))
+            }
+            Err((kind, message)) => Err(std::io::Error::new(*kind, message.clone())),
+        }
+    }
+
+    /// Closes a batch to further reads, and downloads everything its reads asked for.
+    fn download(
+        &self,
+        blob: &str,
+        batch: &Arc<Batch>,
+        fetch: impl FnOnce(u64, usize) -> std::io::Result<(Option<Etag>, Vec<u8>)>,
+    ) {
+        {
+            let mut open = self.open.lock().unwrap();
+            if let Some(batches) = open.get_mut(blob) {
+                batches.retain(|b| !Arc::ptr_eq(b, batch));
+                if batches.is_empty() {
+                    open.remove(blob);
+                }
+            }
+        }
+
+        // The batch can no longer be joined, so its range is final.
+        let (start, end) = {
+            let range = batch.range.lock().unwrap();
+            (range.start, range.end)
+        };
+
+        let outcome = fetch(start, (end - start) as usize)
+            .map(Arc::new)
+            .map_err(|e| (e.kind(), e.to_string()));
+
+        batch.range.lock().unwrap().outcome = Some(outcome);
+        batch.done.notify_all();
+    }
+}
@@ -0,0 +1,70 @@
+//! Virtual files made of several blobs, for `--concat`.
+//!
+//! Datasets are often uploaded as a series of parts (`part-0000`, `part-0001`, ...) that only
+//! make sense together. A concatenation projects them as a single file, made of the parts one
+//! after the other in name order. The parts and their sizes are listed once, the first time the
+//! file is looked up, and each read of the file is split into reads of the parts it spans.
+
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+struct Part {
+    /// The path of the part, relative to the mount root.
+    virt_path: PathBuf,
+    /// Where the part starts in the concatenation.
+    start: u64,
+    len: u64,
+}
+
+pub struct Parts {
+    parts: Vec<Part>,
+}
+
+impl Parts {
+    /// Lays out parts, given by their paths and sizes, one after the other.
+    pub fn new(parts: impl IntoIterator<Item = (PathBuf, u64)>) -> Self {
+        let mut start = 0;
+        let parts = parts
+            .into_iter()
+            .map(|(virt_path, len)| {
+                let part = Part {
+                    virt_path,
+                    start,
+                    len,
+                };
+                start += len;
+                part
+            })
+            .collect();
+
+        Self { parts }
+    }
+
+    /// The total size of the parts.
+    pub fn len(&self) -> u64 {
+        self.parts.last().map_or(0, |p| p.start + p.len)
+    }
+
+    /// Splits a read of `len` bytes at `offset` into reads of the parts it spans, each given by
+    /// the part's path, the offset to read at within it, and the range of the buffer it fills.
+    /// The read must not extend past the end of the last part.
+    pub fn split(&self, offset: u64, len: usize) -> Vec<(&Path, u64, Range<usize>)> {
+        let end = offset + len as u64;
+
+        // The first part that ends after the read starts, skipping empty parts.
+        let first = self.parts.partition_point(|p| p.start + p.len <= offset);
+        self.parts[first..]
+            .iter()
+            .take_while(|p| p.start < end)
+            .filter(|p| p.len != 0)
+            .map(|p| {
+                let from = offset.max(p.start);
+                let to = end.min(p.start + p.len);
+                let buf = (from - offset) as usize..(to - offset) as usize;
+                (p.virt_path.as_path(), from - p.start, buf)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,101 @@
+//! Mount definitions loaded from a TOML file, e.g.
+//!
+//! ```toml
+//! [[mount]]
+//! path = 'C:\mnt\datasets'
+//! url = "https://account.blob.core.windows.net/datasets"
+//! auth = "aad"
+//! prefix = "2024"
+//! cache_dir = 'C:\cache\datasets'
+//! ```
+
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use url::Url;
+
+use razmount::error::RazmountError;
+
+use crate::AuthMode;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "mount")]
+    pub mounts: Vec<MountEntry>,
+}
+
+/// A single container to mount. Unset fields take their values from the command line.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountEntry {
+    pub path: PathBuf,
+    pub url: Option<Url>,
+    pub connection_string: Option<String>,
+    pub container: Option<String>,
+    pub prefix: Option<String>,
+    pub auth: Option<AuthMode>,
+    pub account: Option<String>,
+    pub account_key: Option<String>,
+    pub anonymous: Option<bool>,
+    pub sas_file: Option<PathBuf>,
+    pub dir_cache_size: Option<NonZeroUsize>,
+    pub listing_ttl_secs: Option<u64>,
+    pub metadata_ttl_secs: Option<u64>,
+    pub cache_dir: Option<PathBuf>,
+    pub cache_max_bytes: Option<u64>,
+    pub readahead_bytes: Option<u64>,
+    pub block_size: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    pub pin_etags: Option<bool>,
+    pub hns: Option<bool>,
+    pub delimiter: Option<char>,
+    pub offline: Option<bool>,
+    pub offline_after: Option<NonZeroU32>,
+    pub name: Option<String>,
+    pub tag_filter: Option<String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, RazmountError> {
+        let invalid = |e: &dyn std::fmt::Display| {
+            RazmountError::Config(format!("invalid config file {}: {e}", path.display()))
+        };
+
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            RazmountError::Config(format!(
+                "failed to read config file {}: {e}",
+                path.display()
+            ))
+        })?;
+        let config: Self = toml::from_str(&text).map_err(|e| invalid(&e))?;
+
+        for (i, m) in config.mounts.iter().enumerate() {
+            let name = format!("mount #{} (`{}`)", i + 1, m.path.display());
+
+            match (&m.url, &m.connection_string) {
+                (Some(_), None) | (None, Some(_)) => {}
+                _ => {
+                    return Err(invalid(&format!(
+                        "{name}: specify exactly one of `url` or `connection_string`"
+                    )))
+                }
+            }
+
+            if m.connection_string.is_some() && m.container.is_none() {
+                return Err(invalid(&format!(
+                    "{name}: `container` is required with `connection_string`"
+                )));
+            }
+
+            if let Some(account) = &m.account {
+                crate::parse_account_name(account).map_err(|e| invalid(&format!("{name}: {e}")))?;
+            }
+        }
+
+        Ok(config)
+    }
+}
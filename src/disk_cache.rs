@@ -0,0 +1,306 @@
+//! An on-disk cache of blob ranges.
+//!
+//! Each cached block is stored as a single file named after a hash of the blob name, a hash of the
+//! blob's ETag at the time the block was downloaded, and the (end-exclusive) range it covers.
+//! Blocks are only served once the driver has observed the blob's current ETag, so stale data is
+//! never returned after a blob changes, and blocks from a previous run are picked back up on start.
+//!
+//! The cache also keeps the last listing of each directory, so that directories that were
+//! browsed before can still be browsed without a connection.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{info, warn};
+use lru::LruCache;
+
+const BLOCK_EXTENSION: &str = "blk";
+const LISTING_EXTENSION: &str = "lst";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BlockKey {
+    blob: u64,
+    etag: u64,
+    start: u64,
+    end: u64,
+}
+
+impl BlockKey {
+    fn file_name(&self) -> String {
+        format!(
+            "{:016x}-{:016x}-{}-{}.{BLOCK_EXTENSION}",
+            self.blob, self.etag, self.start, self.end
+        )
+    }
+
+    fn parse(file_name: &str) -> Option<Self> {
+        let stem = file_name.strip_suffix(BLOCK_EXTENSION)?.strip_suffix('.')?;
+        let mut parts = stem.split('-');
+
+        let key = Self {
+            blob: u64::from_str_radix(parts.next()?, 16).ok()?,
+            etag: u64::from_str_radix(parts.next()?, 16).ok()?,
+            start: parts.next()?.parse().ok()?,
+            end: parts.next()?.parse().ok()?,
+        };
+
+        (parts.next().is_none() && key.start < key.end).then_some(key)
+    }
+
+    fn contains(&self, start: u64, end: u64) -> bool {
+        self.start <= start && end <= self.end
+    }
+}
+
+struct State {
+    /// Cached blocks and their sizes, in least- to most-recently used order.
+    blocks: LruCache<BlockKey, u64>,
+    /// Cached blocks, indexed by blob.
+    by_blob: HashMap<u64, HashSet<BlockKey>>,
+    /// The most recently observed ETag of each blob.
+    etags: HashMap<u64, u64>,
+    /// The total size of all cached blocks.
+    total: u64,
+}
+
+impl State {
+    fn add(&mut self, key: BlockKey, size: u64) {
+        if let Some(old) = self.blocks.put(key, size) {
+            self.total -= old;
+        }
+
+        self.total += size;
+        self.by_blob.entry(key.blob).or_default().insert(key);
+    }
+
+    fn remove(&mut self, key: &BlockKey) {
+        if let Some(size) = self.blocks.pop(key) {
+            self.total -= size;
+        }
+
+        if let Some(blocks) = self.by_blob.get_mut(&key.blob) {
+            blocks.remove(key);
+            if blocks.is_empty() {
+                self.by_blob.remove(&key.blob);
+            }
+        }
+    }
+}
+
+pub struct DiskCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+impl DiskCache {
+    /// Opens (or creates) a cache in `dir`, picking up any blocks left by a previous run.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut existing = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let Some(key) = entry.file_name().to_str().and_then(BlockKey::parse) else {
+                continue;
+            };
+
+            let meta = entry.metadata()?;
+            existing.push((meta.modified().ok(), key, meta.len()));
+        }
+
+        // Oldest first, so that the most recently written blocks are the last to be evicted.
+        existing.sort_by_key(|(modified, _, _)| *modified);
+
+        let cache = Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(State {
+                blocks: LruCache::unbounded(),
+                by_blob: HashMap::new(),
+                etags: HashMap::new(),
+                total: 0,
+            }),
+        };
+
+        {
+            let mut state = cache.state.lock().unwrap();
+            for (_, key, size) in existing {
+                state.add(key, size);
+            }
+
+            info!(
+                "disk cache: {} blocks, {} bytes",
+                state.blocks.len(),
+                state.total
+            );
+            cache.evict(&mut state);
+        }
+
+        Ok(cache)
+    }
+
+    /// Records the current ETag for a blob, discarding any blocks downloaded at another ETag.
+    pub fn observe_etag(&self, blob: &str, etag: &str) {
+        let (blob, etag) = (hash(blob), hash(etag));
+        let mut state = self.state.lock().unwrap();
+
+        if state.etags.insert(blob, etag) == Some(etag) {
+            return;
+        }
+
+        let stale = state
+            .by_blob
+            .get(&blob)
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .filter(|k| k.etag != etag)
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        for key in stale {
+            state.remove(&key);
+            self.delete(&key);
+        }
+    }
+
+    /// Fills `buf` with the blob's contents at `offset` if a single cached block covers it.
+    pub fn get(&self, blob: &str, offset: u64, buf: &mut [u8]) -> bool {
+        let blob = hash(blob);
+        let end = offset + buf.len() as u64;
+
+        let key = {
+            let mut state = self.state.lock().unwrap();
+
+            // Without a known ETag, there's no way to tell if the cached blocks are stale.
+            let Some(&etag) = state.etags.get(&blob) else {
+                return false;
+            };
+
+            let key = state.by_blob.get(&blob).and_then(|blocks| {
+                blocks
+                    .iter()
+                    .find(|k| k.etag == etag && k.contains(offset, end))
+                    .copied()
+            });
+
+            match key {
+                Some(key) => {
+                    state.blocks.promote(&key);
+                    key
+                }
+                None => return false,
+            }
+        };
+
+        let r = File::open(self.dir.join(key.file_name())).and_then(|mut f| {
+            f.seek(SeekFrom::Start(offset - key.start))?;
+            f.read_exact(buf)
+        });
+
+        match r {
+            Ok(()) => true,
+            Err(e) => {
+                // Most likely evicted by another thread in the meantime.
+                warn!("failed to read cached block {}: {e}", key.file_name());
+                self.state.lock().unwrap().remove(&key);
+                false
+            }
+        }
+    }
+
+    /// Stores a block of a blob, downloaded while the blob had the given ETag.
+    pub fn insert(&self, blob: &str, etag: &str, offset: u64, data: &[u8]) {
+        let size = data.len() as u64;
+        if size == 0 || size > self.max_bytes {
+            return;
+        }
+
+        let key = BlockKey {
+            blob: hash(blob),
+            etag: hash(etag),
+            start: offset,
+            end: offset + size,
+        };
+
+        let path = self.dir.join(key.file_name());
+        if let Err(e) = write_file(&path, data) {
+            warn!("failed to write cached block {}: {e}", path.display());
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.add(key, size);
+        self.evict(&mut state);
+    }
+
+    /// Stores the listing of a directory, replacing the last one. Listings are small, and don't
+    /// count towards the size limit.
+    pub fn insert_listing(&self, dir: &str, data: &[u8]) {
+        let path = self.listing_path(dir);
+        if let Err(e) = write_file(&path, data) {
+            warn!("failed to write cached listing {}: {e}", path.display());
+        }
+    }
+
+    /// Returns the last listing stored for a directory, however old it is.
+    pub fn get_listing(&self, dir: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.listing_path(dir)).ok()
+    }
+
+    fn listing_path(&self, dir: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.{LISTING_EXTENSION}", hash(dir)))
+    }
+
+    /// Evicts least recently used blocks until the cache is within its size limit.
+    fn evict(&self, state: &mut State) {
+        while state.total > self.max_bytes {
+            let Some((key, _)) = state.blocks.peek_lru() else {
+                break;
+            };
+
+            let key = *key;
+            state.remove(&key);
+            self.delete(&key);
+        }
+    }
+
+    fn delete(&self, key: &BlockKey) {
+        let path = self.dir.join(key.file_name());
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("failed to remove cached block {}: {e}", path.display());
+        }
+    }
+}
+
+/// Writes a file by way of a temporary one, so that a crash never leaves a truncated file behind.
+fn write_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    let r = File::create(&tmp)
+        .and_then(|mut f| f.write_all(data))
+        .and_then(|_| std::fs::rename(&tmp, path));
+
+    if r.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+
+    r
+}
+
+/// A stable 64-bit FNV-1a hash, so that file names remain valid across runs and builds.
+fn hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
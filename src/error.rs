@@ -0,0 +1,109 @@
+//! The errors the driver fails with, classified by cause, so that callers can tell a missing blob
+//! apart from a throttled request (say) without picking apart messages.
+//!
+//! Failures of requests to Azure are classified in one place, [`RazmountError::from`]. File system
+//! callbacks can only return a [`std::io::Error`], so the backend converts into one at that
+//! boundary, with the closest matching [`std::io::ErrorKind`]. The original error is kept inside
+//! it, and can be recovered with `e.get_ref().and_then(|e| e.downcast_ref::<RazmountError>())`.
+
+use azure_core::StatusCode;
+
+/// The underlying error, for the variants that have one.
+pub type Cause = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RazmountError {
+    /// The container, or a blob in it, doesn't exist (or isn't available offline).
+    #[error(transparent)]
+    NotFound(Cause),
+    /// The credentials don't permit the request.
+    #[error(transparent)]
+    PermissionDenied(Cause),
+    /// Azure asked for requests to slow down, or is temporarily unavailable. Worth retrying.
+    #[error(transparent)]
+    Throttled(Cause),
+    /// A request never reached Azure, e.g. because it failed to connect or timed out.
+    #[error(transparent)]
+    Network(Cause),
+    /// Any other failure of a request to blob storage.
+    #[error(transparent)]
+    Storage(Cause),
+    /// A value given on the command line couldn't be parsed.
+    #[error("{0}")]
+    Parse(String),
+    /// The mount's configuration is invalid, or can't be loaded.
+    #[error("{0}")]
+    Config(String),
+}
+
+impl RazmountError {
+    /// The Azure error behind this one, if it came from a request.
+    pub fn azure(&self) -> Option<&azure_core::Error> {
+        match self {
+            Self::NotFound(e)
+            | Self::PermissionDenied(e)
+            | Self::Throttled(e)
+            | Self::Network(e)
+            | Self::Storage(e) => e.downcast_ref(),
+            Self::Parse(_) | Self::Config(_) => None,
+        }
+    }
+}
+
+impl From<azure_core::Error> for RazmountError {
+    fn from(e: azure_core::Error) -> Self {
+        match e.kind() {
+            azure_core::error::ErrorKind::HttpResponse { status, .. } => match *status {
+                StatusCode::NotFound => Self::NotFound(e.into()),
+                StatusCode::Forbidden | StatusCode::Unauthorized => {
+                    Self::PermissionDenied(e.into())
+                }
+                StatusCode::TooManyRequests | StatusCode::ServiceUnavailable => {
+                    Self::Throttled(e.into())
+                }
+                _ => Self::Storage(e.into()),
+            },
+            azure_core::error::ErrorKind::Io => Self::Network(e.into()),
+            _ => Self::Storage(e.into()),
+        }
+    }
+}
+
+/// Converts into an I/O error, preserving the meaning of the failure so that the file system (and
+/// applications) can tell a missing blob apart from a transient failure.
+impl From<RazmountError> for std::io::Error {
+    fn from(e: RazmountError) -> Self {
+        use std::io::ErrorKind;
+
+        let kind = match &e {
+            RazmountError::NotFound(_) => ErrorKind::NotFound,
+            RazmountError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            // Throttling and temporary outages are worth retrying later, but not straight away in
+            // a loop, as `Interrupted` (`EINTR`) would be by `read_exact` and the like. That they
+            // are is left to the `Throttled` variant inside.
+            RazmountError::Throttled(_) => ErrorKind::Other,
+            // An attempt that timed out, and was retried until the SDK gave up.
+            RazmountError::Network(cause) if timed_out(cause.as_ref()) => ErrorKind::TimedOut,
+            RazmountError::Parse(_) | RazmountError::Config(_) => ErrorKind::InvalidInput,
+            RazmountError::Network(_) | RazmountError::Storage(_) => ErrorKind::Other,
+        };
+
+        std::io::Error::new(kind, e)
+    }
+}
+
+/// Determines if an error was caused by an I/O timeout, however deeply it has been wrapped.
+fn timed_out(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if e.kind() == std::io::ErrorKind::TimedOut {
+                return true;
+            }
+        }
+
+        source = e.source();
+    }
+
+    false
+}
@@ -0,0 +1,61 @@
+//! Detection of the blobs that were added, changed or deleted between two listings of a container,
+//! for `--follow`.
+//!
+//! A followed mount lists everything under its prefix at a fixed interval, and keeps the name and
+//! ETag of each blob it saw. Comparing one listing against the last tells the mount which of its
+//! cached listings and properties no longer match the container.
+
+use std::collections::HashMap;
+
+use azure_core::Etag;
+
+/// The ETag of every blob in a listing, by name.
+pub type Snapshot = HashMap<String, Etag>;
+
+/// What changed between two snapshots.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Blobs that are new, along with their ETags.
+    pub added: Vec<(String, Etag)>,
+    /// Blobs that were overwritten, along with their new ETags.
+    pub changed: Vec<(String, Etag)>,
+    pub deleted: Vec<String>,
+}
+
+impl Changes {
+    pub fn between(before: &Snapshot, after: &Snapshot) -> Self {
+        let mut changes = Self::default();
+
+        for (name, etag) in after {
+            match before.get(name) {
+                None => changes.added.push((name.clone(), etag.clone())),
+                Some(old) if old != etag => changes.changed.push((name.clone(), etag.clone())),
+                Some(_) => {}
+            }
+        }
+
+        changes.deleted = before
+            .keys()
+            .filter(|name| !after.contains_key(*name))
+            .cloned()
+            .collect();
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.deleted.is_empty()
+    }
+}
+
+impl std::fmt::Display for Changes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} changed, {} deleted",
+            self.added.len(),
+            self.changed.len(),
+            self.deleted.len()
+        )
+    }
+}
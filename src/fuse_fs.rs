@@ -0,0 +1,389 @@
+//! Mounts a [`BlobBackend`] as a read-only FUSE file system.
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyXattr, Request,
+};
+
+use crate::backend::{BlobBackend, Entry, Listing};
+
+/// How long the kernel may cache attributes and lookups for. The backend caches these itself, so
+/// this only needs to cover bursts of calls for the same file.
+const TTL: Duration = Duration::from_secs(1);
+
+const ROOT_INO: u64 = fuser::FUSE_ROOT_ID;
+
+/// The extended attribute that exposes a blob's properties, like the `azmeta` stream on Windows.
+const META_XATTR: &str = "user.azmeta";
+
+pub type Mounted = fuser::BackgroundSession;
+
+/// Starts serving `backend` on `path`, which must already exist.
+pub fn mount(path: &Path, backend: BlobBackend) -> Result<Mounted> {
+    let fs = BlobFuse {
+        backend: Arc::new(backend),
+        paths: vec![PathBuf::new()],
+        inodes: HashMap::from([(PathBuf::new(), ROOT_INO)]),
+        dirs: HashMap::new(),
+        next_fh: 1,
+    };
+
+    let options = [MountOption::RO, MountOption::FSName("razmount".into())];
+    fuser::spawn_mount2(fs, path, &options).context("failed to mount the FUSE file system")
+}
+
+/// Unmounts the file system on `path`, e.g. one left behind by a razmount that was killed, so
+/// that the directory can be mounted again or deleted.
+///
+/// Returns whether there was anything to unmount, which there isn't if `path` doesn't exist.
+/// `path` must be absolute.
+pub fn unmount(path: &Path) -> Result<bool> {
+    // Once the process serving it is gone, every call on the mount point fails with ENOTCONN.
+    match std::fs::metadata(path) {
+        Ok(meta) => {
+            let parent = path.parent().unwrap_or(path);
+            let parent = std::fs::metadata(parent)
+                .with_context(|| format!("failed to stat {}", parent.display()))?;
+            if meta.dev() == parent.dev() {
+                return Ok(false);
+            }
+        }
+        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("failed to stat {}", path.display())),
+    }
+
+    // Unprivileged users can only unmount through the setuid helper of either version of FUSE.
+    for helper in ["fusermount3", "fusermount"] {
+        match std::process::Command::new(helper)
+            .arg("-u")
+            .arg(path)
+            .status()
+        {
+            Ok(status) if status.success() => return Ok(true),
+            Ok(status) => bail!("{helper} failed to unmount {}: {status}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to run {helper}")),
+        }
+    }
+
+    bail!(
+        "failed to unmount {}: fusermount isn't installed",
+        path.display()
+    )
+}
+
+/// Drive letters only exist on Windows, so there is never a mapping to one.
+pub enum DriveMapping {}
+
+impl Drop for DriveMapping {
+    fn drop(&mut self) {
+        match *self {}
+    }
+}
+
+/// Fails, since drive letters only exist on Windows.
+pub fn map_drive(drive: &str, _path: &Path) -> Result<DriveMapping> {
+    bail!("cannot map drive {drive}: drive letters only exist on Windows; mount onto a directory instead")
+}
+
+struct BlobFuse {
+    backend: Arc<BlobBackend>,
+    /// The path, relative to the mount root, of each inode handed out so far. Inode `n` is at
+    /// index `n - 1`. Inodes are never forgotten, so that their numbers stay stable.
+    paths: Vec<PathBuf>,
+    inodes: HashMap<PathBuf, u64>,
+    /// The listings of the open directories, by file handle.
+    dirs: HashMap<u64, OpenDir>,
+    next_fh: u64,
+}
+
+/// An open directory, whose listing is read a batch of entries at a time as the kernel asks for
+/// them, rather than listed again for every batch.
+struct OpenDir {
+    path: PathBuf,
+    listing: Listing,
+    /// The offset of the next entry to hand out, counting `.` and `..`.
+    next: i64,
+    /// An entry taken from the listing that didn't fit in the last reply.
+    held: Option<Entry>,
+}
+
+impl BlobFuse {
+    fn path(&self, ino: u64) -> Result<PathBuf, i32> {
+        let index = usize::try_from(ino).ok().and_then(|i| i.checked_sub(1));
+        index
+            .and_then(|i| self.paths.get(i))
+            .cloned()
+            .ok_or(libc::ENOENT)
+    }
+
+    fn inode(&mut self, path: PathBuf) -> u64 {
+        if let Some(&ino) = self.inodes.get(&path) {
+            return ino;
+        }
+
+        self.paths.push(path.clone());
+        let ino = self.paths.len() as u64;
+        self.inodes.insert(path, ino);
+
+        ino
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Entry, i32> {
+        self.backend.metadata(path).map_err(|e| errno(&e))
+    }
+}
+
+/// Answers an extended attribute request with `data`, or just its size if `size` is zero.
+fn reply_xattr(reply: ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() <= size as usize {
+        reply.data(data);
+    } else {
+        reply.error(libc::ERANGE);
+    }
+}
+
+/// Maps an I/O error from the backend onto the closest `errno` value.
+fn errno(e: &std::io::Error) -> i32 {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => libc::ENOENT,
+        std::io::ErrorKind::PermissionDenied => libc::EACCES,
+        std::io::ErrorKind::WouldBlock => libc::EAGAIN,
+        std::io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+        _ => libc::EIO,
+    }
+}
+
+fn kind(entry: &Entry) -> FileType {
+    if entry.is_dir {
+        FileType::Directory
+    } else {
+        FileType::RegularFile
+    }
+}
+
+fn attr(req: &Request<'_>, ino: u64, entry: &Entry) -> FileAttr {
+    // Blobs have no separate change time, so a change to a blob's metadata counts as a write, as
+    // it does for the blob's last-modified time.
+    let modified = entry.modified.unwrap_or(UNIX_EPOCH);
+
+    FileAttr {
+        ino,
+        size: entry.size,
+        blocks: entry.size.div_ceil(512),
+        atime: entry.fetched,
+        mtime: modified,
+        ctime: modified,
+        crtime: entry.created.unwrap_or(UNIX_EPOCH),
+        kind: kind(entry),
+        perm: if entry.is_dir { 0o555 } else { 0o444 },
+        nlink: if entry.is_dir { 2 } else { 1 },
+        uid: req.uid(),
+        gid: req.gid(),
+        rdev: 0,
+        blksize: 4096,
+        flags: 0,
+    }
+}
+
+impl Filesystem for BlobFuse {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let path = match self.path(parent) {
+            Ok(parent) => parent.join(name),
+            Err(e) => return reply.error(e),
+        };
+
+        match self.metadata(&path) {
+            Ok(entry) => {
+                let ino = self.inode(path);
+                reply.entry(&TTL, &attr(req, ino, &entry), 0);
+            }
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.path(ino).and_then(|path| self.metadata(&path)) {
+            Ok(entry) => reply.attr(&TTL, &attr(req, ino, &entry)),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, _ino: u64, flags: i32, reply: ReplyOpen) {
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+
+        reply.opened(0, 0);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+
+        let entry = match self.metadata(&path) {
+            Ok(entry) => entry,
+            Err(e) => return reply.error(e),
+        };
+
+        // The backend fills the whole buffer, so reads are cut off at the end of the file.
+        let offset = offset.max(0) as u64;
+        let len = entry.size.saturating_sub(offset).min(size.into()) as usize;
+        let mut buf = vec![0; len];
+
+        match self.backend.read(&path, offset, &mut buf) {
+            Ok(()) => reply.data(&buf),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn opendir(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        let path = match self.path(ino) {
+            Ok(path) => path,
+            Err(e) => return reply.error(e),
+        };
+
+        let listing = match self.backend.enumerate(&path, None) {
+            Ok(listing) => listing,
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.dirs.insert(
+            fh,
+            OpenDir {
+                path,
+                listing,
+                next: 0,
+                held: None,
+            },
+        );
+
+        reply.opened(fh, 0);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(mut dir) = self.dirs.remove(&fh) else {
+            return reply.error(libc::EBADF);
+        };
+
+        // `offset` is the position after the last entry the kernel has already seen. It only
+        // goes back if the directory is rewound, which lists it again from the start.
+        if offset != dir.next {
+            match self.backend.enumerate(&dir.path, None) {
+                Ok(listing) => dir.listing = listing,
+                Err(e) => {
+                    self.dirs.insert(fh, dir);
+                    return reply.error(errno(&e));
+                }
+            }
+            dir.next = 0;
+            dir.held = None;
+        }
+
+        let parent = match dir.path.parent() {
+            Some(parent) => self.inode(parent.to_path_buf()),
+            None => ROOT_INO,
+        };
+
+        loop {
+            let (child, kind, name, entry) = match dir.next {
+                0 => (ino, FileType::Directory, ".".to_string(), None),
+                1 => (parent, FileType::Directory, "..".to_string(), None),
+                _ => match dir.held.take().or_else(|| dir.listing.next()) {
+                    Some(entry) => {
+                        let child = self.inode(dir.path.join(&entry.name));
+                        (child, kind(&entry), entry.name.clone(), Some(entry))
+                    }
+                    None => break,
+                },
+            };
+
+            // Entries before `offset` are skipped over, after the directory is listed again.
+            if dir.next >= offset && reply.add(child, dir.next + 1, kind, name) {
+                dir.held = entry;
+                break;
+            }
+            dir.next += 1;
+        }
+
+        self.dirs.insert(fh, dir);
+        reply.ok();
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.dirs.remove(&fh);
+        reply.ok();
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: ReplyXattr,
+    ) {
+        let path = match self
+            .path(ino)
+            .and_then(|path| Ok((self.metadata(&path)?, path)))
+        {
+            Ok((entry, path)) if !entry.is_dir && name == META_XATTR => path,
+            Ok(_) => return reply.error(libc::ENODATA),
+            Err(e) => return reply.error(e),
+        };
+
+        match self.backend.meta_stream(&path) {
+            Ok(doc) => reply_xattr(reply, size, &doc),
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
+        match self.path(ino).and_then(|path| self.metadata(&path)) {
+            Ok(entry) if entry.is_dir => reply_xattr(reply, size, &[]),
+            Ok(_) => reply_xattr(reply, size, format!("{META_XATTR}\0").as_bytes()),
+            Err(e) => reply.error(e),
+        }
+    }
+}
@@ -0,0 +1,332 @@
+//! Mounts an Azure blob storage container into a folder: with the Projected File System on
+//! Windows, and with FUSE elsewhere.
+//!
+//! The `razmount` command line is a thin wrapper over this library. Applications can start a mount
+//! of their own with [`mount_blob_container`], or put one together from a [`BlobBackend`] and
+//! [`platform::mount`] for finer control.
+
+pub mod backend;
+mod breaker;
+mod cache;
+mod coalesce;
+mod concat;
+mod disk_cache;
+pub mod error;
+mod follow;
+#[cfg(unix)]
+pub mod fuse_fs;
+pub mod logging;
+mod memory_cache;
+mod pattern;
+#[cfg(windows)]
+pub mod projfs_fs;
+mod readahead;
+pub mod source;
+pub mod stats;
+mod throttle;
+mod verify;
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use azure_storage_blobs::prelude::{ClientBuilder, ContainerClient};
+use log::info;
+
+pub use backend::{BlobBackend, DriverOptions};
+#[cfg(windows)]
+pub use projfs_fs::BlobFSDriver;
+
+/// The file system that mounts are served through on this platform.
+#[cfg(unix)]
+pub use fuse_fs as platform;
+/// The file system that mounts are served through on this platform.
+#[cfg(windows)]
+pub use projfs_fs as platform;
+
+/// A running mount of a container. Dropping it unmounts the container.
+pub struct MountHandle {
+    mounted: Option<platform::Mounted>,
+    /// The runtime that the mount's requests to Azure are dispatched on.
+    rt: Option<tokio::runtime::Runtime>,
+}
+
+impl Drop for MountHandle {
+    fn drop(&mut self) {
+        // Callbacks that are still running need the runtime to complete their requests.
+        drop(self.mounted.take());
+
+        // Unlike dropping it, this doesn't block, so the handle can be dropped in async code.
+        if let Some(rt) = self.rt.take() {
+            rt.shutdown_background();
+        }
+    }
+}
+
+/// What to do while setting up a mount, beyond what the driver's own options cover.
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    /// Whether to check that the container can be listed before mounting it (never done offline).
+    pub probe: bool,
+    /// Log the number and total size of the blobs being mounted before mounting, counting up to
+    /// this many.
+    pub summarize: Option<u64>,
+    /// How often to log the mount's statistics, if at all.
+    pub stats_interval: Option<Duration>,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            probe: true,
+            summarize: None,
+            stats_interval: None,
+        }
+    }
+}
+
+/// Mounts `container` into `path`, which must be an existing directory, until the returned handle
+/// is dropped. The mount dispatches its requests to Azure on a runtime of its own.
+///
+/// Requests are made with `client` as configured (e.g. its credentials, and its transport and
+/// retry options). The command line also bounds each attempt at a request, which
+/// [`source::AttemptTimeout`] can do here too, as a per-retry policy of the client.
+///
+/// Setting up the mount waits on requests to Azure, so this must not be called from async code,
+/// where it would panic: use [`mount_blob_container_async`] there instead.
+pub fn mount_blob_container(
+    path: &Path,
+    client: ClientBuilder,
+    container: impl Into<String>,
+    options: DriverOptions,
+) -> Result<MountHandle> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to build tokio runtime")?;
+
+    let mounted = mount_container(
+        path,
+        client,
+        container.into(),
+        rt.handle(),
+        options,
+        &MountOptions::default(),
+    )?;
+    Ok(MountHandle {
+        mounted: Some(mounted),
+        rt: Some(rt),
+    })
+}
+
+/// Like [`mount_blob_container`], for async code: the mount is set up on a blocking thread.
+pub async fn mount_blob_container_async(
+    path: PathBuf,
+    client: ClientBuilder,
+    container: impl Into<String>,
+    options: DriverOptions,
+) -> Result<MountHandle> {
+    let container = container.into();
+    tokio::task::spawn_blocking(move || mount_blob_container(&path, client, container, options))
+        .await
+        .context("mount setup panicked")?
+}
+
+/// Mounts `container` into `path`, dispatching the mount's requests to Azure on `rt`, and returns
+/// the running file system. This is what both [`mount_blob_container`] and the command line do.
+///
+/// Like [`mount_blob_container`], this waits on requests to Azure, and so must not be called
+/// from async code.
+pub fn mount_container(
+    path: &Path,
+    client: ClientBuilder,
+    container: String,
+    rt: &tokio::runtime::Handle,
+    options: DriverOptions,
+    setup: &MountOptions,
+) -> Result<platform::Mounted> {
+    let offline = options.offline;
+    let source = container_source(client, container, &options, rt)?;
+    let mut backend =
+        BlobBackend::new(source, rt.clone(), options).context("failed to setup driver")?;
+
+    // There's nothing to check without a connection.
+    if setup.probe && !offline {
+        backend.probe()?;
+    }
+
+    backend
+        .detect_namespace()
+        .context("failed to detect a hierarchical namespace")?;
+
+    if let Some(max_blobs) = setup.summarize {
+        let summary = backend
+            .summarize(max_blobs)
+            .with_context(|| format!("failed to summarize {}", path.display()))?;
+        info!("{}: {summary}", path.display());
+    }
+
+    if let Some(period) = setup.stats_interval {
+        let stats = backend.stats();
+        let path = path.display().to_string();
+
+        rt.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+
+            // N.B: The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                info!("stats for {path}: {stats}");
+            }
+        });
+    }
+
+    platform::mount(path, backend)
+}
+
+/// Builds the source that a mount reads its container through, finding the blobs that match the
+/// mount's tag filter, if it has one. Like [`mount_container`], this must not be called from
+/// async code.
+pub fn container_source(
+    client: ClientBuilder,
+    container: String,
+    options: &DriverOptions,
+    rt: &tokio::runtime::Handle,
+) -> Result<source::Delimited<source::TagFiltered<ContainerClient>>> {
+    let service = client.blob_service_client();
+
+    let names = match &options.tag_filter {
+        Some(_) if options.offline => {
+            bail!("--tag-filter can't be used offline: blob tags can only be searched in Azure")
+        }
+        Some(expression) => {
+            let names = rt
+                .block_on(source::find_blobs_by_tags(&service, &container, expression))
+                .with_context(|| {
+                    format!("failed to find blobs matching tag filter `{expression}`")
+                })?;
+            info!(
+                "{} blobs in `{container}` match the tag filter",
+                names.len()
+            );
+            Some(names)
+        }
+        None => None,
+    };
+
+    Ok(source::Delimited::new(
+        source::TagFiltered::new(service.container_client(container), names),
+        options.delimiter,
+    ))
+}
+
+/// Converts a path into its absolute, extended-length form (e.g. `\\?\C:\mnt`), which Windows
+/// doesn't limit to `MAX_PATH` characters. Paths without a drive or UNC prefix, including all
+/// paths on other platforms, are merely made absolute.
+pub fn extended_path(path: &Path) -> std::io::Result<PathBuf> {
+    use std::path::{Component, Prefix};
+
+    let path = std::path::absolute(path)?;
+    let mut components = path.components();
+
+    let mut extended = match components.next() {
+        Some(Component::Prefix(p)) => match p.kind() {
+            Prefix::Disk(_) => {
+                let mut s = std::ffi::OsString::from(r"\\?\");
+                s.push(p.as_os_str());
+                s
+            }
+            Prefix::UNC(server, share) => {
+                let mut s = std::ffi::OsString::from(r"\\?\UNC\");
+                s.push(server);
+                s.push(r"\");
+                s.push(share);
+                s
+            }
+            // Already verbatim (`\\?\`) or a device path (`\\.\`).
+            _ => return Ok(path),
+        },
+        _ => return Ok(path),
+    };
+
+    // Verbatim paths are passed to the file system as-is, so they must use `\` throughout, and
+    // `absolute` has already resolved any `.` and `..` components.
+    let mut separate = false;
+    for c in components {
+        if separate || c == Component::RootDir {
+            extended.push(r"\");
+        }
+
+        if c != Component::RootDir {
+            extended.push(c.as_os_str());
+        }
+        separate = c != Component::RootDir;
+    }
+
+    Ok(PathBuf::from(extended))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_are_made_absolute() {
+        let path = extended_path(Path::new("mnt")).unwrap();
+        assert!(path.is_absolute());
+        assert!(path.ends_with("mnt"));
+    }
+
+    #[test]
+    fn unmounting_leaves_folders_that_are_not_mounted_alone() {
+        let missing = extended_path(Path::new("razmount-test-missing/mnt")).unwrap();
+        assert!(!platform::unmount(&missing).unwrap());
+
+        let dir = std::env::temp_dir().join(format!("razmount-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let unmounted = platform::unmount(&extended_path(&dir).unwrap());
+        std::fs::remove_dir(&dir).unwrap();
+        assert!(!unmounted.unwrap());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn disk_and_unc_paths_are_made_verbatim() {
+        let cases = [
+            (r"C:\mnt\data", r"\\?\C:\mnt\data"),
+            (r"C:/mnt/data", r"\\?\C:\mnt\data"),
+            (r"C:\mnt\.\a\..\data", r"\\?\C:\mnt\data"),
+            (r"C:\", r"\\?\C:\"),
+            (r"\\server\share\data", r"\\?\UNC\server\share\data"),
+            (r"\\server\share", r"\\?\UNC\server\share"),
+            // Already verbatim, or a device.
+            (r"\\?\C:\mnt", r"\\?\C:\mnt"),
+            (r"\\.\PIPE\razmount", r"\\.\PIPE\razmount"),
+        ];
+        for (path, extended) in cases {
+            assert_eq!(
+                extended_path(Path::new(path)).unwrap(),
+                Path::new(extended),
+                "{path}"
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_longer_than_max_path_are_kept_whole() {
+        // What a deeply nested blob key becomes under the mount.
+        let key = ["0123456789abcdef"; 20].join(r"\");
+        let path = Path::new(r"C:\mnt").join(&key).join("data.csv");
+        assert!(path.as_os_str().len() > 260);
+
+        let extended = extended_path(&path).unwrap();
+        assert_eq!(
+            extended.as_os_str(),
+            std::ffi::OsStr::new(&format!(r"\\?\C:\mnt\{key}\data.csv"))
+        );
+    }
+}
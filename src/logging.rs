@@ -0,0 +1,127 @@
+use std::{cell::RefCell, io::Write, sync::Arc, time::Instant};
+
+use log::{info, kv, LevelFilter};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Sets up the global logger. An explicit `level` takes precedence over `RUST_LOG`.
+pub fn init(level: Option<LevelFilter>, format: LogFormat) {
+    let mut builder = match level {
+        Some(level) => {
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(level);
+            builder
+        }
+        None => env_logger::Builder::from_default_env(),
+    };
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut fields = serde_json::Map::new();
+            fields.insert("timestamp".into(), buf.timestamp().to_string().into());
+            fields.insert("level".into(), record.level().as_str().into());
+            fields.insert("target".into(), record.target().into());
+            if let Some(mount) = current_mount() {
+                fields.insert("mount".into(), mount.as_ref().into());
+            }
+            fields.insert("message".into(), record.args().to_string().into());
+
+            // Structured fields (e.g. `op`, `path` and `duration_ms`) are flattened into the object.
+            let _ = record.key_values().visit(&mut JsonFields(&mut fields));
+
+            writeln!(buf, "{}", serde_json::Value::Object(fields))
+        });
+    } else {
+        // As env_logger's own format, with the mount's name in front of the message.
+        builder.format(|buf, record| {
+            let mount = current_mount()
+                .map(|m| format!("{m}: "))
+                .unwrap_or_default();
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {mount}{}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target(),
+                record.args()
+            )
+        });
+    }
+
+    builder.init();
+}
+
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> kv::Visitor<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = serde_json::to_value(&value).unwrap_or_else(|_| value.to_string().into());
+        self.0.insert(key.as_str().to_string(), value);
+
+        Ok(())
+    }
+}
+
+thread_local! {
+    /// The name of the mount that the thread is logging on behalf of, if any.
+    static MOUNT: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// The name of the mount that log lines on this thread are attributed to.
+pub fn current_mount() -> Option<Arc<str>> {
+    MOUNT.with(|m| m.borrow().clone())
+}
+
+/// Attributes log lines on this thread to a mount until it goes out of scope.
+pub struct MountScope(Option<Arc<str>>);
+
+impl MountScope {
+    pub fn enter(mount: Option<Arc<str>>) -> Self {
+        Self(MOUNT.with(|m| m.replace(mount)))
+    }
+}
+
+impl Drop for MountScope {
+    fn drop(&mut self) {
+        MOUNT.with(|m| *m.borrow_mut() = self.0.take());
+    }
+}
+
+/// Logs how long a file system callback took once it goes out of scope. Everything logged in the
+/// meantime is attributed to the mount that the callback is for.
+pub struct Op {
+    op: &'static str,
+    path: String,
+    start: Instant,
+    _mount: MountScope,
+}
+
+impl Op {
+    pub fn start(mount: &Arc<str>, op: &'static str, path: impl Into<String>) -> Self {
+        Self {
+            op,
+            path: path.into(),
+            start: Instant::now(),
+            _mount: MountScope::enter(Some(mount.clone())),
+        }
+    }
+}
+
+impl Drop for Op {
+    fn drop(&mut self) {
+        let duration_ms = self.start.elapsed().as_secs_f64() * 1000.0;
+
+        info!(
+            op = self.op,
+            path = self.path.as_str(),
+            duration_ms = duration_ms;
+            "{} {}: {duration_ms:.1}ms", self.op, self.path
+        );
+    }
+}
@@ -1,366 +1,1601 @@
+mod check;
+mod config;
+
 use std::{
-    collections::HashSet,
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
     path::{Path, PathBuf},
-    sync::Mutex,
+    sync::Arc,
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 
-use azure_storage::StorageCredentials;
-use azure_storage_blobs::{
-    container::operations::BlobItem,
-    prelude::{ClientBuilder, ContainerClient},
+use azure_core::{ClientOptions, ExponentialRetryOptions, RetryOptions, TransportOptions};
+use azure_identity::{AutoRefreshingTokenCredential, DefaultAzureCredential};
+use azure_storage::{
+    CloudLocation, ConnectionString, EndpointProtocol, StorageCredentials, EMULATOR_ACCOUNT,
+};
+use azure_storage_blobs::prelude::{AccessTier, ClientBuilder, ContainerClient};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::{error, info, warn};
+use time::OffsetDateTime;
+use url::{Host, Url};
+
+use config::{Config, MountEntry};
+use razmount::{
+    backend::{
+        BlobBackend, BlobPath, Concat, DriverOptions, LeasePolicy, ModifiedWindow, PointInTime,
+        REQUEST_ATTEMPTS,
+    },
+    error::RazmountError,
+    extended_path,
+    logging::{self, LogFormat},
+    platform,
+    source::{AttemptTimeout, Delimited, TagFiltered},
+    MountOptions,
 };
-use clap::Parser;
-use futures::{StreamExt, TryStreamExt};
-use log::info;
-use projfs::{start_proj_virtualization, FileBasicInfo, ProjFSDirEnum, ProjFSRead};
-use url::Url;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Destination directory to project into
-    path: PathBuf,
+    #[arg(required_unless_present_any = ["mounts", "config"])]
+    path: Option<PathBuf>,
+
+    /// Also make the mount point available as this drive letter (e.g. `Z:`), as `subst` would,
+    /// until razmount exits. Windows only
+    #[arg(long, value_name = "LETTER:", value_parser = parse_drive, requires = "path")]
+    drive: Option<String>,
 
     /// Azure SAS URL
-    url: Url,
+    #[arg(required_unless_present_any = ["connection_string", "mounts", "config"])]
+    url: Option<Url>,
+
+    /// TOML file with `[[mount]]` entries to mount, in addition to any given on the command line
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Additional containers to mount, as `<path>=<url>` (repeatable)
+    #[arg(long = "mount", value_name = "PATH=URL", value_parser = parse_mount)]
+    mounts: Vec<(PathBuf, Url)>,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+
+    #[command(flatten)]
+    transport: TransportArgs,
+
+    /// Azure storage connection string, used in place of a URL
+    #[arg(long, conflicts_with_all = ["url", "account_key", "auth", "anonymous"], requires = "container")]
+    connection_string: Option<String>,
+
+    /// Container to mount, in place of the one in the URL's path (required with
+    /// --connection-string)
+    #[arg(long)]
+    container: Option<String>,
+
+    /// Only project blobs under this prefix, e.g. `datasets/2024`
+    #[arg(long, default_value = "")]
+    prefix: String,
+
+    /// Project a single blob (relative to --prefix) as a folder of --chunk-size chunk files,
+    /// `00000000.chunk` onwards, instead of projecting the container
+    #[arg(long)]
+    blob_as_dir: Option<String>,
+
+    /// The size of each chunk file with --blob-as-dir, in bytes
+    #[arg(long, default_value_t = NonZeroU64::new(64 << 20).unwrap(), requires = "blob_as_dir")]
+    chunk_size: NonZeroU64,
+
+    /// Project the blobs matching a glob relative to the mount root (e.g. `data/part-*`) as a
+    /// single file at NAME, made of them one after the other in name order. May be given more
+    /// than once
+    #[arg(
+        long,
+        num_args = 3,
+        value_names = ["PATTERN", "as", "NAME"],
+        conflicts_with = "blob_as_dir"
+    )]
+    concat: Vec<String>,
+
+    /// The character that separates folders in blob names, for containers that use something
+    /// other than `/` (e.g. `\\`)
+    #[arg(long, default_value_t = '/')]
+    delimiter: char,
+
+    /// Maximum number of recently-seen directories to remember
+    #[arg(long, default_value = "4096")]
+    dir_cache_size: NonZeroUsize,
+
+    /// How long to reuse a directory listing before re-listing from Azure (0 to disable)
+    #[arg(long, default_value_t = 30)]
+    listing_ttl_secs: u64,
+
+    /// How long to reuse a file's properties before looking them up again (0 to disable)
+    #[arg(long, default_value_t = 30)]
+    metadata_ttl_secs: u64,
+
+    /// Hide blobs matching this glob, relative to the mount root (e.g. `**/*.tmp`, `logs/**`)
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// File with a glob to exclude on each line; blank lines and lines starting with `#` are ignored
+    #[arg(long)]
+    exclude_from: Option<PathBuf>,
+
+    /// Hide files with this extension (e.g. `exe`), ignoring case. May be given more than once
+    #[arg(long = "block-extension", value_name = "EXT")]
+    block_extensions: Vec<String>,
+
+    /// Directory in which to cache downloaded blob ranges
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum size of the on-disk cache, in bytes
+    #[arg(long, default_value_t = 1 << 30, requires = "cache_dir")]
+    cache_max_bytes: u64,
+
+    /// Download blobs up to this size, in bytes, whole on their first read, and keep them in
+    /// memory to serve later reads (disabled by default)
+    #[arg(long, default_value_t = 0)]
+    small_blob_threshold: u64,
+
+    /// Maximum total size of the small blobs kept in memory, in bytes
+    #[arg(long, default_value_t = 64 << 20, requires = "small_blob_threshold")]
+    small_blob_cache_bytes: u64,
+
+    /// Serve only what is cached (see --cache-dir), without contacting Azure at all. Anything
+    /// that isn't cached is reported as not found
+    #[arg(long)]
+    offline: bool,
+
+    /// Serve only what is cached, like --offline, after this many requests in a row fail to reach
+    /// Azure, until it can be reached again
+    #[arg(long)]
+    offline_after: Option<NonZeroU32>,
+
+    /// Fail requests straight away, without waiting on retries, after this many requests in a
+    /// row fail with Azure unreachable or unavailable
+    #[arg(long)]
+    circuit_breaker_threshold: Option<NonZeroU32>,
+
+    /// How long the circuit breaker fails requests for before letting one through to check on
+    /// Azure, in seconds
+    #[arg(
+        long,
+        default_value_t = 30,
+        requires = "circuit_breaker_threshold",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    circuit_breaker_cooldown_secs: u64,
+
+    /// Download (and cache) files in blocks of this many bytes, aligned to multiples of it, rather
+    /// than exactly the ranges that are read (0 to disable)
+    #[arg(long, default_value_t = 0)]
+    block_size: u64,
+
+    /// How far ahead of sequential reads to download, in bytes (0 to disable)
+    #[arg(long, default_value_t = 0)]
+    readahead_bytes: u64,
+
+    /// Log how much of each file has been read, every few seconds, while it's being read from
+    /// start to finish (e.g. by a copy)
+    #[arg(long)]
+    progress: bool,
+
+    /// How long to hold a small read back, in milliseconds, so that adjacent reads of the same
+    /// file can be downloaded together (0 to disable)
+    #[arg(long, default_value_t = 0)]
+    coalesce_window_ms: u64,
+
+    /// Largest download to coalesce reads into, in bytes
+    #[arg(long, default_value_t = 4 << 20)]
+    coalesce_max_bytes: u64,
+
+    /// Mount the container as of the latest snapshot taken at or before this time (RFC 3339)
+    #[arg(long, value_parser = parse_datetime, conflicts_with = "as_of")]
+    snapshot: Option<OffsetDateTime>,
+
+    /// Mount each blob's version that was current at this time (RFC 3339), for containers with
+    /// versioning enabled
+    #[arg(long, value_parser = parse_datetime)]
+    as_of: Option<OffsetDateTime>,
+
+    /// Levels of folders to project below the mount root (0 for only the files in it). Blobs in
+    /// deeper folders are hidden
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Only project blobs last modified at or after this time (RFC 3339)
+    #[arg(long, value_parser = parse_datetime)]
+    modified_after: Option<OffsetDateTime>,
+
+    /// Only project blobs last modified before this time (RFC 3339)
+    #[arg(long, value_parser = parse_datetime)]
+    modified_before: Option<OffsetDateTime>,
+
+    /// Log level (`off`, `error`, `warn`, `info`, `debug` or `trace`), overriding `RUST_LOG`
+    #[arg(long)]
+    log_level: Option<log::LevelFilter>,
+
+    /// Format of log output
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Log request and cache statistics at this interval, in seconds (0 to disable)
+    #[arg(long, default_value_t = 0)]
+    stats_interval: u64,
+
+    /// Match blob names case-sensitively, rather than falling back to a case-insensitive match
+    #[arg(long)]
+    case_sensitive: bool,
+
+    /// Upload changes made in the mount back to blob storage (defaults to read-only)
+    #[arg(long)]
+    writable: bool,
+
+    /// Mark every projected file read-only, so that edits are refused (the default without --writable)
+    #[arg(long, conflicts_with = "writable")]
+    read_only: bool,
+
+    /// ProjFS file notifications to register for, such as `pre-delete,file-renamed` (none by
+    /// default, since read-only mounts don't need any). Windows only
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "NOTIFICATION")]
+    notify: Vec<Notification>,
+
+    /// Number of concurrent range requests to split large reads into
+    #[arg(long, default_value_t = NonZeroUsize::new(4).unwrap())]
+    parallel_ranges: NonZeroUsize,
+
+    /// Check blobs that are read from start to finish against their stored MD5
+    #[arg(long)]
+    verify_checksums: bool,
+
+    /// Maximum number of requests to Azure to have in flight at once (unlimited by default)
+    #[arg(long)]
+    max_concurrency: Option<NonZeroUsize>,
+
+    /// Maximum rate to download blobs at, in bytes per second, shared by all reads of the mount
+    /// (unlimited by default)
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    max_bandwidth: Option<NonZeroU64>,
+
+    /// Whether the account has a hierarchical namespace (ADLS Gen2), in which directories are
+    /// blobs of their own. Detected from the start of the listing if not given. `--hns` alone
+    /// means `--hns=true`
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    hns: Option<bool>,
+
+    /// Fail reads of blobs that have changed since they were listed or looked up, instead of
+    /// mixing old and new contents
+    #[arg(long)]
+    pin_etags: bool,
+
+    /// How long to wait for each attempt at a request to Azure, in seconds, before retrying it (0 to
+    /// wait indefinitely)
+    #[arg(long, default_value_t = 30)]
+    request_timeout_secs: u64,
+
+    /// Keep listing the container in the background, so that blobs that are added, changed or
+    /// deleted are noticed without waiting for cached listings to expire
+    #[arg(long, conflicts_with_all = ["offline", "snapshot", "as_of"])]
+    follow: bool,
+
+    /// How often to list the container with --follow, in seconds
+    #[arg(long, default_value_t = 60, requires = "follow", value_parser = clap::value_parser!(u64).range(1..))]
+    follow_interval_secs: u64,
+
+    /// Only project blobs whose index tags match this expression, e.g. `"project" = 'alpha'`.
+    /// Blobs are matched once, when mounting
+    #[arg(long, conflicts_with = "offline")]
+    tag_filter: Option<String>,
+
+    /// List soft-deleted blobs too, as `<name>.deleted`. They can be read if versioning kept a
+    /// version of them
+    #[arg(long)]
+    include_deleted: bool,
+
+    /// Project the versions of each blob in a `<name>.versions` folder next to it, for containers
+    /// with versioning enabled
+    #[arg(long, conflicts_with_all = ["snapshot", "as_of", "blob_as_dir"])]
+    versions_as_subdir: bool,
+
+    /// Give blobs without a file extension one that matches their content type (e.g. `.png` for
+    /// `image/png`), so that applications recognize them
+    #[arg(long)]
+    infer_extensions: bool,
+
+    /// Fetch the properties of every file in a folder as it's listed, with up to
+    /// --max-concurrency requests at once, so that details views fill in quickly. This costs a
+    /// request per file
+    #[arg(long)]
+    prefetch_metadata: bool,
+
+    /// List folders with the total size of the blobs in them, rather than 0. This lists everything
+    /// in each folder that is listed, so it's costly for large containers
+    #[arg(long)]
+    compute_dir_sizes: bool,
+
+    /// What to do about reads of blobs that another client holds a lease on, and so may be in the
+    /// middle of writing to
+    #[arg(long, value_enum, default_value_t = LeasePolicy::Ignore)]
+    lease_policy: LeasePolicy,
+
+    /// Name to attribute this mount's log lines to (defaults to the container's name)
+    #[arg(long, visible_alias = "mount-name")]
+    name: Option<String>,
+
+    /// Print the tree that would be projected and exit, instead of mounting
+    #[arg(long)]
+    list: bool,
+
+    /// How many levels of folders to print with --list
+    #[arg(long, requires = "list")]
+    depth: Option<usize>,
+
+    /// Mount without first checking that the container can be listed
+    #[arg(long)]
+    no_probe: bool,
+
+    /// Count the blobs being mounted, and their total size, before mounting
+    #[arg(long)]
+    summarize: bool,
+
+    /// Stop counting blobs for --summarize after this many
+    #[arg(long, default_value_t = 100_000, requires = "summarize")]
+    summarize_max_blobs: u64,
+
+    /// Mount even if the mount point already contains files, which are hidden or mixed in with
+    /// the container's
+    #[arg(long)]
+    force: bool,
+
+    /// Request rehydration of archived blobs to this tier when they are read. Reads fail until
+    /// rehydration completes, which can take hours
+    #[arg(long, value_enum)]
+    auto_rehydrate: Option<RehydrateTier>,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+struct AuthArgs {
+    /// Storage account name, instead of the one in the URL's host (e.g. for a custom domain). The
+    /// URL's host is still used to reach the account
+    #[arg(long, value_parser = parse_account_name)]
+    account: Option<String>,
+
+    /// Storage account key, used when the URL does not carry a SAS token
+    #[arg(long)]
+    account_key: Option<String>,
+
+    /// Authentication method
+    #[arg(long, value_enum, default_value_t = AuthMode::Auto)]
+    auth: AuthMode,
+
+    /// Access a public container without any credentials
+    #[arg(long, conflicts_with_all = ["account_key", "auth"])]
+    anonymous: bool,
+
+    /// Read the SAS token from a file instead of the URL (also: `RAZMOUNT_SAS`)
+    #[arg(long, conflicts_with_all = ["account_key", "auth", "anonymous"])]
+    sas_file: Option<PathBuf>,
+
+    /// Don't check the SAS token's expiry and permissions before mounting
+    #[arg(long)]
+    skip_sas_validation: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Check that a running mount is serving files, exiting with an error if it isn't. This goes
+    /// through the mount, so it needs no credentials
+    Check {
+        /// Directory the container is mounted at
+        path: PathBuf,
+
+        /// File to read, relative to the mount (defaults to the first file in its root)
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// How long to wait for the mount to respond, in seconds
+        #[arg(long, default_value_t = 10)]
+        timeout_secs: u64,
+    },
+
+    /// Clean up a mount point left behind by a razmount that didn't exit cleanly, so that the
+    /// directory can be mounted again or deleted. Does nothing if it isn't mounted
+    Unmount {
+        /// Directory the container was mounted at
+        path: PathBuf,
+    },
+}
+
+/// Settings of the HTTP client that requests to Azure are sent with.
+#[derive(clap::Args, Debug, Clone)]
+struct TransportArgs {
+    /// Maximum number of idle connections to keep open for reuse (unlimited by default). Busy
+    /// mounts should keep at least --max-concurrency, so that bursts of requests don't reconnect
+    #[arg(long)]
+    max_idle_connections: Option<usize>,
+
+    /// How long to keep an idle connection open for reuse, in seconds
+    #[arg(long, default_value_t = 90)]
+    idle_connection_timeout_secs: u64,
+
+    /// How long to wait for a connection to be established, in seconds (bounded only by
+    /// --request-timeout-secs by default)
+    #[arg(long)]
+    connect_timeout_secs: Option<u64>,
+
+    /// Send TCP keep-alive probes on open connections at this interval, in seconds
+    #[arg(long)]
+    tcp_keepalive_secs: Option<u64>,
+
+    /// Oldest version of TLS to accept from the storage endpoint
+    #[arg(long, value_enum)]
+    min_tls_version: Option<TlsVersion>,
+
+    /// PEM file of an additional root certificate to trust (e.g. for a TLS-inspecting proxy)
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl From<TlsVersion> for reqwest::tls::Version {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+/// Environment variable that may hold the SAS token.
+const SAS_ENV_VAR: &str = "RAZMOUNT_SAS";
+
+/// How often `--progress` reports on each file being read.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Fetches the SAS token from `--sas-file` or the environment, if either was provided.
+fn external_sas_token(auth: &AuthArgs) -> Result<Option<String>> {
+    let token = if let Some(path) = &auth.sas_file {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read SAS token from {}", path.display()))?
+    } else if let Ok(token) = std::env::var(SAS_ENV_VAR) {
+        token
+    } else {
+        return Ok(None);
+    };
+
+    // Tolerate tokens copied along with their leading `?` or a trailing newline.
+    let token = token.trim_end();
+    Ok(Some(token.strip_prefix('?').unwrap_or(token).to_string()))
+}
+
+/// Checks that a SAS token is usable for a mount: that it has not expired, and that it grants
+/// the read (`r`) and list (`l`) permissions.
+fn validate_sas(token: &str) -> Result<()> {
+    let params = url::form_urlencoded::parse(token.as_bytes()).collect::<HashMap<_, _>>();
+
+    // Azure accepts both full timestamps and plain dates (e.g. `2024-01-31`).
+    let parse_time = |s: &str| {
+        azure_core::date::parse_rfc3339(s)
+            .or_else(|_| azure_core::date::parse_rfc3339(&format!("{s}T00:00:00Z")))
+            .ok()
+    };
+    let now = OffsetDateTime::now_utc();
+
+    match params.get("se").map(|se| (se, parse_time(se))) {
+        Some((_, Some(expiry))) if expiry <= now => bail!(
+            "SAS token expired at {}; generate a new one, or pass --skip-sas-validation",
+            azure_core::date::to_rfc3339(&expiry)
+        ),
+        Some((_, Some(expiry))) if expiry - now < time::Duration::HOUR => warn!(
+            "SAS token expires at {}, after which the mount will stop working",
+            azure_core::date::to_rfc3339(&expiry)
+        ),
+        Some((_, Some(_))) => {}
+        Some((se, None)) => warn!("could not parse the SAS token's expiry `{se}`"),
+        None => warn!("SAS token has no expiry (`se`); it may rely on a stored access policy"),
+    }
+
+    if let Some(start) = params.get("st").and_then(|st| parse_time(st)) {
+        if start > now {
+            bail!(
+                "SAS token is not valid until {}",
+                azure_core::date::to_rfc3339(&start)
+            );
+        }
+    }
+
+    match params.get("sp") {
+        Some(sp) => {
+            let missing = ['r', 'l']
+                .into_iter()
+                .filter(|p| !sp.contains(*p))
+                .collect::<String>();
+
+            if !missing.is_empty() {
+                bail!(
+                    "SAS token grants `{sp}`, but mounting requires read (`r`) and list (`l`) \
+                     permissions (missing `{missing}`); pass --skip-sas-validation to mount anyway"
+                );
+            }
+        }
+        None => {
+            warn!("SAS token has no permissions (`sp`); they may come from a stored access policy")
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum AuthMode {
+    /// Use the SAS token in the URL, or the account key if one was specified
+    Auto,
+    /// Azure AD via `DefaultAzureCredential` (environment, managed identity, then `az login`)
+    Aad,
+}
+
+/// The operations ProjFS can notify the provider about. Each notification is a callback on the
+/// thread performing the operation, which it waits for, so every one that's registered slows that
+/// operation down, by a round trip to Azure if the callback makes a request.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Notification {
+    /// A file or folder was opened. Sent for every open, so this is the most expensive
+    FileOpened,
+    /// A file or folder was created
+    NewFileCreated,
+    /// A file was overwritten or superseded
+    FileOverwritten,
+    /// A file or folder is about to be deleted, which the provider may veto
+    PreDelete,
+    /// A file or folder is about to be renamed, which the provider may veto
+    PreRename,
+    /// A hard link is about to be created, which the provider may veto
+    PreSetHardlink,
+    /// A file or folder was renamed
+    FileRenamed,
+    /// A hard link was created
+    HardlinkCreated,
+    /// A handle was closed without the file being modified. Sent as often as `file-opened`
+    FileHandleClosedNoModification,
+    /// A handle was closed after the file was modified
+    FileHandleClosedFileModified,
+    /// A handle was closed, and the file deleted
+    FileHandleClosedFileDeleted,
+    /// A placeholder is about to be written to for the first time, which the provider may veto
+    FilePreConvertToFull,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum RehydrateTier {
+    Hot,
+    Cool,
 }
 
-fn builder_from_url(url: &Url) -> Result<ClientBuilder> {
-    // Determine the account.
-    let account = if let Some(domain) = url.domain() {
-        // Split out the subdomain.
-        if let Some(subdomain) = domain.split('.').next() {
-            subdomain
+impl From<RehydrateTier> for AccessTier {
+    fn from(tier: RehydrateTier) -> Self {
+        match tier {
+            RehydrateTier::Hot => AccessTier::Hot,
+            RehydrateTier::Cool => AccessTier::Cool,
+        }
+    }
+}
+
+/// Checks that a storage account name is one Azure would accept: 3 to 24 lowercase letters and
+/// digits.
+fn parse_account_name(s: &str) -> std::result::Result<String, RazmountError> {
+    let legal = (3..=24).contains(&s.len())
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+
+    if legal {
+        Ok(s.to_string())
+    } else {
+        Err(RazmountError::Parse(format!(
+            "`{s}` is not a storage account name: expected 3 to 24 lowercase letters and digits"
+        )))
+    }
+}
+
+/// Determines if a URL points at a local storage emulator such as Azurite, which
+/// uses path-style URLs (`http://127.0.0.1:10000/<account>/<container>`).
+fn is_emulator_url(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(_) | Host::Ipv6(_)) => true,
+        Some(Host::Domain(domain)) => domain == "localhost",
+        None => false,
+    }
+}
+
+/// Determines the account that a URL addresses (unless it's given as `account`), and the endpoint
+/// of the cloud it's in.
+fn url_location<'a>(url: &'a Url, account: Option<&'a str>) -> Result<(&'a str, CloudLocation)> {
+    let found = if let Some(account) = account {
+        let host = url.host_str().context("URL has no host")?;
+        let port = url.port().map(|p| format!(":{p}")).unwrap_or_default();
+
+        // The emulator's path-style URLs name the account in the path, which is part of the endpoint.
+        let uri = if is_emulator_url(url) {
+            format!("{}://{host}{port}/{account}", url.scheme())
+        } else {
+            format!("{}://{host}{port}", url.scheme())
+        };
+
+        (account, CloudLocation::Custom { uri })
+    } else if is_emulator_url(url) {
+        // The emulator places the account name in the first path segment.
+        let account = url
+            .path_segments()
+            .and_then(|mut s| s.next())
+            .filter(|s| !s.is_empty())
+            .context("emulator URL has no account in its path")?;
+        let host = url.host_str().context("emulator URL has no host")?;
+        let port = url.port().map(|p| format!(":{p}")).unwrap_or_default();
+
+        let location = CloudLocation::Custom {
+            uri: format!("{}://{host}{port}/{account}", url.scheme()),
+        };
+
+        (account, location)
+    } else if let Some(domain) = url.domain() {
+        // Split out the subdomain, and keep the rest as the endpoint suffix.
+        if let Some((subdomain, suffix)) = domain.split_once('.').filter(|(a, _)| !a.is_empty()) {
+            let account = subdomain.to_string();
+            let location = match suffix {
+                "blob.core.windows.net" => CloudLocation::Public { account },
+                "blob.core.chinacloudapi.cn" => CloudLocation::China { account },
+                // Other clouds (e.g. `blob.core.usgovcloudapi.net`) are addressed directly.
+                _ => CloudLocation::Custom {
+                    uri: format!("{}://{domain}", url.scheme()),
+                },
+            };
+
+            (subdomain, location)
         } else {
-            bail!("could not parse domain: {domain}");
+            bail!("could not determine account from host `{domain}`: expected `<account>.blob.<endpoint suffix>`");
         }
     } else {
-        bail!("unsupported URL: {url}");
+        bail!("no DNS host found in URL `{url}`: expected `https://<account>.blob.core.windows.net/<container>`");
     };
 
-    // Determine if the URL is an SAS URL.
-    let creds = if url.query_pairs().any(|(a, _)| a == "sig") {
+    Ok(found)
+}
+
+fn builder_from_url(url: &Url, auth: &AuthArgs) -> Result<ClientBuilder> {
+    let (account, location) = url_location(url, auth.account.as_deref())?;
+
+    // Determine which credentials to use.
+    let creds = if auth.anonymous {
+        StorageCredentials::anonymous()
+    } else if auth.auth == AuthMode::Aad {
+        // Cache tokens and refresh them on expiry, since the mount is long-lived.
+        let credential = Arc::new(DefaultAzureCredential::default());
+        StorageCredentials::token_credential(Arc::new(AutoRefreshingTokenCredential::new(
+            credential,
+        )))
+    } else if let Some(token) = external_sas_token(auth)? {
+        if !auth.skip_sas_validation {
+            validate_sas(&token)?;
+        }
+        StorageCredentials::sas_token(token).context("failed to parse SAS token")?
+    } else if let Some(query) = url
+        .query()
+        .filter(|_| url.query_pairs().any(|(a, _)| a == "sig"))
+    {
         // This is an SAS URL.
-        // FIXME: Somehow avoid that unwrapping?
-        StorageCredentials::sas_token(url.query().unwrap()).context("failed to parse SAS token")?
+        if !auth.skip_sas_validation {
+            validate_sas(query)?;
+        }
+        StorageCredentials::sas_token(query).context("failed to parse SAS token")?
+    } else if let Some(key) = &auth.account_key {
+        StorageCredentials::access_key(account, key)
+    } else if is_emulator_url(url) && account == EMULATOR_ACCOUNT {
+        // Fall back to the emulator's well-known account key.
+        StorageCredentials::emulator()
     } else {
-        todo!()
+        bail!("no credentials found: pass a SAS URL (with a `sig` parameter), use --sas-file, specify --account-key, use --auth aad, or --anonymous for public containers");
     };
 
-    Ok(ClientBuilder::new(account, creds))
+    Ok(ClientBuilder::with_location(location, creds))
 }
 
-#[derive(Debug, Clone)]
-struct BlobPath(String);
+fn builder_from_connection_string(connection_string: &str) -> Result<ClientBuilder> {
+    let cs =
+        ConnectionString::new(connection_string).context("failed to parse connection string")?;
 
-impl<P: AsRef<Path>> From<P> for BlobPath {
-    fn from(value: P) -> Self {
-        let c = value.as_ref().components();
+    let account = cs
+        .account_name
+        .context("connection string has no AccountName")?;
+    let key = cs
+        .account_key
+        .context("connection string has no AccountKey")?;
+    let creds = StorageCredentials::access_key(account, key);
 
-        Self(
-            c.into_iter()
-                .filter_map(|c| match c {
-                    std::path::Component::Prefix(_) => todo!(),
-                    std::path::Component::RootDir => todo!(),
-                    std::path::Component::CurDir => todo!(),
-                    std::path::Component::ParentDir => todo!(),
-                    std::path::Component::Normal(p) => Some(p.to_string_lossy()),
-                })
-                .collect::<Vec<_>>()
-                .join("/"),
-        )
-    }
+    let location = if let Some(endpoint) = cs.blob_endpoint {
+        CloudLocation::Custom {
+            uri: endpoint.to_string(),
+        }
+    } else {
+        let account = account.to_string();
+        match cs.endpoint_suffix {
+            None | Some("core.windows.net") => CloudLocation::Public { account },
+            Some("core.chinacloudapi.cn") => CloudLocation::China { account },
+            Some(suffix) => {
+                let protocol = cs
+                    .default_endpoints_protocol
+                    .unwrap_or(EndpointProtocol::Https);
+
+                CloudLocation::Custom {
+                    uri: format!("{protocol}://{account}.blob.{suffix}"),
+                }
+            }
+        }
+    };
+
+    Ok(ClientBuilder::with_location(location, creds))
 }
 
-impl std::fmt::Display for BlobPath {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0)
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(args.log_level, args.log_format);
+
+    if let Some(Command::Check {
+        path,
+        file,
+        timeout_secs,
+    }) = &args.command
+    {
+        let timeout = Duration::from_secs(*timeout_secs);
+        println!("{}", check::check(path, file.as_deref(), timeout)?);
+        return Ok(());
+    }
+
+    if let Some(Command::Unmount { path }) = &args.command {
+        let path =
+            extended_path(path).with_context(|| format!("failed to resolve {}", path.display()))?;
+        if platform::unmount(&path)? {
+            info!("cleaned up {}", path.display());
+        }
+        return Ok(());
     }
-}
 
-impl BlobPath {
-    fn new(p: impl Into<String>) -> Self {
-        Self(p.into())
+    // Writing back requires ProjFS file notifications, which the projfs bindings do not deliver
+    // (`NotificationCallback` is never registered), so there is no way to observe modifications.
+    if args.writable {
+        bail!(
+            "--writable is not supported yet: the projfs bindings do not expose file notifications"
+        );
     }
 
-    fn as_str(&self) -> &str {
-        &self.0
+    // Likewise, `start_proj_virtualization` registers no notification mappings, and takes no
+    // options to register any with.
+    if !args.notify.is_empty() {
+        bail!(
+            "--notify is not supported yet: the projfs bindings do not expose file notifications"
+        );
     }
 
-    fn to_path_buf(&self) -> PathBuf {
-        let n = &self.0;
-        let c = n.split('/');
+    let exclude = build_excludes(&args.exclude, args.exclude_from.as_deref())?;
+    let concat = build_concats(&args.concat)?;
 
-        let mut p = PathBuf::new();
-        for c in c {
-            p.push(c);
+    let modified = ModifiedWindow {
+        after: args.modified_after,
+        before: args.modified_before,
+    };
+    if let (Some(after), Some(before)) = (modified.after, modified.before) {
+        if after >= before {
+            bail!("--modified-after must be earlier than --modified-before");
         }
+    }
+
+    let options = DriverOptions {
+        exclude,
+        modified,
+        blocked_extensions: args
+            .block_extensions
+            .iter()
+            .map(|ext| format!(".{}", ext.trim_start_matches('.').to_lowercase()))
+            .collect(),
+        max_depth: args.max_depth,
+        anonymous: args.auth.anonymous,
+        prefix: blob_prefix(&args.prefix, args.delimiter),
+        dir_cache_size: args.dir_cache_size,
+        listing_ttl: Duration::from_secs(args.listing_ttl_secs),
+        metadata_ttl: Duration::from_secs(args.metadata_ttl_secs),
+        cache_dir: args.cache_dir.clone(),
+        cache_max_bytes: args.cache_max_bytes,
+        small_blob_threshold: args.small_blob_threshold,
+        small_blob_cache_bytes: args.small_blob_cache_bytes,
+        readahead_bytes: args.readahead_bytes,
+        block_size: args.block_size,
+        blob_as_dir: args.blob_as_dir.clone(),
+        chunk_size: args.chunk_size,
+        concat,
+        progress_interval: args.progress.then_some(PROGRESS_INTERVAL),
+        coalesce_window: Duration::from_millis(args.coalesce_window_ms),
+        coalesce_max_bytes: args.coalesce_max_bytes,
+        parallel_ranges: args.parallel_ranges,
+        point_in_time: args
+            .snapshot
+            .map(PointInTime::Snapshot)
+            .or(args.as_of.map(PointInTime::Version)),
+        case_sensitive: args.case_sensitive,
+        read_only: args.read_only || !args.writable,
+        verify_checksums: args.verify_checksums,
+        max_concurrency: args.max_concurrency,
+        max_bandwidth: args.max_bandwidth,
+        auto_rehydrate: args.auto_rehydrate.map(AccessTier::from),
+        request_timeout: request_timeout(args.request_timeout_secs),
+        pin_etags: args.pin_etags,
+        hns: args.hns,
+        delimiter: args.delimiter,
+        offline: args.offline,
+        offline_after: args.offline_after,
+        breaker_threshold: args.circuit_breaker_threshold,
+        breaker_cooldown: Duration::from_secs(args.circuit_breaker_cooldown_secs),
+        include_deleted: args.include_deleted,
+        versions_as_subdir: args.versions_as_subdir,
+        infer_extensions: args.infer_extensions,
+        prefetch_metadata: args.prefetch_metadata,
+        compute_dir_sizes: args.compute_dir_sizes,
+        lease_policy: args.lease_policy,
+        name: args.name.clone(),
+        follow_interval: args
+            .follow
+            .then(|| Duration::from_secs(args.follow_interval_secs)),
+        tag_filter: args.tag_filter.clone(),
+    };
+
+    // Each mount is resolved independently, so that one bad URL doesn't stop the others.
+    let mut mounts = Vec::new();
+    match (&args.path, &args.url, &args.connection_string) {
+        (Some(path), Some(url), None) => mounts.push((
+            path.clone(),
+            client_from_url(url, &args.auth, args.container.as_deref()),
+            options.clone(),
+        )),
+        (Some(path), None, Some(cs)) => {
+            let target = builder_from_connection_string(cs)
+                .context("failed to build storage account client")
+                .and_then(|client| {
+                    let container = args
+                        .container
+                        .clone()
+                        .context("--container is required with --connection-string")?;
+
+                    Ok((client, container))
+                });
 
-        p
+            mounts.push((path.clone(), target, options.clone()));
+        }
+        (None, None, None) => {}
+        _ => bail!("specify a `path` along with exactly one of `url` or `--connection-string`"),
     }
-}
 
-fn blob_to_path(blob: impl AsRef<str>) -> PathBuf {
-    let n = blob.as_ref();
-    let c = n.split('/');
+    for (path, url) in &args.mounts {
+        mounts.push((
+            path.clone(),
+            client_from_url(url, &args.auth, None),
+            options.clone(),
+        ));
+    }
 
-    let mut p = PathBuf::new();
-    for c in c {
-        p.push(c);
+    if let Some(config) = &args.config {
+        for entry in Config::load(config)?.mounts {
+            mounts.push(config_mount(entry, &args, &matches, &options));
+        }
     }
 
-    p
-}
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to build tokio runtime")?;
 
-fn main() -> Result<()> {
-    env_logger::init();
+    let single = mounts.len() == 1;
+
+    if args.list {
+        for (path, target, options) in mounts {
+            let (client, container) = target?;
+            let source =
+                container_source(client, container, &options, &args.transport, rt.handle())?;
+            let mut driver = BlobBackend::new(source, rt.handle().clone(), options)
+                .context("failed to setup driver")?;
+            driver
+                .detect_namespace()
+                .context("failed to detect a hierarchical namespace")?;
+
+            if !single {
+                println!("{}:", path.display());
+            }
+
+            driver
+                .print_tree(Path::new(""), 0, args.depth)
+                .with_context(|| format!("failed to list {}", path.display()))?;
+        }
 
-    let args = Args::parse();
+        return Ok(());
+    }
 
-    let client = builder_from_url(&args.url).context("failed to build storage account client")?;
-    let mut segments = args
-        .url
-        .path_segments()
-        .context("SAS URL has no path segments")?;
-    let container = segments.next().context("no container specified")?;
+    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = shutdown_tx.send(());
+    })
+    .context("failed to install Ctrl+C handler")?;
 
-    let driver =
-        BlobFSDriver::new(client.container_client(container)).context("failed to setup driver")?;
+    let mut instances = Vec::new();
 
-    std::fs::create_dir_all("test").unwrap();
-    let _instance = start_proj_virtualization("test", Box::new(driver)).unwrap();
-    std::thread::sleep(std::time::Duration::from_secs(u64::MAX));
+    for (path, target, mut options) in mounts {
+        let r = target.and_then(|(client, container)| {
+            // Keep the caches of different containers apart.
+            if !single {
+                options.cache_dir = options.cache_dir.map(|dir| dir.join(&container));
+            }
+
+            prepare_mount_point(&path, args.force)?;
+            mount(&path, client, container, &rt, options, &args)
+        });
+
+        match r {
+            Ok(instance) => {
+                info!("mounted {}", path.display());
+                instances.push(instance);
+            }
+            Err(e) if single => return Err(e),
+            Err(e) => error!("failed to mount {}: {e:#}", path.display()),
+        }
+    }
+
+    if instances.is_empty() {
+        bail!("none of the containers could be mounted");
+    }
+
+    let drive = match (&args.drive, &args.path) {
+        (Some(drive), Some(path)) => {
+            let path = extended_path(path)
+                .with_context(|| format!("failed to resolve {}", path.display()))?;
+            let mapping = platform::map_drive(drive, &path)?;
+            info!("mapped {drive} to {}", path.display());
+            Some(mapping)
+        }
+        _ => None,
+    };
+
+    shutdown_rx.recv().context("Ctrl+C handler was dropped")?;
+    info!("shutting down");
+
+    // Remove the drive letter, so that none is left behind leading nowhere. Then stop
+    // virtualizing before exiting, and give any in-flight requests a chance to finish.
+    drop(drive);
+    drop(instances);
+    rt.shutdown_timeout(Duration::from_secs(5));
 
     Ok(())
 }
 
-struct BlobFSDriver {
-    client: ContainerClient,
-    /// Directories that we know about. Hack to ensure consistency between iteration and metadata calls.
-    known_dirs: Mutex<HashSet<PathBuf>>,
-    /// Required by the current API for ProjFS.
-    iter_cache: projfs::CacheMap<<Self as ProjFSDirEnum>::DirIter>,
-    /// An asynchronous runtime for dispatching requests to Azure blob storage.
-    rt: tokio::runtime::Runtime,
-}
-
-impl BlobFSDriver {
-    pub fn new(client: ContainerClient) -> Result<Self> {
-        Ok(Self {
-            client,
-            known_dirs: Default::default(),
-            iter_cache: Default::default(),
-            rt: tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
+/// Compiles the exclusion patterns given on the command line and in `--exclude-from`.
+fn build_excludes(patterns: &[String], file: Option<&Path>) -> Result<GlobSet> {
+    let mut patterns = patterns.to_vec();
+
+    if let Some(file) = file {
+        let text = std::fs::read_to_string(file)
+            .with_context(|| format!("failed to read {}", file.display()))?;
+
+        patterns.extend(
+            text.lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(String::from),
+        );
+    }
+
+    let mut set = GlobSetBuilder::new();
+    for p in patterns {
+        // Like `.gitignore`, `*` stays within a single folder, while `**` crosses folders.
+        let glob = GlobBuilder::new(&p)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid exclude pattern `{p}`"))?;
+        set.add(glob);
+    }
+
+    set.build().context("failed to compile exclude patterns")
+}
+
+/// Compiles the `--concat <pattern> as <name>` rules given on the command line.
+fn build_concats(args: &[String]) -> Result<Vec<Concat>> {
+    args.chunks(3)
+        .map(|rule| {
+            let [pattern, as_, name] = rule else {
+                bail!("expected `--concat <pattern> as <name>`");
+            };
+            if as_ != "as" {
+                bail!("expected `--concat {pattern} as {name}`, not `{as_}`");
+            }
+
+            let name = PathBuf::from(name);
+            if name.file_name().is_none() || name.has_root() {
+                bail!(
+                    "invalid --concat name `{}`: expected a file path relative to the mount root",
+                    name.display()
+                );
+            }
+
+            // Like exclusions, `*` stays within a single folder, while `**` crosses folders.
+            let parts = GlobBuilder::new(pattern)
+                .literal_separator(true)
                 .build()
-                .context("failed to build tokio runtime")?,
+                .with_context(|| format!("invalid --concat pattern `{pattern}`"))?
+                .compile_matcher();
+
+            Ok(Concat { parts, name })
         })
+        .collect()
+}
+
+/// Resolves a mount from the config file. Flags given on the command line take precedence over the
+/// file, which in turn takes precedence over the flags' defaults.
+fn config_mount(
+    entry: MountEntry,
+    args: &Args,
+    matches: &ArgMatches,
+    base: &DriverOptions,
+) -> (PathBuf, Result<(ClientBuilder, String)>, DriverOptions) {
+    let pick = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+    let mut auth = args.auth.clone();
+    if let Some(mode) = entry.auth.filter(|_| pick("auth")) {
+        auth.auth = mode;
+    }
+    if let Some(anonymous) = entry.anonymous.filter(|_| pick("anonymous")) {
+        auth.anonymous = anonymous;
+    }
+    if entry.account.is_some() && pick("account") {
+        auth.account = entry.account;
+    }
+    if entry.account_key.is_some() && pick("account_key") {
+        auth.account_key = entry.account_key;
+    }
+    if entry.sas_file.is_some() && pick("sas_file") {
+        auth.sas_file = entry.sas_file;
+    }
+
+    let mut options = base.clone();
+    options.anonymous = auth.anonymous;
+    if let Some(delimiter) = entry.delimiter.filter(|_| pick("delimiter")) {
+        options.delimiter = delimiter;
+    }
+    // The prefix may be written with either delimiter, so it's re-read for this mount's.
+    let prefix = entry
+        .prefix
+        .filter(|_| pick("prefix"))
+        .unwrap_or_else(|| args.prefix.clone());
+    options.prefix = blob_prefix(&prefix, options.delimiter);
+    if let Some(size) = entry.dir_cache_size.filter(|_| pick("dir_cache_size")) {
+        options.dir_cache_size = size;
+    }
+    if let Some(ttl) = entry.listing_ttl_secs.filter(|_| pick("listing_ttl_secs")) {
+        options.listing_ttl = Duration::from_secs(ttl);
+    }
+    if let Some(ttl) = entry
+        .metadata_ttl_secs
+        .filter(|_| pick("metadata_ttl_secs"))
+    {
+        options.metadata_ttl = Duration::from_secs(ttl);
+    }
+    if entry.cache_dir.is_some() && pick("cache_dir") {
+        options.cache_dir = entry.cache_dir;
+    }
+    if let Some(max) = entry.cache_max_bytes.filter(|_| pick("cache_max_bytes")) {
+        options.cache_max_bytes = max;
+    }
+    if let Some(bytes) = entry.readahead_bytes.filter(|_| pick("readahead_bytes")) {
+        options.readahead_bytes = bytes;
+    }
+    if let Some(bytes) = entry.block_size.filter(|_| pick("block_size")) {
+        options.block_size = bytes;
+    }
+    if let Some(offline) = entry.offline.filter(|_| pick("offline")) {
+        options.offline = offline;
     }
+    if entry.offline_after.is_some() && pick("offline_after") {
+        options.offline_after = entry.offline_after;
+    }
+    if let Some(pin) = entry.pin_etags.filter(|_| pick("pin_etags")) {
+        options.pin_etags = pin;
+    }
+    if entry.hns.is_some() && pick("hns") {
+        options.hns = entry.hns;
+    }
+    if entry.name.is_some() && pick("name") {
+        options.name = entry.name;
+    }
+    if entry.tag_filter.is_some() && pick("tag_filter") {
+        options.tag_filter = entry.tag_filter;
+    }
+    if let Some(secs) = entry
+        .request_timeout_secs
+        .filter(|_| pick("request_timeout_secs"))
+    {
+        options.request_timeout = request_timeout(secs);
+    }
+
+    // `Config::load` ensures that exactly one of these is present.
+    let target = match (&entry.url, entry.connection_string) {
+        (Some(url), _) => client_from_url(url, &auth, entry.container.as_deref()),
+        (None, Some(cs)) => builder_from_connection_string(&cs)
+            .context("failed to build storage account client")
+            .map(|client| (client, entry.container.unwrap_or_default())),
+        (None, None) => Err(anyhow!("no `url` or `connection_string` given")),
+    };
+
+    (entry.path, target, options)
 }
 
-impl ProjFSDirEnum for BlobFSDriver {
-    type DirIter = Box<dyn Iterator<Item = FileBasicInfo> + Send + Sync>;
-
-    fn dir_iter(
-        &self,
-        _id: projfs::Guid,
-        path: projfs::RawPath,
-        _pattern: Option<projfs::RawPath>,
-        _version: projfs::VersionInfo,
-    ) -> std::io::Result<Self::DirIter> {
-        let path = BlobPath::from(path.to_path_buf());
-        info!("iter: {path}");
-
-        let stream = self
-            .client
-            .list_blobs()
-            .prefix(path.to_string())
-            .into_stream();
-
-        let r = self
-            .rt
-            .block_on(async {
-                stream
-                    .map_ok(|b| {
-                        // HACK: Not really sure why I have to map the inner here, but
-                        // we quickly get into trait hell if it isn't mapped to a Result<_>.
-                        futures::stream::iter(
-                            b.blobs.items.into_iter().map(|b| Ok::<_, anyhow::Error>(b)),
-                        )
-                    })
-                    .try_flatten()
-                    .try_collect::<Vec<_>>()
-                    .await
-            })
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    e.context("failed to query blob storage"),
-                )
-            })?;
-
-        let mut subdirs = HashSet::new();
-        let mut items = Vec::new();
-
-        for i in r.iter() {
-            match i {
-                BlobItem::Blob(b) => {
-                    let path = path.to_path_buf();
-
-                    // Determine which files are located in "subdirectories" from the search path,
-                    // and hide them behind folder entries.
-                    let blob_path = BlobPath::new(&b.name).to_path_buf();
-
-                    // All blobs should have a parent, since blob storage cannot represent empty folders.
-                    if let Some(blob_folder) = blob_path.parent() {
-                        if blob_folder != &path {
-                            // Determine the relative path, and strip the first component to use as the folder name.
-                            let rel_path = blob_folder.strip_prefix(&path).unwrap();
-
-                            if let Some(std::path::Component::Normal(dir)) =
-                                rel_path.components().next()
-                            {
-                                let dir = dir.to_str().unwrap();
-
-                                if subdirs.insert(dir.to_string()) == true {
-                                    info!("-> folder: {}", dir);
-
-                                    // HACK: Track "known" directories.
-                                    let mut dirs = self.known_dirs.lock().unwrap();
-                                    dirs.insert(path.join(dir));
-
-                                    items.push(FileBasicInfo {
-                                        file_name: dir.into(),
-                                        is_dir: true,
-                                        file_size: 0,
-                                        created: 0,
-                                        accessed: 0,
-                                        writed: 0,
-                                        changed: 0,
-                                        attrs: 0,
-                                    })
-                                }
-                            }
-
-                            continue;
-                        }
-                    }
-
-                    let file_name = blob_path.file_name().unwrap().to_str().unwrap();
-                    info!("-> {file_name}");
-
-                    // Alright, we should only get here if this is a file in the current directory.
-                    items.push(FileBasicInfo {
-                        file_name: file_name.into(),
-                        is_dir: false,
-                        file_size: b.properties.content_length,
-                        created: 0,
-                        accessed: 0,
-                        writed: 0,
-                        changed: 0,
-                        attrs: 0,
-                    })
-                }
-                BlobItem::BlobPrefix(_) => todo!(),
+/// Parses a `--prefix` into the projected path it names, which is written with `/` even in
+/// containers that use another delimiter. Either is accepted.
+fn blob_prefix(prefix: &str, delimiter: char) -> BlobPath {
+    let prefix = prefix.replace(delimiter, "/");
+    BlobPath::new(prefix.trim_matches('/'))
+}
+
+/// Builds a client for the account in a URL, along with the container to mount: `container` if
+/// given, or else the one in the URL's path.
+fn client_from_url(
+    url: &Url,
+    auth: &AuthArgs,
+    container: Option<&str>,
+) -> Result<(ClientBuilder, String)> {
+    let container = url_container(url, container)?;
+    let client = builder_from_url(url, auth).context("failed to build storage account client")?;
+
+    Ok((client, container))
+}
+
+/// Determines the container to mount from an explicit name, falling back to the first segment of
+/// the URL's path (after the account name, for the emulator's path-style URLs).
+fn url_container(url: &Url, container: Option<&str>) -> Result<String> {
+    if let Some(container) = container {
+        return Ok(container.to_string());
+    }
+
+    let mut segments = url.path_segments().into_iter().flatten();
+    if is_emulator_url(url) {
+        // Skip over the account name.
+        segments.next();
+    }
+
+    segments
+        .next()
+        .filter(|c| !c.is_empty())
+        .map(str::to_string)
+        .context("no container to mount: pass --container, or give a URL of the form `<account URL>/<container>`")
+}
+
+/// Converts `--request-timeout-secs` into a timeout, where 0 means there is none.
+fn request_timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Builds the HTTP client that the SDK sends requests with, in place of its default one.
+fn http_client(args: &TransportArgs) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .pool_idle_timeout(Duration::from_secs(args.idle_connection_timeout_secs))
+        .tcp_keepalive(args.tcp_keepalive_secs.map(Duration::from_secs));
+
+    if let Some(n) = args.max_idle_connections {
+        builder = builder.pool_max_idle_per_host(n);
+    }
+    if let Some(secs) = args.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(version) = args.min_tls_version {
+        builder = builder.min_tls_version(version.into());
+    }
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("failed to read certificate from {}", path.display()))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .with_context(|| format!("invalid certificate in {}", path.display()))?;
+        builder = builder.add_root_certificate(cert);
+    }
+
+    builder.build().context("failed to build HTTP client")
+}
+
+/// Sets up the client to send requests with `http`.
+///
+/// With a `timeout`, each attempt at a request is given that long to be answered, and those that
+/// aren't are retried, so that a stalled connection doesn't hold up a file system callback
+/// indefinitely. The SDK's default of 8 retries is cut down to match the backend, which gives up on
+/// a request once every attempt could have timed out.
+fn with_client_options(
+    client: ClientBuilder,
+    http: reqwest::Client,
+    timeout: Option<Duration>,
+) -> ClientBuilder {
+    let mut options = ClientOptions::new(TransportOptions::new(Arc::new(http)));
+
+    if let Some(timeout) = timeout {
+        let retry = ExponentialRetryOptions::default().max_retries(REQUEST_ATTEMPTS - 1);
+        options = options.retry(RetryOptions::exponential(retry));
+        options
+            .per_retry_policies_mut()
+            .push(Arc::new(AttemptTimeout(timeout)));
+    }
+
+    client.client_options(options)
+}
+
+/// Builds the source that a mount reads its container through, with the client configured by the
+/// command line.
+fn container_source(
+    client: ClientBuilder,
+    container: String,
+    options: &DriverOptions,
+    transport: &TransportArgs,
+    rt: &tokio::runtime::Handle,
+) -> Result<Delimited<TagFiltered<ContainerClient>>> {
+    let client = with_client_options(client, http_client(transport)?, options.request_timeout);
+    razmount::container_source(client, container, options, rt)
+}
+
+/// Projects a container into `path`, returning the running virtualization instance.
+fn mount(
+    path: &Path,
+    client: ClientBuilder,
+    container: String,
+    rt: &tokio::runtime::Runtime,
+    options: DriverOptions,
+    args: &Args,
+) -> Result<platform::Mounted> {
+    let client = with_client_options(
+        client,
+        http_client(&args.transport)?,
+        options.request_timeout,
+    );
+    let setup = MountOptions {
+        probe: !args.no_probe,
+        summarize: args.summarize.then_some(args.summarize_max_blobs),
+        stats_interval: (args.stats_interval > 0).then(|| Duration::from_secs(args.stats_interval)),
+    };
+
+    razmount::mount_container(path, client, container, rt.handle(), options, &setup)
+}
+
+/// Creates the directory to mount into if it doesn't exist, and otherwise checks that it's an
+/// empty directory (unless `force`d), so that a mistyped path can't bury existing files.
+fn prepare_mount_point(path: &Path, force: bool) -> Result<()> {
+    let root =
+        extended_path(path).with_context(|| format!("failed to resolve {}", path.display()))?;
+
+    match std::fs::read_dir(&root) {
+        Ok(mut entries) => {
+            if !force && entries.next().is_some() {
+                bail!(
+                    "{} is not empty: mount into an empty directory, or pass --force",
+                    path.display()
+                );
             }
-        }
 
-        Ok(Box::new(items.into_iter()))
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => std::fs::create_dir_all(&root)
+            .with_context(|| format!("failed to create {}", path.display())),
+        Err(e) => Err(e).with_context(|| {
+            format!(
+                "{} is not a directory that can be mounted into",
+                path.display()
+            )
+        }),
     }
+}
 
-    fn dir_iter_cache(&self, _version: projfs::VersionInfo) -> &projfs::CacheMap<Self::DirIter> {
-        &self.iter_cache
+/// Parses a `<path>=<url>` mount given on the command line.
+fn parse_mount(s: &str) -> std::result::Result<(PathBuf, Url), RazmountError> {
+    let (path, url) = s
+        .split_once('=')
+        .ok_or_else(|| RazmountError::Parse(format!("`{s}` is not of the form `<path>=<url>`")))?;
+    let url =
+        Url::parse(url).map_err(|e| RazmountError::Parse(format!("invalid URL `{url}`: {e}")))?;
+
+    Ok((PathBuf::from(path), url))
+}
+
+/// Parses a drive letter given on the command line, such as `z:`, into the form Windows uses.
+fn parse_drive(s: &str) -> std::result::Result<String, RazmountError> {
+    match s.as_bytes() {
+        [letter, b':'] | [letter, b':', b'\\'] if letter.is_ascii_alphabetic() => {
+            Ok(format!("{}:", letter.to_ascii_uppercase() as char))
+        }
+        _ => Err(RazmountError::Parse(format!(
+            "`{s}` is not a drive letter (e.g. `Z:`)"
+        ))),
     }
 }
 
-impl ProjFSRead for BlobFSDriver {
-    fn get_metadata(
-        &self,
-        path: projfs::RawPath,
-        _version: projfs::VersionInfo,
-    ) -> std::io::Result<FileBasicInfo> {
-        let path = path.to_path_buf();
-        info!("metadata: {}", path.display());
+/// Parses a timestamp given on the command line.
+fn parse_datetime(s: &str) -> std::result::Result<OffsetDateTime, RazmountError> {
+    azure_core::date::parse_rfc3339(s).map_err(|_| {
+        RazmountError::Parse(format!(
+            "`{s}` is not an RFC 3339 timestamp (e.g. `2024-01-31T12:00:00Z`)"
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(url: &str, explicit: Option<&str>) -> Result<String> {
+        url_container(&Url::parse(url).unwrap(), explicit)
+    }
+
+    #[test]
+    fn container_comes_from_the_url_path() {
+        let url = "https://account.blob.core.windows.net/data/ignored?sig=x";
+        assert_eq!(container(url, None).unwrap(), "data");
+
+        let url = "http://127.0.0.1:10000/devstoreaccount1/data";
+        assert_eq!(container(url, None).unwrap(), "data");
+    }
+
+    #[test]
+    fn explicit_container_takes_precedence() {
+        let url = "https://account.blob.core.windows.net/data";
+        assert_eq!(container(url, Some("other")).unwrap(), "other");
+
+        let url = "https://account.blob.core.windows.net/?sig=x";
+        assert_eq!(container(url, Some("other")).unwrap(), "other");
+    }
+
+    #[test]
+    fn account_urls_need_a_container() {
+        assert!(container("https://account.blob.core.windows.net/", None).is_err());
+        assert!(container("http://localhost:10000/devstoreaccount1", None).is_err());
+    }
+
+    #[test]
+    fn mount_points_must_be_empty() {
+        let dir = std::env::temp_dir().join(format!("razmount-mount-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // A missing mount point is created, and an empty one is left alone.
+        prepare_mount_point(&dir, false).unwrap();
+        assert!(dir.is_dir());
+        prepare_mount_point(&dir, false).unwrap();
 
-        let dirs = self.known_dirs.lock().unwrap();
-        if dirs.contains(&path) {
-            return Ok(FileBasicInfo {
-                file_name: path,
-                is_dir: true,
-                file_size: 0,
-                created: 0,
-                accessed: 0,
-                writed: 0,
-                changed: 0,
-                attrs: 0,
-            });
+        std::fs::write(dir.join("file.txt"), b"").unwrap();
+        let e = prepare_mount_point(&dir, false).unwrap_err();
+        assert!(e.to_string().contains("--force"));
+        prepare_mount_point(&dir, true).unwrap();
+
+        // Files can't be mounted onto.
+        assert!(prepare_mount_point(&dir.join("file.txt"), true).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn account_names_are_validated() {
+        assert_eq!(parse_account_name("data2024").unwrap(), "data2024");
+
+        for name in ["ab", "Data", "my-account", "a".repeat(25).as_str()] {
+            assert!(parse_account_name(name).is_err(), "{name}");
         }
+    }
 
-        drop(dirs);
-
-        let r = self
-            .client
-            .blob_client(path.to_string_lossy())
-            .get_properties()
-            .into_future();
-
-        let blob = self
-            .rt
-            .block_on(async { r.await })
-            .map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    e.context("failed to query blob storage"),
-                )
-            })?
-            .blob;
-
-        Ok(FileBasicInfo {
-            file_name: blob.name.clone().into(),
-            is_dir: false,
-            file_size: blob.properties.content_length,
-            created: 0,
-            accessed: 0,
-            writed: 0,
-            changed: 0,
-            attrs: 0,
-        })
+    #[test]
+    fn check_reads_through_the_mount() {
+        let root = std::env::temp_dir().join(format!("razmount-check-{}", std::process::id()));
+        let timeout = Duration::from_secs(10);
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert!(check::check(&root, None, timeout).is_err());
+
+        std::fs::create_dir_all(root.join("dir")).unwrap();
+        std::fs::write(root.join("a.txt"), b"hello").unwrap();
+
+        let report = check::check(&root, None, timeout).unwrap();
+        assert!(report.contains("read 5 bytes"), "{report}");
+        assert!(check::check(&root, Some(Path::new("dir/missing.txt")), timeout).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    fn read(
-        &self,
-        path: projfs::RawPath,
-        _version: projfs::VersionInfo,
-        offset: u64,
-        buf: &mut [u8],
-    ) -> std::io::Result<()> {
-        let path = BlobPath::from(path.to_path_buf());
-        info!("{path}: {offset}, {}", buf.len());
-
-        let mut r = self
-            .client
-            .blob_client(path.as_str())
-            .get()
-            .range(azure_core::request_options::Range {
-                start: offset,
-                end: offset + (buf.len() as u64),
-            })
-            .into_stream();
-
-        self.rt.block_on(async move {
-            while let Ok(Some(r)) = r.try_next().await {
-                let bytes = r.data.collect().await.unwrap();
-
-                if let Some(range) = r.content_range {
-                    buf[range.start as usize..(range.end + 1) as usize].copy_from_slice(&bytes[..]);
-                } else {
-                    buf[..].copy_from_slice(&bytes[..]);
-                }
-            }
-        });
+    #[test]
+    fn check_is_a_subcommand() {
+        let args = Args::try_parse_from(["razmount", "check", "/mnt/data"]).unwrap();
+        assert!(
+            matches!(args.command, Some(Command::Check { path, .. }) if path == Path::new("/mnt/data"))
+        );
+    }
+
+    #[test]
+    fn transport_flags_build_a_client() {
+        let args = Args::try_parse_from([
+            "razmount",
+            "--max-idle-connections",
+            "32",
+            "--connect-timeout-secs",
+            "5",
+            "--tcp-keepalive-secs",
+            "60",
+            "--min-tls-version",
+            "1.2",
+            "/mnt/data",
+            "https://account.blob.core.windows.net/data",
+        ])
+        .unwrap();
 
-        Ok(())
+        assert_eq!(args.transport.max_idle_connections, Some(32));
+        assert_eq!(args.transport.min_tls_version, Some(TlsVersion::Tls12));
+        assert!(http_client(&args.transport).is_ok());
+
+        let args = TransportArgs {
+            ca_cert: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..args.transport
+        };
+        assert!(http_client(&args).is_err());
+    }
+
+    #[test]
+    fn unmount_is_a_subcommand() {
+        let args = Args::try_parse_from(["razmount", "unmount", "/mnt/data"]).unwrap();
+        assert!(
+            matches!(args.command, Some(Command::Unmount { path }) if path == Path::new("/mnt/data"))
+        );
+    }
+
+    #[test]
+    fn notifications_are_listed_by_name() {
+        let args = Args::try_parse_from([
+            "razmount",
+            "--notify",
+            "pre-delete,file-renamed",
+            "/mnt/data",
+            "https://account.blob.core.windows.net/data",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.notify,
+            [Notification::PreDelete, Notification::FileRenamed]
+        );
+
+        let args = Args::try_parse_from([
+            "razmount",
+            "/mnt/data",
+            "https://account.blob.core.windows.net/data",
+        ])
+        .unwrap();
+        assert!(args.notify.is_empty());
+    }
+
+    #[test]
+    fn drives_are_single_letters() {
+        assert_eq!(parse_drive("z:").unwrap(), "Z:");
+        assert_eq!(parse_drive(r"Q:\").unwrap(), "Q:");
+        for bad in ["Z", "ZZ:", "1:", r"\\?\Z:"] {
+            assert!(parse_drive(bad).is_err(), "{bad}");
+        }
+
+        let args = Args::try_parse_from([
+            "razmount",
+            "--drive",
+            "z:",
+            "/mnt/data",
+            "https://account.blob.core.windows.net/data?sig=x",
+        ])
+        .unwrap();
+        assert_eq!(args.drive.as_deref(), Some("Z:"));
+    }
+
+    #[test]
+    fn concatenations_are_patterns_and_names() {
+        let args = Args::try_parse_from([
+            "razmount",
+            "--concat",
+            "data/part-*",
+            "as",
+            "data/all.bin",
+            "/mnt/data",
+            "https://account.blob.core.windows.net/data?sig=x",
+        ])
+        .unwrap();
+        let concat = build_concats(&args.concat).unwrap();
+        assert_eq!(concat.len(), 1);
+        assert!(concat[0].parts.is_match("data/part-0001"));
+        assert!(!concat[0].parts.is_match("data/old/part-0001"));
+        assert_eq!(concat[0].name, Path::new("data/all.bin"));
+
+        let rule = |r: [&str; 3]| build_concats(&r.map(String::from));
+        assert!(rule(["data/part-*", "to", "all.bin"]).is_err());
+        assert!(rule(["data/part-[", "as", "all.bin"]).is_err());
+        assert!(rule(["data/part-*", "as", "/all.bin"]).is_err());
+    }
+
+    #[test]
+    fn hns_takes_its_value_after_an_equals_sign() {
+        let url = "https://account.blob.core.windows.net/data?sig=x";
+        let hns = |flag: &str| {
+            Args::try_parse_from(["razmount", flag, "/mnt/data", url]).map(|a| (a.hns, a.path))
+        };
+
+        let mount = Some(PathBuf::from("/mnt/data"));
+        assert_eq!(hns("--hns").unwrap(), (Some(true), mount.clone()));
+        assert_eq!(hns("--hns=false").unwrap(), (Some(false), mount));
+        assert!(hns("--hns=maybe").is_err());
+    }
+
+    #[test]
+    fn urls_of_each_cloud_are_parsed() {
+        let cases = [
+            (
+                "https://acct.blob.core.windows.net/data",
+                "acct",
+                "https://acct.blob.core.windows.net/",
+            ),
+            (
+                "https://acct.blob.core.usgovcloudapi.net/data?sig=x",
+                "acct",
+                "https://acct.blob.core.usgovcloudapi.net/",
+            ),
+            (
+                "https://acct.blob.core.chinacloudapi.cn/data",
+                "acct",
+                "https://acct.blob.core.chinacloudapi.cn/",
+            ),
+            (
+                "http://127.0.0.1:10000/devstoreaccount1/data",
+                "devstoreaccount1",
+                "http://127.0.0.1:10000/devstoreaccount1",
+            ),
+        ];
+
+        for (url, account, endpoint) in cases {
+            let url = Url::parse(url).unwrap();
+            let (found, location) = url_location(&url, None).unwrap();
+            let location = location
+                .url(azure_storage::clients::ServiceType::Blob)
+                .unwrap();
+            assert_eq!((found, location.as_str()), (account, endpoint), "{url}");
+        }
+
+        for url in [
+            "https://storage/data",
+            "http://localhost:10000/",
+            "file:///data",
+        ] {
+            assert!(
+                url_location(&Url::parse(url).unwrap(), None).is_err(),
+                "{url}"
+            );
+        }
     }
 }
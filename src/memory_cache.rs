@@ -0,0 +1,89 @@
+//! Whole small blobs held in memory, for `--small-blob-threshold`.
+//!
+//! Small files (configuration, JSON and the like) tend to be read over and over, often a few
+//! bytes at a time. The first read of a blob no larger than the threshold downloads all of it,
+//! and later reads are served from memory until the blob changes. Blobs are evicted least
+//! recently used first, once they add up to more than the cache's capacity.
+
+use std::sync::Mutex;
+
+use azure_core::Etag;
+use lru::LruCache;
+
+struct Cached {
+    /// The ETag of the blob the data came from, if known.
+    etag: Option<Etag>,
+    data: Vec<u8>,
+}
+
+struct State {
+    blobs: LruCache<String, Cached>,
+    /// The total size of the cached blobs, in bytes.
+    bytes: u64,
+}
+
+pub struct MemoryCache {
+    max_bytes: u64,
+    state: Mutex<State>,
+}
+
+impl MemoryCache {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(State {
+                blobs: LruCache::unbounded(),
+                bytes: 0,
+            }),
+        }
+    }
+
+    /// Fills `buf` from the cached contents of a blob, starting at `offset`, returning the ETag
+    /// they came from, or `None` if the blob isn't cached or the read extends past its end.
+    pub fn get(&self, blob: &str, offset: u64, buf: &mut [u8]) -> Option<Option<Etag>> {
+        let mut state = self.state.lock().unwrap();
+        let cached = state.blobs.get(blob)?;
+
+        let start = usize::try_from(offset).ok()?;
+        let data = cached.data.get(start..start.checked_add(buf.len())?)?;
+        buf.copy_from_slice(data);
+
+        Some(cached.etag.clone())
+    }
+
+    /// Caches the whole contents of a blob, evicting others to make room. Blobs larger than the
+    /// cache itself aren't cached.
+    pub fn insert(&self, blob: &str, etag: Option<Etag>, data: Vec<u8>) {
+        let len = data.len() as u64;
+        if len > self.max_bytes {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.blobs.put(blob.to_string(), Cached { etag, data }) {
+            state.bytes -= old.data.len() as u64;
+        }
+        state.bytes += len;
+
+        while state.bytes > self.max_bytes {
+            let Some((_, evicted)) = state.blobs.pop_lru() else {
+                break;
+            };
+            state.bytes -= evicted.data.len() as u64;
+        }
+    }
+
+    /// Drops a blob whose contents have changed since they were cached.
+    pub fn invalidate(&self, blob: &str, etag: &Etag) {
+        let mut state = self.state.lock().unwrap();
+        let stale = state
+            .blobs
+            .peek(blob)
+            .is_some_and(|c| c.etag.as_ref() != Some(etag));
+        if stale {
+            if let Some(evicted) = state.blobs.pop(blob) {
+                state.bytes -= evicted.data.len() as u64;
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Matching of the search patterns that applications enumerate directories with (e.g. `*.csv`).
+//!
+//! `*` matches any run of characters and `?` matches exactly one. Patterns may also have the DOS
+//! wildcards that Windows translates some patterns into (e.g. `*.` into `<"*`), which match as
+//! they do for `FsRtlIsNameInExpression`:
+//!
+//! - `<` matches any run of characters up to the name's last `.`.
+//! - `>` matches any one character, or nothing at a `.` or at the end of the name.
+//! - `"` matches a `.`, or nothing at the end of the name.
+//!
+//! Matching is case-insensitive, like the rest of the Windows file system.
+
+/// A search pattern for a directory enumeration.
+#[derive(Debug, Clone)]
+pub struct SearchPattern(Vec<char>);
+
+impl SearchPattern {
+    /// Parses a pattern, returning `None` if it matches everything.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub fn new(pattern: &str) -> Option<Self> {
+        if pattern.is_empty() || pattern.chars().all(|c| c == '*') {
+            return None;
+        }
+
+        Some(Self(fold(pattern).collect()))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = fold(name).collect::<Vec<_>>();
+        let pat = &self.0;
+        let last_dot = name.iter().rposition(|&c| c == '.');
+
+        // `end[n]` says whether `pat[p..]` matches `name[n..]`, for each `p` from the end of the
+        // pattern back to its start.
+        let mut end = vec![false; name.len() + 1];
+        end[name.len()] = true;
+
+        for &c in pat.iter().rev() {
+            let next = end;
+            end = vec![false; name.len() + 1];
+
+            for n in (0..=name.len()).rev() {
+                let here = name.get(n).copied();
+                end[n] = match c {
+                    '*' => next[n] || (here.is_some() && end[n + 1]),
+                    // Up to the last `.`, or to the end of the name without one.
+                    '<' => next[n] || (here.is_some() && Some(n) != last_dot && end[n + 1]),
+                    '?' => here.is_some() && next[n + 1],
+                    '>' => match here {
+                        None | Some('.') => next[n],
+                        Some(_) => next[n + 1],
+                    },
+                    '"' => match here {
+                        None => next[n],
+                        Some('.') => next[n + 1],
+                        Some(_) => false,
+                    },
+                    c => here == Some(c) && next[n + 1],
+                };
+            }
+        }
+
+        end[0]
+    }
+
+    /// The literal text every matching name starts with, if it can be used as a case-sensitive
+    /// listing prefix. Names only differ by case where a character has case, so the prefix is
+    /// only given if none of its characters do.
+    pub fn listing_prefix(&self) -> Option<String> {
+        let prefix = self
+            .0
+            .iter()
+            .take_while(|c| !WILDCARDS.contains(c))
+            .collect::<String>();
+
+        let caseless = prefix
+            .chars()
+            .all(|c| c.to_lowercase().eq(c.to_uppercase()));
+
+        (!prefix.is_empty() && caseless).then_some(prefix)
+    }
+}
+
+const WILDCARDS: [char; 5] = ['*', '?', '<', '>', '"'];
+
+fn fold(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars().flat_map(char::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        SearchPattern::new(pattern).is_none_or(|p| p.matches(name))
+    }
+
+    #[test]
+    fn common_patterns_match() {
+        let cases = [
+            ("*", "anything.txt", true),
+            ("*", "", true),
+            ("?", "a", true),
+            ("?", "ab", false),
+            ("?", "", false),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("*.txt", "notes.txt", true),
+            ("*.txt", "NOTES.TXT", true),
+            ("*.txt", "notes.txt.bak", false),
+            ("*.txt", "a.b.txt", true),
+            ("*.txt", "txt", false),
+            ("data*.c?v", "data-2024.csv", true),
+            ("data*.c?v", "data.json", false),
+            ("report.pdf", "Report.PDF", true),
+            ("report.pdf", "report.pdfx", false),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(matches(pattern, name), expected, "{pattern} {name}");
+        }
+    }
+
+    #[test]
+    fn dos_wildcards_match() {
+        let cases = [
+            // `<` stops at the last `.`.
+            ("<.txt", "a.b.txt", true),
+            ("<.txt", "notes.txt", true),
+            ("<", "noext", true),
+            ("<", "a.txt", false),
+            ("<.*", "a.b.c", true),
+            // `>` matches one character, or nothing at a `.` or the end.
+            ("a>>>", "a", true),
+            ("a>>>", "abc", true),
+            ("a>>>", "abcde", false),
+            ("a>>>.txt", "ab.txt", true),
+            ("a>>>.txt", "abcde.txt", false),
+            // `"` matches a `.`, or nothing at the end.
+            ("a\"", "a", true),
+            ("a\"", "a.", true),
+            ("a\"b", "a.b", true),
+            ("a\"b", "ab", false),
+            // `*.` as Windows hands it over: names without an extension.
+            ("<\"*", "readme", true),
+            ("<\"*", "readme.md", true),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(matches(pattern, name), expected, "{pattern} {name}");
+        }
+    }
+
+    #[test]
+    fn literal_prefixes_stop_at_any_wildcard() {
+        let prefix = |p: &str| SearchPattern::new(p).and_then(|p| p.listing_prefix());
+        assert_eq!(prefix("2024*.csv").as_deref(), Some("2024"));
+        assert_eq!(prefix("2024<.csv").as_deref(), Some("2024"));
+        assert_eq!(prefix("12>4.csv").as_deref(), Some("12"));
+        assert_eq!(prefix("data*"), None);
+    }
+}
@@ -0,0 +1,386 @@
+//! Projects a [`BlobBackend`] onto a directory with the Windows Projected File System.
+
+use std::{
+    fs::{File, OpenOptions},
+    os::windows::{ffi::OsStrExt, fs::OpenOptionsExt, io::AsRawHandle},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use projfs::{start_proj_virtualization, FileBasicInfo, Instance, ProjFSDirEnum, ProjFSRead};
+
+use windows_sys::Win32::{
+    Storage::FileSystem::{
+        DefineDosDeviceW, FileAttributeTagInfo, GetFileInformationByHandleEx, QueryDosDeviceW,
+        DDD_EXACT_MATCH_ON_REMOVE, DDD_RAW_TARGET_PATH, DDD_REMOVE_DEFINITION,
+        FILE_ATTRIBUTE_REPARSE_POINT, FILE_ATTRIBUTE_TAG_INFO, FILE_FLAG_BACKUP_SEMANTICS,
+        FILE_FLAG_OPEN_REPARSE_POINT, FILE_READ_ATTRIBUTES, FILE_WRITE_ATTRIBUTES, FILE_WRITE_DATA,
+    },
+    System::{
+        Ioctl::FSCTL_DELETE_REPARSE_POINT,
+        SystemServices::{IO_REPARSE_TAG_PROJFS, IO_REPARSE_TAG_PROJFS_TOMBSTONE},
+        IO::DeviceIoControl,
+    },
+};
+
+use crate::{
+    backend::{filetime, BlobBackend, Entry, FILE_ATTRIBUTE_READONLY},
+    pattern::SearchPattern,
+};
+
+/// The reparse tags ProjFS marks the virtualization root and its placeholders with, and deleted
+/// placeholders with.
+const PROJFS_TAGS: [u32; 2] = [IO_REPARSE_TAG_PROJFS, IO_REPARSE_TAG_PROJFS_TOMBSTONE];
+
+/// The alternate data stream that exposes a blob's properties, e.g. `file.txt:azmeta`.
+const META_STREAM: &str = "azmeta";
+
+pub type Mounted = Instance<BlobFSDriver>;
+
+pub struct BlobFSDriver {
+    backend: Arc<BlobBackend>,
+    /// Required by the current API for ProjFS.
+    iter_cache: projfs::CacheMap<<Self as ProjFSDirEnum>::DirIter>,
+}
+
+/// Starts projecting `backend` onto `path`, which must already exist.
+pub fn mount(path: &Path, backend: BlobBackend) -> Result<Mounted> {
+    let driver = BlobFSDriver {
+        backend: Arc::new(backend),
+        iter_cache: Default::default(),
+    };
+
+    // N.B: Paths that ProjFS hands to callbacks are relative to the root, so they aren't subject
+    // to `MAX_PATH` here, regardless of how deep the blob hierarchy goes.
+    start_proj_virtualization(path, Box::new(driver))
+        .map_err(|hr| anyhow!("failed to start virtualization: {hr:#010x}"))
+}
+
+/// Cleans up after a mount whose razmount is no longer running, so that the directory can be
+/// mounted again or deleted: the directory stops being a virtualization root, and the placeholders
+/// ProjFS left in it are removed. Files that were added to the directory are kept.
+///
+/// Returns whether there was anything to clean up, which there isn't if `path` doesn't exist.
+pub fn unmount(path: &Path) -> Result<bool> {
+    let root = match open_reparse_point(path) {
+        Ok(root) => root,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+    };
+    let Some(tag) = projfs_tag(&root)? else {
+        return Ok(false);
+    };
+
+    // ProjFS refuses while the root is still being served.
+    delete_reparse_point(&root, tag).with_context(|| {
+        format!(
+            "failed to remove the virtualization root at {}; if razmount is still serving it, \
+             stop it first",
+            path.display()
+        )
+    })?;
+    drop(root);
+
+    remove_placeholders(path)?;
+    Ok(true)
+}
+
+/// A drive letter that leads to a mounted directory, as `subst` would map it. Dropping it removes
+/// the drive letter again.
+pub struct DriveMapping {
+    /// The drive, e.g. `Z:`, as a NUL-terminated wide string.
+    drive: Vec<u16>,
+    /// The NT path the drive leads to, as a NUL-terminated wide string.
+    target: Vec<u16>,
+}
+
+/// Maps `drive` (e.g. `Z:`) to `path`, which must be an extended-length path, for the current
+/// user's session.
+pub fn map_drive(drive: &str, path: &Path) -> Result<DriveMapping> {
+    let wide = |s: &std::ffi::OsStr| s.encode_wide().chain([0]).collect::<Vec<_>>();
+    let device = wide(drive.as_ref());
+
+    // Raw targets are NT paths, which differ from extended-length paths only in their prefix.
+    let Some(path) = path.to_str().and_then(|p| p.strip_prefix(r"\\?\")) else {
+        bail!("cannot map a drive to {}", path.display());
+    };
+    let target = wide(format!(r"\??\{path}").as_ref());
+
+    // Defining a drive that's already in use would shadow it until the mapping is removed.
+    let mut existing = [0u16; 260];
+    // SAFETY: `drive` is NUL-terminated, and `existing` is a buffer of the length given.
+    let len = unsafe {
+        QueryDosDeviceW(
+            device.as_ptr(),
+            existing.as_mut_ptr(),
+            existing.len() as u32,
+        )
+    };
+    if len != 0 {
+        bail!("drive {drive} is already in use");
+    }
+
+    // SAFETY: both strings are NUL-terminated.
+    let ok = unsafe { DefineDosDeviceW(DDD_RAW_TARGET_PATH, device.as_ptr(), target.as_ptr()) };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to map drive {drive}"));
+    }
+
+    Ok(DriveMapping {
+        drive: device,
+        target,
+    })
+}
+
+impl Drop for DriveMapping {
+    fn drop(&mut self) {
+        // Only remove this mapping, in case another has since been defined over it.
+        let flags = DDD_RAW_TARGET_PATH | DDD_REMOVE_DEFINITION | DDD_EXACT_MATCH_ON_REMOVE;
+        // SAFETY: both strings are NUL-terminated.
+        let ok = unsafe { DefineDosDeviceW(flags, self.drive.as_ptr(), self.target.as_ptr()) };
+        if ok == 0 {
+            let drive = String::from_utf16_lossy(&self.drive[..self.drive.len() - 1]);
+            log::warn!(
+                "failed to remove drive {drive}: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// Opens a file or folder itself, as opposed to whatever its reparse point leads to.
+fn open_reparse_point(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .access_mode(FILE_READ_ATTRIBUTES | FILE_WRITE_ATTRIBUTES | FILE_WRITE_DATA)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(path)
+}
+
+/// The ProjFS reparse tag of a file or folder, if it has one.
+fn projfs_tag(file: &File) -> std::io::Result<Option<u32>> {
+    let mut info = FILE_ATTRIBUTE_TAG_INFO {
+        FileAttributes: 0,
+        ReparseTag: 0,
+    };
+    // SAFETY: `info` is a valid buffer of the size given, for the class of information requested.
+    let ok = unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle() as isize,
+            FileAttributeTagInfo,
+            std::ptr::addr_of_mut!(info).cast(),
+            std::mem::size_of_val(&info) as u32,
+        )
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let reparse = info.FileAttributes & FILE_ATTRIBUTE_REPARSE_POINT != 0;
+    Ok((reparse && PROJFS_TAGS.contains(&info.ReparseTag)).then_some(info.ReparseTag))
+}
+
+fn delete_reparse_point(file: &File, tag: u32) -> std::io::Result<()> {
+    /// The header of a `REPARSE_DATA_BUFFER`, which is all that deleting a reparse point takes.
+    #[repr(C)]
+    struct ReparseHeader {
+        tag: u32,
+        data_length: u16,
+        reserved: u16,
+    }
+
+    let header = ReparseHeader {
+        tag,
+        data_length: 0,
+        reserved: 0,
+    };
+    let mut returned = 0;
+    // SAFETY: `header` is a valid input buffer of the size given, and no output is requested.
+    let ok = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle() as isize,
+            FSCTL_DELETE_REPARSE_POINT,
+            std::ptr::addr_of!(header).cast(),
+            std::mem::size_of_val(&header) as u32,
+            std::ptr::null_mut(),
+            0,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Removes the placeholders under `dir`, keeping anything else along with the folders it's in.
+fn remove_placeholders(dir: &Path) -> Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let file = open_reparse_point(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let tag = projfs_tag(&file)?;
+        let is_dir = file.metadata()?.is_dir();
+        drop(file);
+
+        if is_dir {
+            remove_placeholders(&path)?;
+        }
+        let Some(tag) = tag else {
+            continue;
+        };
+
+        // Read-only mounts project read-only files, which can't be deleted as they are.
+        let mut permissions = std::fs::metadata(&path)?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            std::fs::set_permissions(&path, permissions)?;
+        }
+
+        let removed = if is_dir {
+            std::fs::remove_dir(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        if removed.is_err() {
+            // A folder that still has files in it is kept, as an ordinary folder.
+            delete_reparse_point(&open_reparse_point(&path)?, tag)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits a stream-qualified path such as `dir/file.txt:name:$DATA` into the file's path and the
+/// name of the stream. The unnamed (primary) stream is returned as `None`.
+fn split_stream(path: PathBuf) -> (PathBuf, Option<String>) {
+    let Some((file, stream)) = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .and_then(|f| f.split_once(':'))
+    else {
+        return (path, None);
+    };
+
+    // Drop the stream type, if present; `$DATA` is the only type of stream that can be opened.
+    let name = stream.split(':').next().unwrap_or_default();
+    let stream = (!name.is_empty()).then(|| name.to_string());
+
+    (path.with_file_name(file), stream)
+}
+
+/// Projects an entry with its blob's times. Blobs have no separate change time, so a change to
+/// a blob's metadata counts as a write, as it does for the blob's last-modified time.
+fn blob_props_to_file_info(entry: Entry) -> FileBasicInfo {
+    let modified = entry.modified.map_or(0, filetime);
+
+    FileBasicInfo {
+        file_name: entry.name.into(),
+        is_dir: entry.is_dir,
+        file_size: entry.size,
+        created: entry.created.map_or(0, filetime),
+        accessed: filetime(entry.fetched),
+        writed: modified,
+        changed: modified,
+        attrs: entry.attrs,
+    }
+}
+
+impl ProjFSDirEnum for BlobFSDriver {
+    type DirIter = Box<dyn Iterator<Item = FileBasicInfo> + Send + Sync>;
+
+    /// Starts an enumeration, which ProjFS then drains over as many callbacks as it takes to fill
+    /// its buffers. The iterator is kept in `iter_cache` under the enumeration's ID between them,
+    /// and lists the folder a page at a time as it's drained, so huge folders are neither listed
+    /// up front nor held in memory as a whole (until the listing is complete and cached).
+    fn dir_iter(
+        &self,
+        _id: projfs::Guid,
+        path: projfs::RawPath,
+        pattern: Option<projfs::RawPath>,
+        _version: projfs::VersionInfo,
+    ) -> std::io::Result<Self::DirIter> {
+        let pattern = pattern.and_then(|p| SearchPattern::new(&p.to_path_buf().to_string_lossy()));
+        let items = self
+            .backend
+            .enumerate(&path.to_path_buf(), pattern.as_ref())?;
+
+        Ok(Box::new(items.map(blob_props_to_file_info)))
+    }
+
+    fn dir_iter_cache(&self, _version: projfs::VersionInfo) -> &projfs::CacheMap<Self::DirIter> {
+        &self.iter_cache
+    }
+}
+
+impl ProjFSRead for BlobFSDriver {
+    fn get_metadata(
+        &self,
+        path: projfs::RawPath,
+        _version: projfs::VersionInfo,
+    ) -> std::io::Result<FileBasicInfo> {
+        let (path, stream) = split_stream(path.to_path_buf());
+
+        match stream.as_deref() {
+            None => self.backend.metadata(&path).map(blob_props_to_file_info),
+            Some(META_STREAM) => {
+                let doc = self.backend.meta_stream(&path)?;
+                let leaf = path.file_name().unwrap_or_default().to_string_lossy();
+
+                Ok(blob_props_to_file_info(Entry {
+                    name: format!("{leaf}:{META_STREAM}"),
+                    is_dir: false,
+                    size: doc.len() as u64,
+                    attrs: FILE_ATTRIBUTE_READONLY,
+                    etag: None,
+                    ..self.backend.metadata(&path)?
+                }))
+            }
+            Some(stream) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no stream `{stream}` on {}", path.display()),
+            )),
+        }
+    }
+
+    fn read(
+        &self,
+        path: projfs::RawPath,
+        _version: projfs::VersionInfo,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<()> {
+        let (path, stream) = split_stream(path.to_path_buf());
+
+        // Only the unnamed stream serves the blob's contents.
+        match stream.as_deref() {
+            None => self.backend.read(&path, offset, buf),
+            Some(META_STREAM) => {
+                let doc = self.backend.meta_stream(&path)?;
+                let data = usize::try_from(offset)
+                    .ok()
+                    .and_then(|start| doc.get(start..start.checked_add(buf.len())?))
+                    .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            format!("read past the end of {}:{META_STREAM}", path.display()),
+                        )
+                    })?;
+
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            Some(stream) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no stream `{stream}` on {}", path.display()),
+            )),
+        }
+    }
+}
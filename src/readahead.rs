@@ -0,0 +1,235 @@
+//! Read-ahead for blobs that are read front-to-back.
+//!
+//! Each blob being read tracks where its last read ended. Reads that pick up where the previous
+//! one left off are considered sequential, and kick off background downloads of the ranges that
+//! follow, so that subsequent reads can be served without waiting on Azure. A read anywhere else
+//! cancels any outstanding downloads for that blob.
+//!
+//! With a block size, downloads start on block boundaries and are a whole number of blocks long,
+//! like the downloads of the reads themselves.
+//!
+//! The same tracking measures how far along sequential reads are, for progress reports.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use azure_core::{Etag, StatusCode};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use log::warn;
+use lru::LruCache;
+use tokio::{runtime::Handle, task::AbortHandle};
+
+/// The number of blobs to track read positions for.
+const MAX_STREAMS: NonZeroUsize = match NonZeroUsize::new(64) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+/// A downloaded range and the ETag of the blob it came from, or `None` if the download failed.
+type Prefetched = Option<Arc<(Option<Etag>, Vec<u8>)>>;
+
+struct Prefetch {
+    start: u64,
+    end: u64,
+    data: Shared<BoxFuture<'static, Prefetched>>,
+    abort: AbortHandle,
+}
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    /// The offset just past the last read, if any.
+    next: Option<u64>,
+    /// Outstanding downloads, in ascending order of offset.
+    prefetches: VecDeque<Prefetch>,
+    /// The bytes read since the last read that didn't follow on from the one before it.
+    read: u64,
+    /// When progress was last reported, or the stream started.
+    reported: Option<Instant>,
+}
+
+pub struct ReadAhead {
+    window: u64,
+    block: u64,
+    streams: Mutex<LruCache<String, Stream>>,
+}
+
+impl ReadAhead {
+    /// Creates a new read-ahead tracker that keeps up to `window` bytes in flight per blob. A zero
+    /// `window` only tracks reads, without downloading ahead of them. Downloads are aligned to
+    /// `block` bytes, if that's non-zero, and the window is rounded up to a whole number of blocks.
+    pub fn new(window: u64, block: u64) -> Self {
+        let window = match block {
+            0 => window,
+            block => window.div_ceil(block) * block,
+        };
+
+        Self {
+            window,
+            block,
+            streams: Mutex::new(LruCache::new(MAX_STREAMS)),
+        }
+    }
+
+    /// Records a read of `len` bytes at `offset`, returning whether it follows on from the
+    /// previous read of the blob. Reads at the start of a blob begin a new sequential stream.
+    pub fn access(&self, blob: &str, offset: u64, len: usize) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.get_or_insert_mut(blob.to_string(), Stream::default);
+
+        let sequential = match stream.next {
+            Some(next) => next == offset,
+            None => offset == 0,
+        };
+
+        if sequential {
+            // Anything behind this read won't be needed again.
+            stream.prefetches.retain(|p| p.end > offset);
+            stream.read += len as u64;
+        } else {
+            stream.prefetches.clear();
+            stream.read = len as u64;
+            stream.reported = None;
+        }
+
+        stream.reported.get_or_insert_with(Instant::now);
+
+        stream.next = Some(offset + len as u64);
+        sequential
+    }
+
+    /// Returns how many bytes of a blob have been read in a row, if it's been at least `interval`
+    /// since that was last returned (or since the reads began).
+    pub fn progress(&self, blob: &str, interval: Duration) -> Option<u64> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.peek_mut(blob)?;
+
+        let reported = stream.reported.as_mut()?;
+        if reported.elapsed() < interval {
+            return None;
+        }
+
+        *reported = Instant::now();
+        Some(stream.read)
+    }
+
+    /// Drops the ranges of a blob that weren't downloaded at `etag`, including any that are still
+    /// being downloaded.
+    pub fn invalidate(&self, blob: &str, etag: &Etag) {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(stream) = streams.peek_mut(blob) {
+            stream.prefetches.retain(
+                |p| matches!(p.data.peek(), Some(Some(data)) if data.0.as_ref() == Some(etag)),
+            );
+        }
+    }
+
+    /// Fills `buf` from a prefetched range covering it, waiting for the download if it is still
+    /// in flight. Returns the ETag the data was downloaded at, or `None` if nothing covers `buf`.
+    pub fn get(&self, blob: &str, offset: u64, buf: &mut [u8]) -> Option<Option<Etag>> {
+        let end = offset + buf.len() as u64;
+
+        let (start, data) = {
+            let mut streams = self.streams.lock().unwrap();
+            let prefetch = streams
+                .get(blob)?
+                .prefetches
+                .iter()
+                .find(|p| p.start <= offset && end <= p.end)?;
+
+            (prefetch.start, prefetch.data.clone())
+        };
+
+        // The download runs on the runtime regardless, so this merely waits for it to finish.
+        let data = futures::executor::block_on(data)?;
+        let (etag, bytes) = &*data;
+
+        // The download may have been truncated by the end of the blob.
+        let bytes = bytes.get((offset - start) as usize..(end - start) as usize)?;
+        buf.copy_from_slice(bytes);
+
+        Some(etag.clone())
+    }
+
+    /// Starts downloading the ranges following the last read of a blob, in `chunk`-sized pieces,
+    /// until the read-ahead window is full.
+    pub fn prefetch<F, Fut>(&self, blob: &str, chunk: usize, rt: &Handle, fetch: F)
+    where
+        F: Fn(u64, usize) -> Fut,
+        Fut: Future<Output = azure_core::Result<(Option<Etag>, Vec<u8>)>> + Send + 'static,
+    {
+        let chunk = match self.block {
+            0 => chunk as u64,
+            block => (chunk as u64).div_ceil(block) * block,
+        };
+        let chunk = std::cmp::min(chunk, self.window);
+        if chunk == 0 {
+            return;
+        }
+
+        let mut streams = self.streams.lock().unwrap();
+        let Some(stream) = streams.get_mut(blob) else {
+            return;
+        };
+        let Some(next) = stream.next else {
+            return;
+        };
+
+        let start = stream
+            .prefetches
+            .back()
+            .map_or(next, |p| std::cmp::max(p.end, next));
+        let mut start = match self.block {
+            0 => start,
+            block => start / block * block,
+        };
+
+        while start + chunk <= next + self.window {
+            let f = fetch(start, chunk as usize);
+            let blob = blob.to_string();
+            let mount = crate::logging::current_mount();
+
+            let handle = rt.spawn(async move {
+                match f.await {
+                    Ok(data) => Some(Arc::new(data)),
+                    // Expected when reading ahead past the end of the blob.
+                    Err(e) if is_out_of_range(&e) => None,
+                    Err(e) => {
+                        let _mount = crate::logging::MountScope::enter(mount);
+                        warn!("failed to read ahead {blob} at offset {start}: {e}");
+                        None
+                    }
+                }
+            });
+
+            stream.prefetches.push_back(Prefetch {
+                start,
+                end: start + chunk,
+                abort: handle.abort_handle(),
+                data: handle.map(|r| r.ok().flatten()).boxed().shared(),
+            });
+
+            start += chunk;
+        }
+    }
+}
+
+fn is_out_of_range(e: &azure_core::Error) -> bool {
+    matches!(
+        e.kind(),
+        azure_core::error::ErrorKind::HttpResponse {
+            status: StatusCode::RequestedRangeNotSatisfiable,
+            ..
+        }
+    )
+}
@@ -0,0 +1,577 @@
+//! The requests the backend makes of a container, so that it can be driven by something other
+//! than Azure (e.g. canned listings in tests).
+
+use std::{
+    collections::BTreeSet,
+    num::NonZeroU32,
+    ops::{Bound, Range},
+    sync::Arc,
+    time::Duration,
+};
+
+use azure_core::{
+    error::ErrorKind, request_options::IfMatchCondition, Context, Etag, Policy, PolicyResult,
+    Request, StatusCode,
+};
+use azure_storage_blobs::{
+    blob::Blob,
+    container::operations::BlobItem,
+    prelude::{AccessTier, BlobServiceClient, BlobVersioning, ContainerClient},
+};
+use futures::{
+    future::BoxFuture, stream::BoxStream, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+};
+
+/// What to include in a blob listing.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// Only list blobs whose names start with this.
+    pub prefix: Option<String>,
+    /// Roll up blobs under the next occurrence of this after the prefix into
+    /// `BlobItem::BlobPrefix` items, rather than listing everything under the prefix.
+    pub delimiter: Option<char>,
+    /// The maximum number of items to return per page.
+    pub max_results: Option<NonZeroU32>,
+    pub include_snapshots: bool,
+    pub include_versions: bool,
+    /// Include each blob's user metadata.
+    pub include_metadata: bool,
+    /// Include blobs that have been soft-deleted, and can still be recovered.
+    pub include_deleted: bool,
+}
+
+/// A container of blobs.
+pub trait BlobSource: Send + Sync + 'static {
+    fn container_name(&self) -> &str;
+
+    /// Lists blobs in name order, one page at a time.
+    fn list(&self, options: ListOptions) -> BoxStream<'static, azure_core::Result<Vec<BlobItem>>>;
+
+    /// Fetches the properties of a blob.
+    fn properties(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+    ) -> BoxFuture<'static, azure_core::Result<Blob>>;
+
+    /// Downloads `len` bytes of a blob starting at `offset`, along with the blob's ETag.
+    ///
+    /// The result is shorter than requested if the range extends past the end of the blob. If
+    /// `if_match` is given, the request fails with `412 Precondition Failed` unless the blob still
+    /// has that ETag.
+    fn get_range(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+        offset: u64,
+        len: usize,
+    ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>>;
+
+    /// Lists the (end-exclusive) ranges of a page blob that have been written to, along with the
+    /// blob's ETag. `if_match` is as for [`Self::get_range`].
+    fn page_ranges(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+    ) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>>;
+
+    /// Moves a blob to another access tier, e.g. to rehydrate it from the archive tier.
+    fn set_tier(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        tier: AccessTier,
+    ) -> BoxFuture<'static, azure_core::Result<()>>;
+}
+
+impl BlobSource for ContainerClient {
+    fn container_name(&self) -> &str {
+        ContainerClient::container_name(self)
+    }
+
+    fn list(&self, options: ListOptions) -> BoxStream<'static, azure_core::Result<Vec<BlobItem>>> {
+        let mut list = self.list_blobs();
+        if let Some(prefix) = options.prefix {
+            list = list.prefix(prefix);
+        }
+        if let Some(delimiter) = options.delimiter {
+            list = list.delimiter(delimiter.to_string());
+        }
+        if let Some(n) = options.max_results {
+            list = list.max_results(n);
+        }
+        if options.include_snapshots {
+            list = list.include_snapshots(true);
+        }
+        if options.include_versions {
+            list = list.include_versions(true);
+        }
+        if options.include_metadata {
+            list = list.include_metadata(true);
+        }
+        if options.include_deleted {
+            list = list.include_deleted(true);
+        }
+
+        list.into_stream().map_ok(|page| page.blobs.items).boxed()
+    }
+
+    fn properties(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+    ) -> BoxFuture<'static, azure_core::Result<Blob>> {
+        let mut r = self.blob_client(name).get_properties();
+        if let Some(version) = version {
+            r = r.blob_versioning(version);
+        }
+
+        r.into_future().map(|r| r.map(|r| r.blob)).boxed()
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+        offset: u64,
+        len: usize,
+    ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>> {
+        // N.B: `Range` is end-exclusive, and is sent as the inclusive `bytes=start-(end - 1)`.
+        // Azure truncates ranges that extend past the end of the blob, so the final chunk of a
+        // blob may come back shorter than requested.
+        let mut r = self
+            .blob_client(name)
+            .get()
+            .range(azure_core::request_options::Range {
+                start: offset,
+                end: offset + (len as u64),
+            });
+        if let Some(version) = version {
+            r = r.blob_versioning(version);
+        }
+        if let Some(etag) = if_match {
+            r = r.if_match(IfMatchCondition::Match(etag.to_string()));
+        }
+
+        let mut r = r.into_stream();
+
+        async move {
+            let mut etag = None;
+            let mut download = Download::new(offset, len);
+
+            while let Some(r) = r.try_next().await? {
+                etag.get_or_insert(r.blob.properties.etag);
+                let bytes = r.data.collect().await?;
+
+                download.add(r.content_range.map(|range| range.start), &bytes);
+            }
+
+            Ok((etag, download.finish()))
+        }
+        .boxed()
+    }
+
+    fn page_ranges(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+    ) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>> {
+        let mut r = self.blob_client(name).get_page_ranges();
+        if let Some(version) = version {
+            r = r.blob_versioning(version);
+        }
+        if let Some(etag) = if_match {
+            r = r.if_match(IfMatchCondition::Match(etag.to_string()));
+        }
+
+        r.into_future()
+            .map(|r| {
+                let pages = r?;
+
+                // N.B: Unlike `Range`, page ranges are inclusive on the end.
+                let ranges = pages
+                    .page_list
+                    .ranges
+                    .into_iter()
+                    .map(|p| p.start..p.end + 1)
+                    .collect();
+
+                Ok((Etag::from(pages.etag), ranges))
+            })
+            .boxed()
+    }
+
+    fn set_tier(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        tier: AccessTier,
+    ) -> BoxFuture<'static, azure_core::Result<()>> {
+        let mut r = self.blob_client(name).set_blob_tier(tier);
+        if let Some(version) = version {
+            r = r.blob_versioning(version);
+        }
+
+        r.into_future().map(|r| r.map(|_| ())).boxed()
+    }
+}
+
+/// A range of a blob being downloaded, which may arrive in several parts.
+struct Download {
+    /// Where the range starts in the blob.
+    offset: u64,
+    buf: Vec<u8>,
+    /// How much of `buf` has been filled in.
+    written: usize,
+    /// Where in the blob the next part starts, if it doesn't say. A response without a content
+    /// range holds the whole blob, from the start.
+    next: u64,
+}
+
+impl Download {
+    fn new(offset: u64, len: usize) -> Self {
+        Self {
+            offset,
+            buf: vec![0u8; len],
+            written: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds a part of the download, which starts at `start` in the blob (from its content range),
+    /// or else where the last part ended. Only what falls within the range is kept.
+    fn add(&mut self, start: Option<u64>, bytes: &[u8]) {
+        let start = start.unwrap_or(self.next);
+        self.next = start + bytes.len() as u64;
+
+        let from = std::cmp::max(start, self.offset);
+        let to = std::cmp::min(self.next, self.offset + self.buf.len() as u64);
+        if from >= to {
+            return;
+        }
+
+        self.buf[(from - self.offset) as usize..(to - self.offset) as usize]
+            .copy_from_slice(&bytes[(from - start) as usize..(to - start) as usize]);
+        self.written = std::cmp::max(self.written, (to - self.offset) as usize);
+    }
+
+    /// The range, which is shorter than requested if it extends past the end of the blob.
+    fn finish(mut self) -> Vec<u8> {
+        self.buf.truncate(self.written);
+        self.buf
+    }
+}
+
+/// A per-retry pipeline policy that gives up on an attempt at a request if it hasn't been answered
+/// within a time limit.
+///
+/// The attempt fails with an I/O error, which the SDK's retry policy treats as transient, so that
+/// a stalled connection is retried like a dropped one rather than failing the request outright.
+#[derive(Debug)]
+pub struct AttemptTimeout(pub Duration);
+
+#[async_trait::async_trait]
+impl Policy for AttemptTimeout {
+    async fn send(
+        &self,
+        ctx: &Context,
+        request: &mut Request,
+        next: &[Arc<dyn Policy>],
+    ) -> PolicyResult {
+        match tokio::time::timeout(self.0, next[0].send(ctx, request, &next[1..])).await {
+            Ok(r) => r,
+            Err(_) => Err(azure_core::Error::new(
+                ErrorKind::Io,
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("no response from blob storage within {:?}", self.0),
+                ),
+            )),
+        }
+    }
+}
+
+/// A container whose blob names separate folders with something other than `/` (e.g. `\` in
+/// legacy exports), presented to the backend as if they used `/`.
+///
+/// A `/` within a name can't be represented in a file name either way. It's replaced with a NUL,
+/// which the backend hides like any other unrepresentable name, and is restored on the way out.
+pub struct Delimited<S> {
+    inner: S,
+    delimiter: char,
+}
+
+impl<S> Delimited<S> {
+    pub fn new(inner: S, delimiter: char) -> Self {
+        Self { inner, delimiter }
+    }
+
+    /// Converts a name as the backend sees it into the blob's name.
+    fn to_blob(&self, name: &str) -> String {
+        if self.delimiter == '/' {
+            return name.to_string();
+        }
+
+        name.replace('/', &self.delimiter.to_string())
+            .replace('\0', "/")
+    }
+}
+
+/// Converts a blob's name into the name the backend sees.
+fn to_virtual(name: &str, delimiter: char) -> String {
+    if delimiter == '/' {
+        return name.to_string();
+    }
+
+    name.replace('/', "\0").replace(delimiter, "/")
+}
+
+impl<S: BlobSource> BlobSource for Delimited<S> {
+    fn container_name(&self) -> &str {
+        self.inner.container_name()
+    }
+
+    fn list(&self, options: ListOptions) -> BoxStream<'static, azure_core::Result<Vec<BlobItem>>> {
+        let delimiter = self.delimiter;
+        let options = ListOptions {
+            prefix: options.prefix.map(|p| self.to_blob(&p)),
+            delimiter: options.delimiter.map(|_| delimiter),
+            ..options
+        };
+
+        self.inner
+            .list(options)
+            .map_ok(move |items| {
+                items
+                    .into_iter()
+                    .map(|mut item| {
+                        match &mut item {
+                            BlobItem::Blob(b) => b.name = to_virtual(&b.name, delimiter),
+                            BlobItem::BlobPrefix(p) => p.name = to_virtual(&p.name, delimiter),
+                        }
+                        item
+                    })
+                    .collect()
+            })
+            .boxed()
+    }
+
+    fn properties(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+    ) -> BoxFuture<'static, azure_core::Result<Blob>> {
+        let delimiter = self.delimiter;
+        self.inner
+            .properties(&self.to_blob(name), version)
+            .map_ok(move |mut blob| {
+                blob.name = to_virtual(&blob.name, delimiter);
+                blob
+            })
+            .boxed()
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+        offset: u64,
+        len: usize,
+    ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>> {
+        self.inner
+            .get_range(&self.to_blob(name), version, if_match, offset, len)
+    }
+
+    fn page_ranges(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+    ) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>> {
+        self.inner
+            .page_ranges(&self.to_blob(name), version, if_match)
+    }
+
+    fn set_tier(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        tier: AccessTier,
+    ) -> BoxFuture<'static, azure_core::Result<()>> {
+        self.inner.set_tier(&self.to_blob(name), version, tier)
+    }
+}
+
+/// A container narrowed down to the blobs whose index tags matched an expression when it was
+/// created (see [`find_blobs_by_tags`]).
+///
+/// Folders are only listed if they contain a matching blob, and other blobs can't be looked up or
+/// read, as if they didn't exist.
+pub struct TagFiltered<S> {
+    inner: S,
+    /// The names of the matching blobs, or `None` to keep every blob.
+    names: Option<Arc<BTreeSet<String>>>,
+}
+
+impl<S> TagFiltered<S> {
+    pub fn new(inner: S, names: Option<BTreeSet<String>>) -> Self {
+        Self {
+            inner,
+            names: names.map(Arc::new),
+        }
+    }
+
+    fn check(&self, name: &str) -> azure_core::Result<()> {
+        match &self.names {
+            Some(names) if !names.contains(name) => Err(azure_core::Error::message(
+                ErrorKind::HttpResponse {
+                    status: StatusCode::NotFound,
+                    error_code: Some("BlobNotFound".into()),
+                },
+                "the blob's tags don't match the tag filter",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether any of `names` starts with `prefix`.
+fn has_prefix(names: &BTreeSet<String>, prefix: &str) -> bool {
+    names
+        .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+        .next()
+        .is_some_and(|n| n.starts_with(prefix))
+}
+
+/// Finds the names of the blobs in a container whose index tags match `expression`, e.g.
+/// `"project" = 'alpha' AND "year" >= '2024'`.
+pub async fn find_blobs_by_tags(
+    service: &BlobServiceClient,
+    container: &str,
+    expression: &str,
+) -> azure_core::Result<BTreeSet<String>> {
+    // N.B: The search covers the whole account unless it's narrowed down to the container.
+    let mut pages = service
+        .find_blobs_by_tags(format!("@container = '{container}' AND {expression}"))
+        .into_stream();
+
+    let mut names = BTreeSet::new();
+    while let Some(page) = pages.try_next().await? {
+        names.extend(
+            page.blobs
+                .into_iter()
+                .filter(|b| b.container_name == container)
+                .map(|b| b.name),
+        );
+    }
+
+    Ok(names)
+}
+
+impl<S: BlobSource> BlobSource for TagFiltered<S> {
+    fn container_name(&self) -> &str {
+        self.inner.container_name()
+    }
+
+    fn list(&self, options: ListOptions) -> BoxStream<'static, azure_core::Result<Vec<BlobItem>>> {
+        let Some(names) = self.names.clone() else {
+            return self.inner.list(options);
+        };
+
+        self.inner
+            .list(options)
+            .map_ok(move |items| {
+                items
+                    .into_iter()
+                    .filter(|item| match item {
+                        BlobItem::Blob(b) => names.contains(&b.name),
+                        BlobItem::BlobPrefix(p) => has_prefix(&names, &p.name),
+                    })
+                    .collect()
+            })
+            .boxed()
+    }
+
+    fn properties(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+    ) -> BoxFuture<'static, azure_core::Result<Blob>> {
+        match self.check(name) {
+            Ok(()) => self.inner.properties(name, version),
+            Err(e) => futures::future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn get_range(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+        offset: u64,
+        len: usize,
+    ) -> BoxFuture<'static, azure_core::Result<(Option<Etag>, Vec<u8>)>> {
+        match self.check(name) {
+            Ok(()) => self.inner.get_range(name, version, if_match, offset, len),
+            Err(e) => futures::future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn page_ranges(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        if_match: Option<Etag>,
+    ) -> BoxFuture<'static, azure_core::Result<(Etag, Vec<Range<u64>>)>> {
+        match self.check(name) {
+            Ok(()) => self.inner.page_ranges(name, version, if_match),
+            Err(e) => futures::future::ready(Err(e)).boxed(),
+        }
+    }
+
+    fn set_tier(
+        &self,
+        name: &str,
+        version: Option<BlobVersioning>,
+        tier: AccessTier,
+    ) -> BoxFuture<'static, azure_core::Result<()>> {
+        match self.check(name) {
+            Ok(()) => self.inner.set_tier(name, version, tier),
+            Err(e) => futures::future::ready(Err(e)).boxed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(offset: u64, len: usize, parts: &[(Option<u64>, &[u8])]) -> Vec<u8> {
+        let mut download = Download::new(offset, len);
+        for &(start, bytes) in parts {
+            download.add(start, bytes);
+        }
+
+        download.finish()
+    }
+
+    #[test]
+    fn ranges_are_assembled_from_parts() {
+        let parts: &[(Option<u64>, &[u8])] = &[(Some(4), b"efgh"), (Some(8), b"ij")];
+        assert_eq!(assemble(4, 8, parts), b"efghij");
+    }
+
+    #[test]
+    fn blobs_smaller_than_the_buffer_are_truncated() {
+        // Without a content range, the response holds the whole blob.
+        assert_eq!(assemble(0, 16, &[(None, b"tiny")]), b"tiny");
+        assert_eq!(assemble(2, 16, &[(None, b"tiny")]), b"ny");
+        assert_eq!(assemble(8, 16, &[(None, b"tiny")]), b"");
+        assert_eq!(assemble(0, 16, &[(Some(0), b"tiny")]), b"tiny");
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// A monotonically increasing count, cheap enough to bump from every callback.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counters describing the work a mount has done since it started.
+#[derive(Default)]
+pub struct Stats {
+    /// Requests made to Azure on behalf of a ProjFS callback.
+    pub requests: Counter,
+    /// Requests that failed.
+    pub request_errors: Counter,
+    /// The total time spent waiting on requests, in microseconds.
+    pub request_micros: Counter,
+    /// Directory enumerations.
+    pub listings: Counter,
+    /// Directory enumerations served from the listing cache.
+    pub listing_cache_hits: Counter,
+    /// Metadata lookups.
+    pub metadata: Counter,
+    /// Metadata lookups served from the metadata cache.
+    pub metadata_cache_hits: Counter,
+    /// Read callbacks.
+    pub reads: Counter,
+    /// Bytes handed back to ProjFS.
+    pub read_bytes: Counter,
+    /// Reads served from the on-disk cache.
+    pub disk_cache_hits: Counter,
+    /// Reads served from the in-memory cache of small blobs.
+    pub memory_cache_hits: Counter,
+    /// Reads served from read-ahead.
+    pub readahead_hits: Counter,
+}
+
+impl Stats {
+    /// Records the outcome of a request to Azure.
+    pub fn record_request(&self, elapsed: Duration, ok: bool) {
+        self.requests.inc();
+        self.request_micros.add(elapsed.as_micros() as u64);
+        if !ok {
+            self.request_errors.inc();
+        }
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let requests = self.requests.get();
+        let avg_ms = if requests == 0 {
+            0.0
+        } else {
+            self.request_micros.get() as f64 / requests as f64 / 1000.0
+        };
+
+        write!(
+            f,
+            "requests: {requests} ({} failed, {avg_ms:.1}ms avg), \
+             listings: {} ({} cached), metadata: {} ({} cached), \
+             reads: {} ({} bytes, {} from memory, {} from disk cache, {} from read-ahead)",
+            self.request_errors.get(),
+            self.listings.get(),
+            self.listing_cache_hits.get(),
+            self.metadata.get(),
+            self.metadata_cache_hits.get(),
+            self.reads.get(),
+            self.read_bytes.get(),
+            self.memory_cache_hits.get(),
+            self.disk_cache_hits.get(),
+            self.readahead_hits.get(),
+        )
+    }
+}
@@ -0,0 +1,57 @@
+//! A limit on how fast blobs are downloaded, for `--max-bandwidth`.
+//!
+//! The limiter is a token bucket shared by every download of a mount, which fills at the
+//! configured rate and holds up to a second's worth of bytes. Each download takes the bytes it
+//! asks for before it's made; once the bucket runs dry, downloads go into debt, and wait (without
+//! holding up a thread) for as long as it takes the bucket to pay it back. Concurrent downloads
+//! thus queue up behind each other, and share the rate between them.
+
+use std::{
+    num::NonZeroU64,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Bucket {
+    /// The bytes that can be taken right away, or a debt if negative.
+    tokens: f64,
+    /// When `tokens` was last brought up to date.
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    /// The rate the bucket fills at, in bytes per second, which is also how many it holds.
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: NonZeroU64) -> Self {
+        let rate = bytes_per_sec.get() as f64;
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, waiting until the download may go ahead.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+            bucket.updated = now;
+
+            bucket.tokens -= bytes as f64;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.rate)
+        };
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Verification of downloaded data against the MD5 stored with a blob.
+//!
+//! Azure only stores a checksum for the blob as a whole, so data can only be verified once a blob
+//! has been read from start to finish. Each blob being read accumulates an MD5 over reads that
+//! follow on from one another, and the digest is compared once the last byte has been read. Reads
+//! anywhere else abandon verification until the blob is read from the start again.
+
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use azure_storage::ConsistencyMD5;
+use lru::LruCache;
+use md5::{Digest, Md5};
+
+/// The number of blobs to track running checksums for.
+const MAX_STREAMS: NonZeroUsize = match NonZeroUsize::new(64) {
+    Some(n) => n,
+    None => unreachable!(),
+};
+
+struct Running {
+    /// The offset just past the data hashed so far.
+    next: u64,
+    len: u64,
+    expected: ConsistencyMD5,
+    hasher: Md5,
+}
+
+pub struct Verifier {
+    streams: Mutex<LruCache<String, Running>>,
+}
+
+impl Verifier {
+    pub fn new() -> Self {
+        Self {
+            streams: Mutex::new(LruCache::new(MAX_STREAMS)),
+        }
+    }
+
+    /// Determines if a read at `offset` would start a new checksum, in which case the blob's
+    /// checksum should be passed to the following call to [`Self::update`].
+    pub fn starts(&self, blob: &str, offset: u64) -> bool {
+        offset == 0 && !self.streams.lock().unwrap().contains(blob)
+    }
+
+    /// Adds data read at `offset` to the running checksum of a blob. `start` gives the blob's
+    /// length and stored MD5 if this read begins a new checksum.
+    ///
+    /// Fails if this read completes the blob, and the digest doesn't match.
+    pub fn update(
+        &self,
+        blob: &str,
+        offset: u64,
+        data: &[u8],
+        start: Option<(u64, ConsistencyMD5)>,
+    ) -> std::io::Result<()> {
+        let mut streams = self.streams.lock().unwrap();
+
+        if let Some((len, expected)) = start {
+            streams.put(
+                blob.to_string(),
+                Running {
+                    next: 0,
+                    len,
+                    expected,
+                    hasher: Md5::new(),
+                },
+            );
+        }
+
+        let Some(running) = streams.get_mut(blob) else {
+            return Ok(());
+        };
+
+        if offset != running.next {
+            // Re-reads of data that has already been hashed leave the checksum alone.
+            if offset + data.len() as u64 > running.next {
+                streams.pop(blob);
+            }
+
+            return Ok(());
+        }
+
+        running.hasher.update(data);
+        running.next += data.len() as u64;
+
+        if running.next < running.len {
+            return Ok(());
+        }
+
+        let running = streams.pop(blob).unwrap();
+        let actual = running.hasher.finalize();
+
+        if actual.as_slice() != running.expected.as_slice() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "checksum mismatch for {blob}: expected MD5 {}, got {}",
+                    hex(running.expected.as_slice()),
+                    hex(&actual)
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}